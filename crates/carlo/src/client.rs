@@ -1,16 +1,19 @@
 use anyhow::{anyhow, Context};
 pub use carol_core::BinaryId;
-pub use carol_http::api::{BinaryCreated, MachineCreated};
+pub use carol_http::api::{BinaryCreated, MachineCreated, Problem, PROBLEM_CONTENT_TYPE};
 
 pub struct Client {
     pub base: reqwest::Url,
+    /// Sent as a bearer token with requests that upload binaries or create machines.
+    token: Option<String>,
     http_client: reqwest::blocking::Client,
 }
 
 impl Client {
-    pub fn new(base: reqwest::Url) -> Self {
+    pub fn new(base: reqwest::Url, token: Option<String>) -> Self {
         Self {
             base,
+            token,
             http_client: reqwest::blocking::Client::new(), // blocking::get() does builder().build()?
         }
     }
@@ -53,23 +56,37 @@ impl Client {
 
     fn post(&self, path: &str) -> reqwest::blocking::RequestBuilder {
         let url = self.base.join(path).expect("path is valid");
-        self.http_client
+        let request = self
+            .http_client
             .post(url)
-            .header(reqwest::header::ACCEPT, "application/json")
+            .header(reqwest::header::ACCEPT, "application/json");
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
     }
 
     fn decode_response<B>(&self, response: reqwest::blocking::Response) -> anyhow::Result<B>
     where
         B: for<'de> serde::Deserialize<'de>,
     {
-        let response = response.error_for_status()?;
-
         // FIXME error if no content type specified?
         let content_type = response
             .headers()
             .get(reqwest::header::CONTENT_TYPE)
             .map(|h| h.to_str().unwrap_or(""))
-            .unwrap_or("");
+            .unwrap_or("")
+            .to_owned();
+
+        if !response.status().is_success() {
+            if content_type == PROBLEM_CONTENT_TYPE {
+                let body = response.bytes().context("Reading server error")?;
+                let problem: Problem =
+                    serde_json::from_slice(&body).context("Decoding server error")?;
+                return Err(problem.into());
+            }
+            return Err(response.error_for_status().unwrap_err().into());
+        }
 
         if !content_type.is_empty() && content_type != "application/json" {
             return Err(anyhow!("Unsupported response content-type {content_type}"));
@@ -79,3 +96,60 @@ impl Client {
         serde_json::from_slice(&body).context("Decoding response body")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use carol::config::{ApiAccess, HttpServerConfig};
+    use carol_http::api::ProblemType;
+    use std::str::FromStr;
+
+    #[test]
+    fn problems_from_the_server_are_decoded() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let addr = {
+            let _enter_guard = rt.enter();
+            let (addr, server) = carol::http::server::start(
+                HttpServerConfig {
+                    listen: std::net::SocketAddr::from_str("127.0.0.1:0").unwrap(),
+                    api_access: ApiAccess::Closed,
+                    api_tokens: vec!["token".into()],
+                    ..Default::default()
+                },
+                carol_host::State::new(
+                    carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+                    carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+                ),
+            )
+            .unwrap();
+            rt.spawn(server);
+            addr
+        };
+        let base = reqwest::Url::from_str(&format!("http://{addr}")).unwrap();
+        let binary_id = BinaryId::new(b"not uploaded");
+
+        let client = Client::new(base.clone(), Some("token".into()));
+        let error = client.create_machine(&binary_id).err().unwrap();
+        let problem = error
+            .downcast_ref::<Problem>()
+            .expect("server sent a problem");
+        assert_eq!(problem.problem_type(), Some(ProblemType::BinaryNotFound));
+        assert_eq!(problem.status, 404);
+        assert_eq!(problem.instance, Some(format!("/binaries/{binary_id}")));
+        assert_eq!(
+            problem.detail,
+            Some(format!("binary {binary_id} not found"))
+        );
+
+        let client = Client::new(base, Some("wrong".into()));
+        let error = client
+            .upload_binary(&binary_id, &b"not uploaded"[..])
+            .err()
+            .unwrap();
+        let problem = error
+            .downcast_ref::<Problem>()
+            .expect("server sent a problem");
+        assert_eq!(problem.problem_type(), Some(ProblemType::Unauthorized));
+        assert_eq!(problem.status, 401);
+    }
+}
@@ -150,11 +150,14 @@ struct CreateOpts {
 struct ServerOpts {
     #[arg(long)] // , default_value = "http://localhost:8000")] ?
     carol_url: reqwest::Url,
+    /// API token to upload binaries and create machines on servers that require one
+    #[arg(long, value_name = "TOKEN")]
+    token: Option<String>,
 }
 
 impl ServerOpts {
     pub fn new_client(&self) -> Client {
-        Client::new(self.carol_url.clone())
+        Client::new(self.carol_url.clone(), self.token.clone())
     }
 
     pub fn cname_for_machine(&self, id: MachineId) -> Option<String> {
@@ -382,7 +385,10 @@ pub struct RunOpts {
 
 impl RunOpts {
     fn run(self, exec: &Executor) -> anyhow::Result<()> {
-        let state = carol_host::State::new(carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap());
+        let state = carol_host::State::new(
+            carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+            carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        );
         let rt = tokio::runtime::Runtime::new()?;
         let _enter_guard = rt.enter();
         let http_server_config = carol::config::HttpServerConfig {
@@ -396,6 +402,7 @@ impl RunOpts {
         let server_opts = ServerOpts {
             carol_url: reqwest::Url::from_str(&format!("http://{bound_addr}"))
                 .expect("this should be valid"),
+            token: None,
         };
         let client = server_opts.new_client();
 
@@ -1,8 +1,13 @@
 use anyhow::{anyhow, Context};
-use carol::config::Config;
-use carol_host::State;
+use carol::config::{Config, StorageConfig};
+use carol_host::{ExecutorState, State};
 use clap::{Parser, Subcommand};
-use std::{fs::File, path::PathBuf};
+use std::io::{Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+};
 use tracing::{event, Level};
 
 #[derive(Parser, Debug)]
@@ -20,6 +25,35 @@ pub enum Commands {
     ConfigGen,
     /// Run carol
     Run,
+    /// Remove binaries that no machine runs. Run this while carol is stopped.
+    Gc {
+        /// Only remove binaries uploaded at least this many seconds ago. Defaults to
+        /// `gc.min_binary_age_secs` from the config file.
+        #[clap(long)]
+        min_age_secs: Option<u64>,
+        /// List what would be removed without removing anything.
+        #[clap(long)]
+        dry_run: bool,
+    },
+}
+
+fn read_config(file_path: &Path, file_name: &str) -> anyhow::Result<Config> {
+    let mut file = File::open(file_path).context(format!(
+        "unable to open configuration file {file_name} for reading"
+    ))?;
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    serde_yaml::from_str(&content).context(format!("{file_name} is an invalid configuration file"))
+}
+
+/// Generates a secp256k1 key for a config file from before BIP340 signing and appends it to the
+/// file so the node keeps signing with the same key from then on.
+fn append_schnorr_secret_key(file_path: &Path) -> anyhow::Result<carol_schnorr::KeyPair> {
+    let schnorr_keypair = carol_schnorr::KeyPair::random(&mut rand::thread_rng());
+    let mut file = OpenOptions::new().append(true).open(file_path)?;
+    writeln!(file, "\nschnorr_secret_key: {schnorr_keypair}")?;
+    file.sync_all()?;
+    Ok(schnorr_keypair)
 }
 
 #[tokio::main]
@@ -34,17 +68,7 @@ pub async fn main() -> anyhow::Result<()> {
 
     match args.command {
         Commands::Run => {
-            let mut file = File::open(file_path).context(format!(
-                "unable to open configuration file {file_name} for reading"
-            ))?;
-
-            let config: Config = {
-                use std::io::Read;
-                let mut content = String::new();
-                file.read_to_string(&mut content)?;
-                serde_yaml::from_str(&content)
-                    .context(format!("{file_name} is an invalid configuration file"))?
-            };
+            let config = read_config(&file_path, &file_name)?;
 
             let subscriber = tracing_subscriber::fmt()
                 .with_max_level(config.log.level)
@@ -53,8 +77,39 @@ pub async fn main() -> anyhow::Result<()> {
             // use that subscriber to process traces emitted after this point
             tracing::subscriber::set_global_default(subscriber)?;
             event!(Level::INFO, "starting carol");
+            if matches!(config.storage, StorageConfig::Memory) {
+                event!(
+                    Level::WARN,
+                    "{file_name} has no disk storage configured so every binary and machine will be forgotten when carol stops. Set `storage: {{ type: disk, dir: <dir> }}` to keep them."
+                );
+            }
+
+            let schnorr_keypair = match config.schnorr_secret_key {
+                Some(schnorr_keypair) => schnorr_keypair,
+                None => {
+                    let schnorr_keypair = append_schnorr_secret_key(&file_path)
+                        .context(format!("adding schnorr_secret_key to {file_name}"))?;
+                    event!(
+                        Level::WARN,
+                        "{file_name} had no schnorr_secret_key so a new one was generated and added to it"
+                    );
+                    schnorr_keypair
+                }
+            };
+
+            let storage = config.storage.into_storage().context("opening storage")?;
+            let state = State {
+                bls_keypair: config.bls_secret_key,
+                schnorr_keypair,
+                exec: ExecutorState::with_storage(
+                    config.executor.into_executor(config.egress)?,
+                    storage,
+                )
+                .context("loading binaries and machines from storage")?
+                .limit_machines(config.http_server.rate_limits.machine_limits()),
+            };
 
-            let state = State::new(config.bls_secret_key);
+            tokio::spawn(carol::scheduler::Scheduler::new(state.clone(), config.scheduler).run());
 
             let (local_addr, server) = carol::http::server::start(config.http_server, state)?;
 
@@ -62,6 +117,40 @@ pub async fn main() -> anyhow::Result<()> {
 
             server.await;
         }
+        Commands::Gc {
+            min_age_secs,
+            dry_run,
+        } => {
+            let config = read_config(&file_path, &file_name)?;
+            if matches!(config.storage, StorageConfig::Memory) {
+                return Err(anyhow!(
+                    "{file_name} uses memory storage so there is nothing to collect"
+                ));
+            }
+            let min_age_secs = min_age_secs.unwrap_or(config.gc.min_binary_age_secs);
+            let storage = config.storage.into_storage().context("opening storage")?;
+            let executor = config.executor.into_executor(config.egress)?;
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("system clock is after the unix epoch")
+                .as_secs();
+            let binaries = carol::gc::unreferenced_binaries(storage.as_ref(), min_age_secs, now)
+                .context("finding binaries without machines")?;
+            for binary_id in &binaries {
+                if !dry_run {
+                    storage
+                        .delete_binary(*binary_id)
+                        .with_context(|| format!("deleting binary {binary_id}"))?;
+                    executor.remove_compiled_binary(*binary_id);
+                }
+                println!("{binary_id}");
+            }
+            eprintln!(
+                "{} {} binaries without machines",
+                if dry_run { "would remove" } else { "removed" },
+                binaries.len()
+            );
+        }
         Commands::ConfigGen => {
             if file_path.exists() {
                 return Err(anyhow!(
@@ -1,18 +1,199 @@
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Config {
     pub http_server: HttpServerConfig,
     pub bls_secret_key: carol_bls::KeyPair,
+    /// Config files written before BIP340 signing was added don't have one so `carol run`
+    /// generates it and appends it to the config file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schnorr_secret_key: Option<carol_schnorr::KeyPair>,
     pub log: LogConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub executor: ExecutorConfig,
+    #[serde(default)]
+    pub egress: EgressConfig,
+    #[serde(default)]
+    pub gc: GcConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
 }
 
 impl Config {
     pub fn generate(rng: &mut impl rand::RngCore) -> Self {
+        let mut random_token = || {
+            let mut token = [0u8; 32];
+            rng.fill_bytes(&mut token);
+            carol_core::hex::encode(&token)
+        };
         Config {
-            http_server: HttpServerConfig::default(),
+            http_server: HttpServerConfig {
+                admin_token: Some(random_token()),
+                api_access: ApiAccess::Closed,
+                api_tokens: vec![random_token()],
+                ..Default::default()
+            },
             bls_secret_key: carol_bls::KeyPair::random(rng),
+            schnorr_secret_key: Some(carol_schnorr::KeyPair::random(rng)),
             log: Default::default(),
+            storage: StorageConfig::Disk {
+                dir: PathBuf::from("carol_data"),
+            },
+            executor: ExecutorConfig {
+                cache_dir: Some(PathBuf::from("carol_cache")),
+                ..Default::default()
+            },
+            egress: EgressConfig::default(),
+            gc: GcConfig::default(),
+            scheduler: SchedulerConfig::default(),
+        }
+    }
+}
+
+/// Where carol keeps uploaded binaries and created machines.
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StorageConfig {
+    /// Keep everything in memory. Everything is forgotten when carol stops so carol warns about
+    /// this on startup. It's the default so config files written before storage was configurable
+    /// still load but `config-gen` writes `disk` storage. Machines can't announce nonces or
+    /// attest to outcomes with it since they could be made to attest twice after a restart.
+    #[default]
+    Memory,
+    /// Keep everything in files under `dir`.
+    Disk { dir: PathBuf },
+}
+
+impl StorageConfig {
+    pub fn into_storage(self) -> anyhow::Result<Arc<dyn carol_host::Storage>> {
+        Ok(match self {
+            StorageConfig::Memory => Arc::new(carol_host::MemoryStorage::default()),
+            StorageConfig::Disk { dir } => Arc::new(carol_host::DiskStorage::open(dir)?),
+        })
+    }
+}
+
+/// Limits on what a guest can do each time it is activated or handles a HTTP request. Any field
+/// that is left out gets its default. Setting a limit to `null` turns it off.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ExecutorConfig {
+    /// How much fuel (roughly one unit per WASM instruction) a guest may consume. Defaults to one
+    /// billion.
+    pub fuel_per_activation: Option<u64>,
+    /// How many milliseconds a guest may run for. Defaults to 10 seconds.
+    pub activation_timeout_ms: Option<u64>,
+    /// How many bytes each of the guest's linear memories may grow to. Defaults to 128MiB.
+    pub max_memory_bytes: Option<usize>,
+    /// How many elements each of the guest's tables may grow to. Defaults to 100,000.
+    pub max_table_elements: Option<u32>,
+    /// How many core WASM instances a guest component may create. Defaults to 32. wasmtime's own
+    /// default applies if set to `null`.
+    pub max_instances: Option<usize>,
+    /// Where to keep compiled binaries so they don't have to be recompiled when carol restarts.
+    /// Nothing is cached if unset.
+    pub cache_dir: Option<PathBuf>,
+    /// How deeply activations may nest when machines activate other machines (or themselves).
+    /// Defaults to 8.
+    pub max_activation_depth: Option<u32>,
+    /// Request headers that aren't passed on to machines' HTTP handlers. Hop-by-hop headers like
+    /// `Connection` are always stripped.
+    pub stripped_request_headers: Vec<String>,
+    /// How many bytes the body of a request to a machine's HTTP handler may be. Defaults to 16MiB.
+    pub max_request_body_bytes: Option<u64>,
+    /// Reserve memory for guest instances up front to make activations faster. Needs
+    /// `max_memory_bytes` to know how much to reserve.
+    pub pooling_allocator: bool,
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        Self {
+            fuel_per_activation: Some(1_000_000_000),
+            activation_timeout_ms: Some(10_000),
+            max_memory_bytes: Some(128 * 1024 * 1024),
+            max_table_elements: Some(100_000),
+            max_instances: Some(32),
+            cache_dir: None,
+            max_activation_depth: Some(8),
+            stripped_request_headers: vec![],
+            max_request_body_bytes: Some(16 * 1024 * 1024),
+            pooling_allocator: false,
+        }
+    }
+}
+
+impl ExecutorConfig {
+    pub fn into_executor(self, egress: EgressConfig) -> anyhow::Result<carol_host::Executor> {
+        if self.pooling_allocator && self.max_memory_bytes.is_none() {
+            return Err(anyhow::anyhow!(
+                "executor.pooling_allocator needs executor.max_memory_bytes to be set"
+            ));
+        }
+        Ok(carol_host::Executor::with_config(
+            carol_host::ExecutorConfig {
+                fuel_per_activation: self.fuel_per_activation,
+                activation_timeout: self.activation_timeout_ms.map(Duration::from_millis),
+                max_memory_bytes: self.max_memory_bytes,
+                max_table_elements: self.max_table_elements,
+                max_instances: self.max_instances,
+                cache_dir: self.cache_dir,
+                max_activation_depth: self.max_activation_depth,
+                stripped_request_headers: self.stripped_request_headers,
+                egress: egress.into_policy(),
+                max_request_body_bytes: self.max_request_body_bytes,
+                pooling_allocator: self.pooling_allocator,
+            },
+        ))
+    }
+}
+
+/// Which outbound HTTP requests machines may make. Any field that is left out gets its default.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct EgressConfig {
+    /// If not empty machines may only make requests to these hosts (or their subdomains).
+    pub allow_hosts: Vec<String>,
+    /// Hosts (and their subdomains) machines may never make requests to.
+    pub deny_hosts: Vec<String>,
+    /// Stop machines making requests to loopback, private and link-local addresses (e.g. the
+    /// cloud metadata service at 169.254.169.254).
+    pub block_private_addresses: bool,
+    /// How many bytes a response body may be. No limit if unset.
+    pub max_response_bytes: Option<u64>,
+    /// How many milliseconds a request may take. No limit if unset.
+    pub request_timeout_ms: Option<u64>,
+    /// How many requests a machine may make each time it is activated. No limit if unset.
+    pub max_requests_per_activation: Option<u32>,
+}
+
+impl Default for EgressConfig {
+    fn default() -> Self {
+        Self {
+            allow_hosts: vec![],
+            deny_hosts: vec![],
+            block_private_addresses: true,
+            max_response_bytes: Some(16 * 1024 * 1024),
+            request_timeout_ms: Some(5_000),
+            max_requests_per_activation: Some(32),
+        }
+    }
+}
+
+impl EgressConfig {
+    pub fn into_policy(self) -> carol_host::EgressPolicy {
+        carol_host::EgressPolicy {
+            allow_hosts: self.allow_hosts,
+            deny_hosts: self.deny_hosts,
+            block_private_addresses: self.block_private_addresses,
+            max_response_bytes: self.max_response_bytes,
+            request_timeout: self.request_timeout_ms.map(Duration::from_millis),
+            max_requests_per_activation: self.max_requests_per_activation,
         }
     }
 }
@@ -21,6 +202,43 @@ impl Config {
 pub struct HttpServerConfig {
     pub listen: std::net::SocketAddr,
     pub dns: dns::Config,
+    /// Bearer token that must be sent in the `Authorization` header to delete binaries and
+    /// machines. Nothing can be deleted over HTTP if unset.
+    #[serde(default)]
+    pub admin_token: Option<String>,
+    /// Whether anyone may upload binaries and create machines or only clients with a token.
+    #[serde(default)]
+    pub api_access: ApiAccess,
+    /// Bearer tokens that may be sent in the `Authorization` header to upload binaries and create
+    /// machines when `api_access` is `closed`. The admin token works too.
+    #[serde(default)]
+    pub api_tokens: Vec<String>,
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+    /// How many `/machines/{id}/events/stream` connections may be open at once across all
+    /// clients. No limit if unset.
+    #[serde(default = "default_max_event_streams")]
+    pub max_event_streams: Option<usize>,
+}
+
+fn default_max_event_streams() -> Option<usize> {
+    Some(1_000)
+}
+
+impl HttpServerConfig {
+    /// Checks for settings that would deserialize fine but make no sense.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        // an empty bearer token is easy to send by accident e.g. from an unset environment variable
+        if self.admin_token.as_deref() == Some("") {
+            return Err(anyhow::anyhow!(
+                "http_server.admin_token is empty. Leave it out to disable deleting."
+            ));
+        }
+        if self.api_tokens.iter().any(String::is_empty) {
+            return Err(anyhow::anyhow!("http_server.api_tokens has an empty token"));
+        }
+        self.rate_limits.validate()
+    }
 }
 
 impl Default for HttpServerConfig {
@@ -28,6 +246,141 @@ impl Default for HttpServerConfig {
         Self {
             listen: std::net::SocketAddr::from_str("127.0.0.1:8000").unwrap(),
             dns: Default::default(),
+            admin_token: None,
+            api_access: ApiAccess::default(),
+            api_tokens: vec![],
+            rate_limits: RateLimitConfig::default(),
+            max_event_streams: default_max_event_streams(),
+        }
+    }
+}
+
+/// How much work clients can make the node do. Requests over a limit get a `429 Too Many Requests`
+/// response. Any field that is left out gets its default.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// How often each client may make requests. Clients are told apart by the address of their
+    /// connection (IPv6 clients by their /64) so if the node is behind a reverse proxy every
+    /// client shares the proxy's limit. That's why there's no limit unless it's set.
+    pub per_client: Option<RateLimit>,
+    /// How often each machine may be run. This counts every activation and HTTP request
+    /// including nested and scheduled activations. No limit if unset.
+    pub per_machine: Option<RateLimit>,
+    /// How many activations and HTTP requests each machine may be handling at the same time. No
+    /// limit if unset.
+    pub max_concurrent_activations_per_machine: Option<u32>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_client: None,
+            per_machine: Some(RateLimit {
+                per_second: 50.0,
+                burst: 200,
+            }),
+            max_concurrent_activations_per_machine: Some(8),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// The limits every way of running a machine is subject to. They are applied to the node's
+    /// [`ExecutorState`](carol_host::ExecutorState) rather than the HTTP server so they cover
+    /// activations that don't come from HTTP requests.
+    pub fn machine_limits(&self) -> carol_host::rate_limit::MachineLimits {
+        carol_host::rate_limit::MachineLimits {
+            rate: self.per_machine.map(RateLimit::into_host),
+            max_concurrent: self.max_concurrent_activations_per_machine,
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for (name, limit) in [
+            ("per_client", self.per_client),
+            ("per_machine", self.per_machine),
+        ] {
+            if let Some(limit) = limit {
+                // this also rules out NaN
+                if !(limit.per_second > 0.0 && limit.per_second.is_finite()) {
+                    return Err(anyhow::anyhow!(
+                        "http_server.rate_limits.{name}.per_second must be a positive number"
+                    ));
+                }
+                if limit.burst == 0 {
+                    return Err(anyhow::anyhow!(
+                        "http_server.rate_limits.{name}.burst must be at least 1"
+                    ));
+                }
+            }
+        }
+        if self.max_concurrent_activations_per_machine == Some(0) {
+            return Err(anyhow::anyhow!(
+                "http_server.rate_limits.max_concurrent_activations_per_machine must be at least 1"
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A token bucket that holds up to `burst` requests and refills at `per_second`.
+#[derive(Debug, Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    pub fn into_host(self) -> carol_host::rate_limit::RateLimit {
+        carol_host::rate_limit::RateLimit {
+            per_second: self.per_second,
+            burst: self.burst,
+        }
+    }
+}
+
+/// Who may upload binaries and create machines over HTTP.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiAccess {
+    /// Anyone who can reach the node.
+    #[default]
+    Open,
+    /// Only requests with one of the `api_tokens` (or the `admin_token`).
+    Closed,
+}
+
+/// What `carol gc` removes.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct GcConfig {
+    /// How many seconds ago a binary must have been uploaded before it is removed for not having
+    /// any machines. This leaves time to create machines for freshly uploaded binaries.
+    pub min_binary_age_secs: u64,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        Self {
+            min_binary_age_secs: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// How the outputs of scheduled activations are kept.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    /// How many of the most recent outputs of each scheduled activation of each machine are kept.
+    /// Older ones are removed after each run.
+    pub max_outputs_per_activation: usize,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            max_outputs_per_activation: 1_000,
         }
     }
 }
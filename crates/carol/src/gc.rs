@@ -0,0 +1,42 @@
+//! Removes binaries from storage that no machine runs.
+//!
+//! This works on storage directly rather than through a running node so it has to be run while
+//! carol is stopped. Disk storage is locked while it's open so `carol gc` refuses to run alongside
+//! a node using the same directory.
+use anyhow::Context;
+use carol_core::BinaryId;
+use carol_host::Storage;
+use std::collections::HashSet;
+
+/// The binaries that no machine runs and that were uploaded at least `min_age_secs` before `now`.
+pub fn unreferenced_binaries(
+    storage: &dyn Storage,
+    min_age_secs: u64,
+    now: u64,
+) -> anyhow::Result<Vec<BinaryId>> {
+    let mut referenced = HashSet::new();
+    for machine_id in storage.list_machines()? {
+        if let Some((binary_id, _)) = storage
+            .get_machine(machine_id)
+            .with_context(|| format!("reading machine {machine_id}"))?
+        {
+            referenced.insert(binary_id);
+        }
+    }
+
+    let mut unreferenced = vec![];
+    for binary_id in storage.list_binaries()? {
+        if referenced.contains(&binary_id) {
+            continue;
+        }
+        let created = storage
+            .binary_created(binary_id)
+            .with_context(|| format!("reading when binary {binary_id} was uploaded"))?
+            .unwrap_or_default();
+        if now.saturating_sub(created) >= min_age_secs {
+            unreferenced.push(binary_id);
+        }
+    }
+    unreferenced.sort_unstable();
+    Ok(unreferenced)
+}
@@ -1,3 +1,4 @@
 pub use carol_http::api;
+pub mod problem;
 pub mod resolver;
 pub mod server;
@@ -0,0 +1,301 @@
+//! Errors the HTTP server responds with.
+//!
+//! Every error is sent to the client as an [RFC 7807] `application/problem+json` body (see
+//! [`api::Problem`]) whose `type` tells the client what kind of problem it was.
+//!
+//! [RFC 7807]: https://www.rfc-editor.org/rfc/rfc7807
+use super::api::{self, ProblemType, PROBLEM_CONTENT_TYPE};
+use anyhow::anyhow;
+use carol_core::{hex, BinaryId, MachineId};
+use carol_host::GuestError;
+use hyper::http::HeaderValue;
+use hyper::{header, Body, Response, StatusCode};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct Problem {
+    problem_type: ProblemType,
+    /// What the client is told went wrong.
+    detail: String,
+    /// What gets logged. It may have more detail than we want to tell the client.
+    host_error: anyhow::Error,
+    status: StatusCode,
+    extra_headers: Vec<(String, String)>,
+    extensions: BTreeMap<String, String>,
+}
+
+impl Problem {
+    pub fn new(
+        problem_type: ProblemType,
+        detail: String,
+        host_error: anyhow::Error,
+        status: StatusCode,
+    ) -> Self {
+        Self {
+            problem_type,
+            detail,
+            host_error,
+            status,
+            extra_headers: vec![],
+            extensions: Default::default(),
+        }
+    }
+
+    pub fn guest_error(guest_error: GuestError) -> Self {
+        match guest_error {
+            GuestError::Panic {
+                ref backtrace,
+                ref message,
+            } => {
+                let mut extensions = BTreeMap::default();
+                if let Some(bt) = backtrace {
+                    extensions.insert("backtrace".into(), format!("{}", bt));
+                }
+                Self {
+                    problem_type: ProblemType::GuestPanic,
+                    detail: message.clone(),
+                    status: StatusCode::BAD_REQUEST,
+                    extra_headers: vec![],
+                    host_error: guest_error.into(),
+                    extensions,
+                }
+            }
+            GuestError::OutOfFuel { .. } => Self::new(
+                ProblemType::GuestOutOfFuel,
+                guest_error.to_string(),
+                guest_error.into(),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            GuestError::ResourceLimitExceeded { .. } => Self::new(
+                ProblemType::GuestResourceLimitExceeded,
+                guest_error.to_string(),
+                guest_error.into(),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            GuestError::Timeout { .. } => Self::new(
+                ProblemType::GuestTimeout,
+                guest_error.to_string(),
+                guest_error.into(),
+                StatusCode::GATEWAY_TIMEOUT,
+            ),
+            GuestError::Other(error) => Self::internal_server_error(error),
+        }
+    }
+
+    pub fn bad_request(detail: impl Into<String>, host_error: anyhow::Error) -> Self {
+        Self::new(
+            ProblemType::BadRequest,
+            detail.into(),
+            host_error,
+            StatusCode::BAD_REQUEST,
+        )
+    }
+
+    pub fn internal_server_error(host_error: anyhow::Error) -> Self {
+        Self::new(
+            ProblemType::InternalServerError,
+            "internal server error".into(),
+            host_error,
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+    }
+
+    pub fn misdirected_request(host: &HeaderValue) -> Self {
+        let host = host
+            .to_str()
+            .map(|x| x.to_string())
+            .unwrap_or_else(|_| format!("hex:\"{}\"", hex::encode(host.as_bytes())));
+        Self::new(
+            ProblemType::MisdirectedRequest,
+            format!("HOST {host} couldn't be resovled to a machine"),
+            anyhow!("HOST {host} couldn't be resovled to a machine"),
+            StatusCode::MISDIRECTED_REQUEST,
+        )
+    }
+
+    pub fn not_found(path: &str) -> Self {
+        Self::new(
+            ProblemType::NotFound,
+            format!("{} not found", path),
+            anyhow!("resource not found: {}", path),
+            StatusCode::NOT_FOUND,
+        )
+    }
+
+    pub fn machine_not_found(machine_id: MachineId) -> Self {
+        Self::new(
+            ProblemType::MachineNotFound,
+            format!("machine {machine_id} not found"),
+            anyhow!("machine {machine_id} not found"),
+            StatusCode::NOT_FOUND,
+        )
+    }
+
+    pub fn binary_not_found(binary_id: BinaryId) -> Self {
+        Self::new(
+            ProblemType::BinaryNotFound,
+            format!("binary {binary_id} not found"),
+            anyhow!("binary {binary_id} not found"),
+            StatusCode::NOT_FOUND,
+        )
+    }
+
+    pub fn binary_in_use(binary_id: BinaryId, n_machines: usize) -> Self {
+        Self::new(
+            ProblemType::BinaryInUse,
+            format!("binary {binary_id} is still used by {n_machines} machine(s)"),
+            anyhow!("binary {binary_id} is still used by {n_machines} machine(s)"),
+            StatusCode::CONFLICT,
+        )
+    }
+
+    pub fn invalid_binary(binary_id: BinaryId, error: anyhow::Error) -> Self {
+        Self::new(
+            ProblemType::InvalidBinary,
+            format!("Invalid WASM binary with id {}: {}", binary_id, error),
+            error,
+            StatusCode::BAD_REQUEST,
+        )
+    }
+
+    /// The host couldn't run the machine at all (as opposed to the machine itself failing).
+    pub fn activation_failed(error: anyhow::Error) -> Self {
+        Self::new(
+            ProblemType::ActivationFailed,
+            format!("error occurred while trying to activate machine: {}", error),
+            error,
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+    }
+
+    pub fn payload_too_large(max: u64) -> Self {
+        Self::new(
+            ProblemType::PayloadTooLarge,
+            format!("request body is larger than the limit of {max} bytes"),
+            anyhow!("request body is larger than the limit of {max} bytes"),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        )
+    }
+
+    pub fn method_not_allowed(path: &str, method: &str, allowed: &[&str]) -> Self {
+        let mut problem = Self::new(
+            ProblemType::MethodNotAllowed,
+            format!("HTTP method {} not supported on {}", method, path),
+            anyhow!(
+                "HTTP method {} called on {} but it's not supported",
+                method,
+                path
+            ),
+            StatusCode::METHOD_NOT_ALLOWED,
+        );
+
+        problem
+            .extra_headers
+            .push(("Allow".into(), allowed.join(", ")));
+        problem
+    }
+
+    pub fn unauthorized(reason: &str) -> Self {
+        let mut problem = Self::new(
+            ProblemType::Unauthorized,
+            reason.into(),
+            anyhow!("unauthorized request: {reason}"),
+            StatusCode::UNAUTHORIZED,
+        );
+        problem
+            .extra_headers
+            .push(("WWW-Authenticate".into(), "Bearer".into()));
+        problem
+    }
+
+    pub fn forbidden(reason: &str) -> Self {
+        Self::new(
+            ProblemType::Forbidden,
+            reason.into(),
+            anyhow!("forbidden request: {reason}"),
+            StatusCode::FORBIDDEN,
+        )
+    }
+
+    pub fn too_many_requests(reason: &str, retry_after: Duration) -> Self {
+        let mut problem = Self::new(
+            ProblemType::TooManyRequests,
+            reason.into(),
+            anyhow!("too many requests: {reason}"),
+            StatusCode::TOO_MANY_REQUESTS,
+        );
+        // Retry-After is in whole seconds so round up
+        let retry_after_secs = retry_after
+            .as_secs()
+            .saturating_add(u64::from(retry_after.subsec_nanos() > 0));
+        problem
+            .extra_headers
+            .push(("Retry-After".into(), retry_after_secs.max(1).to_string()));
+        problem
+    }
+
+    pub fn invalid_path_element<T: std::any::Any>(error: anyhow::Error, val: &str) -> Self {
+        Self::new(
+            ProblemType::InvalidPathElement,
+            format!(
+                "path element {} is not a valid {}",
+                val,
+                std::any::type_name::<T>()
+            ),
+            error,
+            StatusCode::BAD_REQUEST,
+        )
+    }
+
+    pub fn host_error(&self) -> &anyhow::Error {
+        &self.host_error
+    }
+
+    /// The response for the problem that happened while handling a request for `instance`.
+    pub fn into_response(self, instance: &str) -> Response<Body> {
+        let body = serde_json::to_vec_pretty(&api::Problem {
+            type_uri: self.problem_type.uri().into(),
+            title: self.problem_type.title().into(),
+            status: self.status.as_u16(),
+            detail: Some(self.detail),
+            instance: Some(instance.into()),
+            extensions: self.extensions,
+        })
+        .unwrap();
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = self.status;
+        let headers = response.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(PROBLEM_CONTENT_TYPE),
+        );
+        for (name, value) in self.extra_headers {
+            if let (Ok(name), Ok(value)) = (
+                header::HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(&value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn retry_after_is_rounded_up_to_whole_seconds() {
+        for (retry_after, header) in [
+            (Duration::ZERO, "1"),
+            (Duration::from_millis(300), "1"),
+            (Duration::from_secs(2), "2"),
+            (Duration::from_millis(2_001), "3"),
+        ] {
+            let response = Problem::too_many_requests("slow down", retry_after).into_response("/");
+            assert_eq!(response.headers()["retry-after"], header, "{retry_after:?}");
+        }
+    }
+}
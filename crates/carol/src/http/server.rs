@@ -1,152 +1,43 @@
 use super::api::{self, *};
+use super::problem::Problem;
 use super::resolver::{Resolution, Resolver};
 use crate::config;
 use anyhow::{anyhow, Context};
-use carol_core::{hex, BinaryId, MachineId};
-use carol_host::{CompiledBinary, GuestError, State};
+use carol_core::{BinaryId, MachineId};
+use carol_host::rate_limit::{ActivationPermit, Busy, RateLimiter};
+use carol_host::{CompiledBinary, ExecutorState, GuestError, RemoveBinaryError, State};
 use hyper::http::uri::PathAndQuery;
 use hyper::http::HeaderValue;
+use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{body::HttpBody, Body, Method, Request, Response, Server, StatusCode};
 use hyper::{header, Uri};
-use std::collections::HashMap;
 use std::convert::Infallible;
 use std::future::Future;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use tracing::{event, span, Instrument, Level};
 
-#[derive(Debug)]
-pub struct Problem {
-    host_error: anyhow::Error,
-    client_desc: String,
-    status: StatusCode,
-    extra_headers: Vec<(String, String)>,
-    extra_fields: HashMap<String, String>,
+/// Compares the bytes of two tokens without returning early so the time it takes doesn't reveal
+/// how much of the token was right.
+fn tokens_match(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
 }
 
-impl Problem {
-    pub fn new(client_desc: String, host_error: anyhow::Error, status: StatusCode) -> Self {
-        Self {
-            host_error,
-            client_desc,
-            status,
-            extra_headers: vec![],
-            extra_fields: Default::default(),
-        }
-    }
-
-    pub fn guest_error(guest_error: GuestError) -> Self {
-        match guest_error {
-            GuestError::Panic {
-                ref backtrace,
-                ref message,
-            } => {
-                let mut extra_fields = HashMap::default();
-                if let Some(bt) = backtrace {
-                    extra_fields.insert("backtrace".into(), format!("{}", bt));
-                }
-                Self {
-                    client_desc: message.clone(),
-                    status: StatusCode::BAD_REQUEST,
-                    extra_headers: vec![],
-                    host_error: guest_error.into(),
-                    extra_fields,
-                }
-            }
-            GuestError::Other(error) => Self::internal_server_error(error),
-        }
-    }
-
-    pub fn bad_request(client_desc: impl Into<String>, host_error: anyhow::Error) -> Self {
-        Self::new(client_desc.into(), host_error, StatusCode::BAD_REQUEST)
-    }
-
-    pub fn internal_server_error(host_error: anyhow::Error) -> Self {
-        Self::new(
-            "internal server error".into(),
-            host_error,
-            StatusCode::INTERNAL_SERVER_ERROR,
-        )
-    }
-
-    pub fn misdirected_request(host: &HeaderValue) -> Self {
-        let host = host
-            .to_str()
-            .map(|x| x.to_string())
-            .unwrap_or_else(|_| format!("hex:\"{}\"", hex::encode(host.as_bytes())));
-        Self::new(
-            format!("HOST {host} couldn't be resovled to a machine"),
-            anyhow!("HOST {host} couldn't be resovled to a machine"),
-            StatusCode::MISDIRECTED_REQUEST,
-        )
-    }
-
-    pub fn not_found(path: &str) -> Self {
-        Self::new(
-            format!("{} not found", path),
-            anyhow!("resource not found: {}", path),
-            StatusCode::NOT_FOUND,
-        )
-    }
-
-    pub fn machine_not_found(machine_id: MachineId) -> Self {
-        Self::new(
-            format!("machine {machine_id} not found"),
-            anyhow!("machine {machine_id} not found"),
-            StatusCode::NOT_FOUND,
-        )
-    }
-
-    pub fn binary_not_found(binary_id: BinaryId) -> Self {
-        Self::new(
-            format!("binary {binary_id} not found"),
-            anyhow!("binary {binary_id} not found"),
-            StatusCode::NOT_FOUND,
-        )
-    }
-
-    pub fn method_not_allowed(path: &str, method: &str, allowed: &[&str]) -> Self {
-        let mut problem = Self::new(
-            format!("HTTP method {} not supported on {}", method, path),
-            anyhow!(
-                "HTTP method {} called on {} but it's not supported",
-                method,
-                path
-            ),
-            StatusCode::METHOD_NOT_ALLOWED,
-        );
-
-        problem
-            .extra_headers
-            .push(("Allow".into(), allowed.join(", ")));
-        problem
-    }
-    pub fn invalid_path_element<T: std::any::Any>(error: anyhow::Error, val: &str) -> Self {
-        Self::new(
-            format!(
-                "path element {} is not a valid {}",
-                val,
-                std::any::type_name::<T>()
-            ),
-            error,
-            StatusCode::BAD_REQUEST,
-        )
-    }
-
-    pub fn into_json_body(self) -> Vec<u8> {
-        #[derive(serde::Serialize)]
-        struct ProblemBody {
-            error: String,
-            #[serde(flatten)]
-            extra_fields: HashMap<String, String>,
-        }
-        serde_json::to_vec_pretty(&ProblemBody {
-            error: self.client_desc,
-            extra_fields: self.extra_fields,
-        })
-        .unwrap()
+fn set_fuel_consumed_header(response: &mut Response<Body>, fuel_consumed: Option<u64>) {
+    if let Some(fuel_consumed) = fuel_consumed {
+        response
+            .headers_mut()
+            .insert(FUEL_CONSUMED_HEADER, HeaderValue::from(fuel_consumed));
     }
 }
 
@@ -163,6 +54,156 @@ fn build_response<B: api::Response>(app_response: &B) -> Response<Body> {
     response
 }
 
+/// The value of the first `name=value` pair in the request's query string.
+fn query_param<'a>(req: &'a Request<Body>, name: &str) -> Option<&'a str> {
+    req.uri().query()?.split('&').find_map(|param| {
+        param
+            .strip_prefix(name)
+            .and_then(|rest| rest.strip_prefix('='))
+    })
+}
+
+/// How many events are returned from `/machines/{id}/events` if the client doesn't say.
+const DEFAULT_EVENTS_LIMIT: usize = 100;
+/// The most events that can be returned from `/machines/{id}/events` in one go.
+const MAX_EVENTS_LIMIT: usize = 1000;
+
+/// How many binaries or machines are listed in one page if the client doesn't say.
+const DEFAULT_LIST_LIMIT: usize = 100;
+/// The most binaries or machines that can be listed in one page.
+const MAX_LIST_LIMIT: usize = 1000;
+
+/// Reads the `cursor` and `limit` query parameters of a request for a page of a list.
+fn list_page<T>(req: &Request<Body>) -> Result<(Option<T>, usize), Problem>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let cursor = query_param(req, "cursor")
+        .map(T::from_str)
+        .transpose()
+        .map_err(|e| Problem::bad_request("cursor must be an id from a previous page", e.into()))?;
+    let limit = query_param(req, "limit")
+        .map(usize::from_str)
+        .transpose()
+        .map_err(|e| Problem::bad_request("limit must be a number", e.into()))?
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .clamp(1, MAX_LIST_LIMIT);
+    Ok((cursor, limit))
+}
+
+/// Cuts a list fetched with one more than `limit` items down to a page and says whether there are
+/// more pages after it.
+fn trim_page<T>(items: &mut Vec<T>, limit: usize) -> bool {
+    let more = items.len() > limit;
+    items.truncate(limit);
+    more
+}
+
+/// The page of machines from `machines` which was fetched with one more than `limit`.
+fn api_machines(mut machines: Vec<carol_host::MachineInfo>, limit: usize) -> api::Machines {
+    let more = trim_page(&mut machines, limit);
+    api::Machines {
+        next_cursor: machines
+            .last()
+            .filter(|_| more)
+            .map(|machine| machine.machine_id),
+        machines: machines
+            .into_iter()
+            .map(|machine| api::MachineSummary {
+                id: machine.machine_id,
+                binary_id: machine.binary_id,
+                params_size: machine.params_size,
+                created: machine.created,
+            })
+            .collect(),
+    }
+}
+
+/// How often a comment is sent down an idle event stream so that proxies don't time it out and so
+/// we notice when the client has gone away.
+const EVENT_STREAM_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+fn api_event(event: carol_host::Event) -> api::Event {
+    api::Event {
+        id: event.id,
+        time: event.time,
+        topic: event.topic,
+        data: event.data.into(),
+    }
+}
+
+/// A `text/event-stream` body of the events in a machine's log after `after` which keeps going
+/// with new events as they are published until the client goes away.
+///
+/// Each event is sent with its id as the SSE `id` and the same JSON as `/machines/{id}/events` as
+/// its `data`. The stream's `permit` is held until it ends.
+fn event_stream(
+    exec: ExecutorState,
+    machine_id: MachineId,
+    mut after: Option<u64>,
+    permit: Option<OwnedSemaphorePermit>,
+) -> Body {
+    let (mut sender, body) = Body::channel();
+    // subscribe before reading the log so nothing published in between is missed
+    let mut published = exec.subscribe_events();
+    tokio::spawn(async move {
+        let _permit = permit;
+        loop {
+            let storage = exec.storage().clone();
+            // reading the log may block on the disk
+            let events = match tokio::task::spawn_blocking(move || {
+                storage.list_events(machine_id, after, MAX_EVENTS_LIMIT)
+            })
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|events| events)
+            {
+                Ok(events) => events,
+                Err(e) => {
+                    event!(
+                        Level::ERROR,
+                        machine_id = machine_id.to_string(),
+                        error = e.to_string(),
+                        "failed to read events for event stream"
+                    );
+                    return;
+                }
+            };
+            let caught_up = events.len() < MAX_EVENTS_LIMIT;
+            for event in events {
+                after = Some(event.id);
+                let id = event.id;
+                let data = serde_json::to_string(&api_event(event)).unwrap();
+                let message = format!("id: {id}\ndata: {data}\n\n");
+                if sender.send_data(message.into()).await.is_err() {
+                    return;
+                }
+            }
+            if !caught_up {
+                continue;
+            }
+            loop {
+                match tokio::time::timeout(EVENT_STREAM_KEEP_ALIVE, published.recv()).await {
+                    Ok(Ok((published_machine_id, _))) if published_machine_id == machine_id => {
+                        break
+                    }
+                    Ok(Ok(_)) => continue,
+                    // we may have missed one of ours so check the log again
+                    Ok(Err(RecvError::Lagged(_))) => break,
+                    Ok(Err(RecvError::Closed)) => return,
+                    Err(_) => {
+                        if sender.send_data(": keep-alive\n\n".into()).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    });
+    body
+}
+
 async fn slurp_request_body(req: &mut Request<Body>) -> Result<Vec<u8>, Problem> {
     let body_stream = req.body_mut();
     let mut buf = Vec::with_capacity(body_stream.size_hint().upper().unwrap_or(0) as usize);
@@ -171,10 +212,9 @@ async fn slurp_request_body(req: &mut Request<Body>) -> Result<Vec<u8>, Problem>
         match body {
             Ok(body) => buf.extend_from_slice(body.as_ref()),
             Err(e) => {
-                return Err(Problem::new(
+                return Err(Problem::bad_request(
                     format!("Unable to fetch next chunk of post body: {}", e),
                     e.into(),
-                    StatusCode::BAD_REQUEST,
                 ))
             }
         }
@@ -183,14 +223,165 @@ async fn slurp_request_body(req: &mut Request<Body>) -> Result<Vec<u8>, Problem>
     Ok(buf)
 }
 
+/// Whether the query string has `name` or `name=true` in it.
+fn query_flag(req: &Request<Body>, name: &str) -> bool {
+    req.uri()
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .any(|param| param == name || param.strip_prefix(name) == Some("=true"))
+}
+
+/// The problem for an error from having a binary describe itself or convert machine parameters.
+/// The guest's own failures are wrapped up in the error.
+fn binary_call_problem(error: anyhow::Error) -> Problem {
+    match error.downcast::<GuestError>() {
+        Ok(guest_error) => Problem::guest_error(guest_error),
+        Err(error) => Problem::internal_server_error(error),
+    }
+}
+
+/// Whether the request says its body is JSON.
+fn has_json_body(req: &Request<Body>) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"))
+}
+
 #[derive(Clone)]
 pub struct Handler {
     state: State,
     resolver: Resolver,
+    admin_token: Option<String>,
+    api_access: config::ApiAccess,
+    api_tokens: Vec<String>,
+    client_rate_limiter: Option<RateLimiter<IpAddr>>,
+    event_streams: Option<Arc<Semaphore>>,
+}
+
+/// Passes `response` on but keeps `permit` until its body has been sent. A machine's HTTP handler
+/// carries on running while it streams the body.
+fn release_after_body(response: Response<Body>, permit: ActivationPermit) -> Response<Body> {
+    let (parts, mut body) = response.into_parts();
+    let (mut sender, new_body) = Body::channel();
+    tokio::spawn(async move {
+        let _permit = permit;
+        while let Some(chunk) = body.data().await {
+            match chunk {
+                Ok(chunk) => {
+                    if sender.send_data(chunk).await.is_err() {
+                        // client went away
+                        return;
+                    }
+                }
+                Err(_) => {
+                    sender.abort();
+                    return;
+                }
+            }
+        }
+    });
+    Response::from_parts(parts, new_body)
+}
+
+/// The problem for when `what` (a machine or binary) can't be run right now.
+fn busy_problem(what: &str, busy: Busy) -> Problem {
+    match busy {
+        Busy::RateLimited { retry_after } => {
+            Problem::too_many_requests(&format!("{what} is getting too many requests"), retry_after)
+        }
+        Busy::TooManyRunning => Problem::too_many_requests(
+            &format!("{what} is handling too many requests at once"),
+            Duration::from_secs(1),
+        ),
+    }
+}
+
+/// The address a client's rate limit is kept under. IPv6 clients are usually given a whole /64 so
+/// they could otherwise get as many limits as they like.
+fn client_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ipv4) => IpAddr::V4(ipv4),
+            None => {
+                let mut segments = ip.segments();
+                segments[4..].fill(0);
+                IpAddr::V6(Ipv6Addr::from(segments))
+            }
+        },
+    }
+}
+
+/// The bearer token in the request's `Authorization` header.
+fn bearer_token(req: &Request<Body>) -> Result<&str, Problem> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .ok_or_else(|| Problem::unauthorized("a bearer token is required"))
 }
 
 impl Handler {
-    async fn handle(self, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    /// Checks that the request carries the admin token as a bearer token.
+    fn authorize_admin(&self, req: &Request<Body>) -> Result<(), Problem> {
+        let admin_token = self
+            .admin_token
+            .as_ref()
+            .ok_or_else(|| Problem::forbidden("deleting is disabled on this node"))?;
+        if !tokens_match(bearer_token(req)?.as_bytes(), admin_token.as_bytes()) {
+            return Err(Problem::unauthorized("the bearer token is not valid"));
+        }
+        Ok(())
+    }
+
+    /// Checks that the request may upload binaries and create machines. When the API is closed it
+    /// must carry one of the API tokens (or the admin token) as a bearer token.
+    fn authorize_api(&self, req: &Request<Body>) -> Result<(), Problem> {
+        if self.api_access == config::ApiAccess::Open {
+            return Ok(());
+        }
+        let given = bearer_token(req)?;
+        // check every token so the time it takes doesn't reveal which one was close
+        let valid = self
+            .api_tokens
+            .iter()
+            .chain(&self.admin_token)
+            .fold(false, |valid, token| {
+                valid | tokens_match(given.as_bytes(), token.as_bytes())
+            });
+        if !valid {
+            return Err(Problem::unauthorized("the bearer token is not valid"));
+        }
+        Ok(())
+    }
+
+    /// Checks the machine's limits and takes one of its places for running at once.
+    ///
+    /// The activation should be run while holding on to the permit.
+    fn admit_activation(&self, machine_id: MachineId) -> Result<ActivationPermit, Problem> {
+        self.state
+            .exec
+            .admit_machine(machine_id)
+            .map_err(|busy| busy_problem(&format!("machine {machine_id}"), busy))
+    }
+
+    /// Like [`Self::admit_activation`] but for running a binary without a machine.
+    fn admit_binary(&self, binary_id: BinaryId) -> Result<ActivationPermit, Problem> {
+        self.state
+            .exec
+            .admit_binary(binary_id)
+            .map_err(|busy| busy_problem(&format!("binary {binary_id}"), busy))
+    }
+
+    async fn handle(
+        self,
+        client_ip: IpAddr,
+        req: Request<Body>,
+    ) -> Result<Response<Body>, Infallible> {
         let host = req
             .headers()
             .get(header::HOST)
@@ -204,23 +395,32 @@ impl Handler {
             host = host,
         );
 
-        match self.dispatch(req).instrument(span.clone()).await {
+        let instance = req.uri().path().to_string();
+
+        let result = match &self.client_rate_limiter {
+            Some(limiter) => limiter.check(client_key(client_ip)).map_err(|retry_after| {
+                Problem::too_many_requests(
+                    &format!("{client_ip} is making too many requests"),
+                    retry_after,
+                )
+            }),
+            None => Ok(()),
+        };
+        let result = match result {
+            Ok(()) => self.dispatch(req).instrument(span.clone()).await,
+            Err(problem) => Err(problem),
+        };
+
+        match result {
             Ok(res) => Ok(res),
             Err(problem) => {
                 let _enter = span.enter();
                 event!(
                     Level::DEBUG,
-                    error = problem.host_error.to_string(),
+                    error = problem.host_error().to_string(),
                     "HTTP response failed"
                 );
-                let status = problem.status;
-                let body = problem.into_json_body();
-                let mut response = Response::new(Body::from(body));
-                response
-                    .headers_mut()
-                    .append(header::CONTENT_TYPE, "application/json".parse().unwrap());
-                *response.status_mut() = status;
-                Ok(response)
+                Ok(problem.into_response(&instance))
             }
         }
     }
@@ -249,13 +449,26 @@ impl Handler {
         request: Request<Body>,
     ) -> Result<Response<Body>, Problem> {
         let (_, params, compiled_binary) = self.machine_components(id)?;
-        let output = self
-            .state
-            .exec
-            .executor()
+        let permit = self.admit_activation(id)?;
+        let executor = self.state.exec.executor();
+        // the guest finds out if the body is too large while reading it but we can save it the
+        // trouble if the client tells us up front
+        if let (Some(max), Some(content_length)) = (
+            executor.config().max_request_body_bytes,
+            request
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok())
+                .and_then(|len| u64::from_str(len).ok()),
+        ) {
+            if content_length > max {
+                return Err(Problem::payload_too_large(max));
+            }
+        }
+        let outcome = executor
             .machine_handle_http_request(
                 self.state.clone(),
-                compiled_binary.as_ref(),
+                compiled_binary,
                 params.as_ref(),
                 request,
             )
@@ -263,7 +476,9 @@ impl Handler {
             .map_err(Problem::internal_server_error)?
             .map_err(Problem::guest_error)?;
 
-        Ok(output)
+        let mut response = outcome.output;
+        set_fuel_consumed_header(&mut response, outcome.fuel_consumed);
+        Ok(release_after_body(response, permit))
     }
 
     pub async fn dispatch(&self, mut req: Request<Body>) -> Result<Response<Body>, Problem> {
@@ -301,7 +516,27 @@ impl Handler {
                 static_public_key: state.bls_keypair.public_key(),
                 base_domain: self.resolver.base_domain().map(ToString::to_string),
             })),
+            (&Method::GET, ["binaries"]) => {
+                let (cursor, limit) = list_page(&req)?;
+                let mut binaries = state.exec.list_binaries(cursor, limit + 1);
+                let more = trim_page(&mut binaries, limit);
+                Ok(build_response(&api::Binaries {
+                    next_cursor: binaries
+                        .last()
+                        .filter(|_| more)
+                        .map(|binary| binary.binary_id),
+                    binaries: binaries
+                        .into_iter()
+                        .map(|binary| api::BinarySummary {
+                            id: binary.binary_id,
+                            size: binary.size,
+                            created: binary.created,
+                        })
+                        .collect(),
+                }))
+            }
             (&Method::POST, ["binaries"]) => {
+                self.authorize_api(&req)?;
                 let body = slurp_request_body(&mut req).await?;
                 let binary_id = BinaryId::new(&body);
                 let already_exists = state.exec.get_binary(binary_id).is_some();
@@ -322,20 +557,22 @@ impl Handler {
                         .exec
                         .executor()
                         .load_binary_from_wasm_binary(&body)
-                        .map_err(|e| {
-                            Problem::new(
-                                format!("Invalid WASM binary with id {}: {}", binary_id, e),
-                                e,
-                                StatusCode::BAD_REQUEST,
-                            )
-                        })?;
+                        .map_err(|e| Problem::invalid_binary(binary_id, e))?;
 
                     debug_assert_eq!(compiled_binary.binary_id(), binary_id);
-                    state.exec.insert_binary(compiled_binary);
+                    state
+                        .exec
+                        .insert_binary(&body, compiled_binary)
+                        .map_err(Problem::internal_server_error)?;
                     event!(Level::INFO, "new binary uploaded");
                     Ok(build_response(&BinaryCreated { id: binary_id }))
                 }
             }
+            (method, ["binaries"]) => Err(Problem::method_not_allowed(
+                path,
+                method.as_str(),
+                &["GET", "POST"],
+            )),
             (method, ["binaries", binary_id]) => {
                 let binary_id = BinaryId::from_str(binary_id)
                     .map_err(|e| Problem::invalid_path_element::<BinaryId>(e.into(), binary_id))?;
@@ -346,28 +583,50 @@ impl Handler {
 
                 match method {
                     &Method::GET => {
+                        let _permit = self.admit_binary(binary_id)?;
                         let carol_host::guest::BinaryApi { activations } = state
                             .exec
                             .executor()
                             .get_binary_api(&binary)
                             .await
-                            .map_err(|e| {
-                                Problem::bad_request("failed to retrieve API from binary", e)
-                            })?;
+                            .map_err(binary_call_problem)?;
                         let response = build_response(&carol_http::api::BinaryDescription {
                             activations: activations
                                 .into_iter()
-                                .map(|carol_host::guest::ActivationDescription { name }| {
-                                    (name, carol_http::api::AcivationDescription {})
-                                })
+                                .map(
+                                    |carol_host::guest::ActivationDescription {
+                                         name,
+                                         schedule,
+                                     }| {
+                                        (name, carol_http::api::AcivationDescription { schedule })
+                                    },
+                                )
                                 .collect(),
                         });
                         Ok(response)
                     }
                     &Method::POST => {
-                        let params = slurp_request_body(&mut req).await?;
-                        let (already_exists, machine_id) =
-                            state.exec.insert_machine(binary_id, params);
+                        self.authorize_api(&req)?;
+                        let mut params = slurp_request_body(&mut req).await?;
+                        if has_json_body(&req) {
+                            let _permit = self.admit_binary(binary_id)?;
+                            let json = String::from_utf8(params).map_err(|e| {
+                                Problem::bad_request("JSON parameters must be UTF-8", e.into())
+                            })?;
+                            params = state
+                                .exec
+                                .executor()
+                                .machine_params_from_json(&binary, &json)
+                                .await
+                                .map_err(binary_call_problem)?
+                                .map_err(|reason| {
+                                    Problem::bad_request(reason.clone(), anyhow!(reason))
+                                })?;
+                        }
+                        let (already_exists, machine_id) = state
+                            .exec
+                            .insert_machine(binary_id, params)
+                            .map_err(Problem::internal_server_error)?;
                         let mut response = build_response(&MachineCreated { id: machine_id });
 
                         if already_exists {
@@ -381,13 +640,52 @@ impl Handler {
                         }
                         Ok(response)
                     }
+                    &Method::DELETE => {
+                        self.authorize_admin(&req)?;
+                        match state.exec.remove_binary(binary_id) {
+                            Ok(()) => {}
+                            Err(RemoveBinaryError::NotFound) => {
+                                return Err(Problem::binary_not_found(binary_id))
+                            }
+                            Err(RemoveBinaryError::InUse { n_machines }) => {
+                                return Err(Problem::binary_in_use(binary_id, n_machines))
+                            }
+                            Err(RemoveBinaryError::Other(e)) => {
+                                return Err(Problem::internal_server_error(e))
+                            }
+                        }
+                        event!(
+                            Level::INFO,
+                            binary_id = binary_id.to_string(),
+                            "binary deleted"
+                        );
+                        Ok(Response::builder()
+                            .status(StatusCode::NO_CONTENT)
+                            .body(Body::empty())
+                            .unwrap())
+                    }
                     method => Err(Problem::method_not_allowed(
                         path,
                         method.as_str(),
-                        &["POST", "GET"],
+                        &["POST", "GET", "DELETE"],
                     )),
                 }
             }
+            (method, ["binaries", binary_id, "machines"]) => {
+                let binary_id = BinaryId::from_str(binary_id)
+                    .map_err(|e| Problem::invalid_path_element::<BinaryId>(e.into(), binary_id))?;
+                if state.exec.get_binary(binary_id).is_none() {
+                    return Err(Problem::binary_not_found(binary_id));
+                }
+                match method {
+                    &Method::GET => {
+                        let (cursor, limit) = list_page(&req)?;
+                        let machines = state.exec.list_machines(Some(binary_id), cursor, limit + 1);
+                        Ok(build_response(&api_machines(machines, limit)))
+                    }
+                    method => Err(Problem::method_not_allowed(path, method.as_str(), &["GET"])),
+                }
+            }
             (method, ["machines", machine_id, trailing @ ..]) => {
                 let machine_id = MachineId::from_str(machine_id).map_err(|e| {
                     Problem::invalid_path_element::<MachineId>(e.into(), machine_id)
@@ -396,13 +694,65 @@ impl Handler {
                 match trailing {
                     &[] => match method {
                         &Method::GET => {
-                            let (binary_id, params, _) = self.machine_components(machine_id)?;
+                            let (binary_id, params, compiled_binary) =
+                                self.machine_components(machine_id)?;
+                            // converting the params means running the binary so only do it
+                            // the first time
+                            let converted = match state.exec.cached_params_json(machine_id) {
+                                Some(converted) => converted,
+                                None => {
+                                    let _permit = self.admit_activation(machine_id)?;
+                                    let converted = state
+                                        .exec
+                                        .executor()
+                                        .machine_params_to_json(&compiled_binary, &params)
+                                        .await
+                                        .map_err(binary_call_problem)?;
+                                    state.exec.cache_params_json(machine_id, converted.clone());
+                                    converted
+                                }
+                            };
+                            let params_json = match converted {
+                                Ok(json) => serde_json::from_str(&json).ok(),
+                                Err(reason) => {
+                                    event!(Level::DEBUG, reason, "params not converted to JSON");
+                                    None
+                                }
+                            };
                             Ok(build_response(&GetMachine {
                                 binary_id,
                                 params: params.as_ref(),
+                                params_json,
+                                public_key: state
+                                    .bls_keypair
+                                    .derive_for_machine(machine_id)
+                                    .public_key(),
                             }))
                         }
-                        _ => Err(Problem::method_not_allowed(path, method.as_str(), &["GET"])),
+                        &Method::DELETE => {
+                            self.authorize_admin(&req)?;
+                            if !state
+                                .exec
+                                .remove_machine(machine_id)
+                                .map_err(Problem::internal_server_error)?
+                            {
+                                return Err(Problem::machine_not_found(machine_id));
+                            }
+                            event!(
+                                Level::INFO,
+                                machine_id = machine_id.to_string(),
+                                "machine deleted"
+                            );
+                            Ok(Response::builder()
+                                .status(StatusCode::NO_CONTENT)
+                                .body(Body::empty())
+                                .unwrap())
+                        }
+                        method => Err(Problem::method_not_allowed(
+                            path,
+                            method.as_str(),
+                            &["GET", "DELETE"],
+                        )),
                     },
                     ["http", inner_path @ ..] => {
                         // we need to direct /http to /http/ so relative urls work in the machine
@@ -440,36 +790,54 @@ impl Handler {
                         let activation_name = activation_name.to_string();
                         match method {
                             &Method::POST => {
+                                let with_transcript = query_flag(&req, "transcript");
+                                let _permit = self.admit_activation(machine_id)?;
                                 let activation_input = slurp_request_body(&mut req).await?;
-                                let output = state
-                                    .exec
-                                    .executor()
-                                    .activate_machine(
-                                        state.clone(),
-                                        compiled_binary.as_ref(),
-                                        params.as_ref(),
-                                        &activation_name,
-                                        &activation_input,
-                                    )
-                                    .await
-                                    .map_err(|e| {
-                                        Problem::new(
-                                            format!(
-                                                "error occurred while trying to activate machine: {}",
-                                                e
-                                            ),
-                                            e,
-                                            StatusCode::INTERNAL_SERVER_ERROR,
+                                let executor = state.exec.executor();
+                                let activation = if with_transcript {
+                                    executor
+                                        .activate_machine_with_transcript(
+                                            state.clone(),
+                                            compiled_binary.as_ref(),
+                                            params.as_ref(),
+                                            &activation_name,
+                                            &activation_input,
                                         )
-                                    })?
-                                    .map_err(|e| {
-                                        Problem::new(
+                                        .await
+                                } else {
+                                    executor
+                                        .activate_machine(
+                                            state.clone(),
+                                            compiled_binary.as_ref(),
+                                            params.as_ref(),
+                                            &activation_name,
+                                            &activation_input,
+                                        )
+                                        .await
+                                };
+                                let outcome = activation
+                                    .map_err(Problem::activation_failed)?
+                                    .map_err(|e| match e {
+                                        GuestError::Other(e) => Problem::new(
+                                            ProblemType::ActivationFailed,
                                             format!("machine failed to complete activation: {}", e),
-                                            e.into(),
+                                            e,
                                             StatusCode::BAD_REQUEST,
-                                        )
+                                        ),
+                                        e => Problem::guest_error(e),
                                     })?;
-                                Ok(Response::new(Body::from(output)))
+                                let mut response = match outcome.transcript {
+                                    Some(transcript) => build_response(&api::SignedTranscript {
+                                        signature: carol_bls::sign_transcript(
+                                            state.bls_keypair,
+                                            &transcript,
+                                        ),
+                                        transcript,
+                                    }),
+                                    None => Response::new(Body::from(outcome.output)),
+                                };
+                                set_fuel_consumed_header(&mut response, outcome.fuel_consumed);
+                                Ok(response)
                             }
                             method => Err(Problem::method_not_allowed(
                                 path,
@@ -478,10 +846,124 @@ impl Handler {
                             )),
                         }
                     }
+                    ["events"] => match method {
+                        &Method::GET => {
+                            self.machine_components(machine_id)?;
+                            let cursor = query_param(&req, "cursor")
+                                .map(u64::from_str)
+                                .transpose()
+                                .map_err(|e| {
+                                    Problem::bad_request("cursor must be an event id", e.into())
+                                })?;
+                            let limit = query_param(&req, "limit")
+                                .map(usize::from_str)
+                                .transpose()
+                                .map_err(|e| {
+                                    Problem::bad_request("limit must be a number", e.into())
+                                })?
+                                .unwrap_or(DEFAULT_EVENTS_LIMIT)
+                                .min(MAX_EVENTS_LIMIT);
+                            let events = state
+                                .exec
+                                .storage()
+                                .list_events(machine_id, cursor, limit)
+                                .map_err(Problem::internal_server_error)?;
+                            Ok(build_response(&api::Events {
+                                next_cursor: events.last().map(|event| event.id).or(cursor),
+                                events: events.into_iter().map(api_event).collect(),
+                            }))
+                        }
+                        method => Err(Problem::method_not_allowed(path, method.as_str(), &["GET"])),
+                    },
+                    ["events", "stream"] => match method {
+                        &Method::GET => {
+                            self.machine_components(machine_id)?;
+                            // EventSource sends Last-Event-ID when it reconnects but it can't be
+                            // set on the first connection so the cursor can be given in the query
+                            let last_event_id = match req.headers().get("last-event-id") {
+                                Some(last_event_id) => Some(
+                                    last_event_id
+                                        .to_str()
+                                        .map_err(anyhow::Error::from)
+                                        .and_then(|id| Ok(u64::from_str(id)?))
+                                        .map_err(|e| {
+                                            Problem::bad_request(
+                                                "Last-Event-ID must be an event id",
+                                                e,
+                                            )
+                                        })?,
+                                ),
+                                None => query_param(&req, "cursor")
+                                    .map(u64::from_str)
+                                    .transpose()
+                                    .map_err(|e| {
+                                        Problem::bad_request("cursor must be an event id", e.into())
+                                    })?,
+                            };
+                            // streams count towards the machine's rate limit but don't keep one
+                            // of its places for running at once while they're open
+                            drop(self.admit_activation(machine_id)?);
+                            let permit = match &self.event_streams {
+                                Some(event_streams) => Some(
+                                    event_streams.clone().try_acquire_owned().map_err(|_| {
+                                        Problem::too_many_requests(
+                                            "too many event streams are open",
+                                            Duration::from_secs(1),
+                                        )
+                                    })?,
+                                ),
+                                None => None,
+                            };
+                            Ok(Response::builder()
+                                .header(header::CONTENT_TYPE, "text/event-stream")
+                                .header(header::CACHE_CONTROL, "no-cache")
+                                .body(event_stream(
+                                    state.exec.clone(),
+                                    machine_id,
+                                    last_event_id,
+                                    permit,
+                                ))
+                                .unwrap())
+                        }
+                        method => Err(Problem::method_not_allowed(path, method.as_str(), &["GET"])),
+                    },
+                    ["scheduled", activation_name] => match method {
+                        &Method::GET => {
+                            self.machine_components(machine_id)?;
+                            let since = match query_param(&req, "since") {
+                                Some(since) => u64::from_str(since).map_err(|e| {
+                                    Problem::bad_request("since must be a unix timestamp", e.into())
+                                })?,
+                                None => 0,
+                            };
+                            let outputs = state
+                                .exec
+                                .storage()
+                                .list_scheduled_outputs(machine_id, activation_name, since)
+                                .map_err(Problem::internal_server_error)?;
+                            Ok(build_response(&api::ScheduledOutputs {
+                                outputs: outputs
+                                    .into_iter()
+                                    .map(|(time, output)| api::ScheduledOutput {
+                                        time,
+                                        output: output.into(),
+                                    })
+                                    .collect(),
+                            }))
+                        }
+                        method => Err(Problem::method_not_allowed(path, method.as_str(), &["GET"])),
+                    },
                     _ => Err(Problem::not_found(path)),
                 }
             }
-            (method, ["machines"]) => Err(Problem::method_not_allowed(path, method.as_str(), &[])),
+            (&Method::GET, ["machines"]) => {
+                let (cursor, limit) = list_page(&req)?;
+                let machines = state.exec.list_machines(None, cursor, limit + 1);
+                Ok(build_response(&api_machines(machines, limit)))
+            }
+            (method, ["machines"]) => {
+                Err(Problem::method_not_allowed(path, method.as_str(), &["GET"]))
+            }
             _ => Err(Problem::not_found(path)),
         }
     }
@@ -491,15 +973,27 @@ pub fn start(
     config: config::HttpServerConfig,
     state: State,
 ) -> anyhow::Result<(SocketAddr, impl Future<Output = ()> + Send + Sync + 'static)> {
+    config.validate()?;
     let handler = Handler {
         state,
         resolver: config.dns.into_resolver(),
+        admin_token: config.admin_token,
+        api_access: config.api_access,
+        api_tokens: config.api_tokens,
+        client_rate_limiter: config
+            .rate_limits
+            .per_client
+            .map(|limit| RateLimiter::new(limit.into_host())),
+        event_streams: config
+            .max_event_streams
+            .map(|max| Arc::new(Semaphore::new(max))),
     };
 
     // And a MakeService to handle each connection...
-    let make_service = make_service_fn(move |_conn| {
+    let make_service = make_service_fn(move |conn: &AddrStream| {
         let handler = handler.clone();
-        let service = move |req| handler.clone().handle(req);
+        let client_ip = conn.remote_addr().ip();
+        let service = move |req| handler.clone().handle(client_ip, req);
         async move { Ok::<_, Infallible>(service_fn(service)) }
     });
 
@@ -520,3 +1014,20 @@ pub fn start(
     };
     Ok((local_addr, server))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ipv6_clients_are_limited_by_their_64() {
+        let key = |ip: &str| client_key(IpAddr::from_str(ip).unwrap());
+        assert_eq!(key("192.0.2.1"), key("192.0.2.1"));
+        assert_ne!(key("192.0.2.1"), key("192.0.2.2"));
+        assert_eq!(key("2001:db8:1:2:3:4:5:6"), key("2001:db8:1:2::"));
+        assert_ne!(key("2001:db8:1:2::"), key("2001:db8:1:3::"));
+        // IPv4 clients connecting over IPv6 still get a limit of their own
+        assert_eq!(key("::ffff:192.0.2.1"), key("192.0.2.1"));
+        assert_ne!(key("::ffff:192.0.2.1"), key("::ffff:192.0.2.2"));
+    }
+}
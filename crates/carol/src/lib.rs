@@ -1,2 +1,4 @@
 pub mod config;
+pub mod gc;
 pub mod http;
+pub mod scheduler;
@@ -0,0 +1,207 @@
+//! Runs the activations machines have asked the node to run on a schedule.
+//!
+//! The output of each run is kept in storage under the time the run was due so it can be fetched
+//! from `GET /machines/{id}/scheduled/{activation}`. Only the most recent outputs of each
+//! activation are kept (see [`SchedulerConfig`]).
+use crate::config::SchedulerConfig;
+use anyhow::Context;
+use carol_core::{schedule::Schedule, BinaryId, MachineId};
+use carol_host::State;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+use tracing::{event, Level};
+
+/// How often the scheduler checks whether anything is due.
+const TICK: Duration = Duration::from_secs(1);
+
+pub struct Scheduler {
+    state: State,
+    config: SchedulerConfig,
+    /// The scheduled activations of each binary we've come across.
+    schedules: HashMap<BinaryId, Vec<(String, Schedule)>>,
+    /// When each scheduled activation of each machine is next due. `None` if never.
+    next_due: HashMap<(MachineId, String), Option<u64>>,
+    /// The scheduled activations that are running right now.
+    running: Arc<Mutex<HashSet<(MachineId, String)>>>,
+}
+
+impl Scheduler {
+    pub fn new(state: State, config: SchedulerConfig) -> Self {
+        Self {
+            state,
+            config,
+            schedules: Default::default(),
+            next_due: Default::default(),
+            running: Default::default(),
+        }
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("system clock is after the unix epoch")
+                .as_secs();
+            self.tick(now).await;
+            tokio::time::sleep(TICK).await;
+        }
+    }
+
+    /// Starts every scheduled activation that is due at `now` and returns the runs it started.
+    ///
+    /// Activations are first due at the first time after the machine is noticed by the scheduler
+    /// so runs that were missed while the node was down aren't made up. A run is skipped if the
+    /// previous run of the same activation of the same machine is still going.
+    pub async fn tick(&mut self, now: u64) -> Vec<JoinHandle<()>> {
+        let mut started = vec![];
+        let machines = self.state.exec.list_machines(None, None, usize::MAX);
+        // forget about machines and binaries that have been removed
+        self.next_due.retain(|(machine_id, _), _| {
+            machines
+                .binary_search_by_key(machine_id, |machine| machine.machine_id)
+                .is_ok()
+        });
+        self.schedules.retain(|binary_id, _| {
+            machines
+                .iter()
+                .any(|machine| machine.binary_id == *binary_id)
+        });
+        for machine in machines {
+            let machine_id = machine.machine_id;
+            for (activation, schedule) in self.schedules(machine.binary_id).await {
+                let next_due = self
+                    .next_due
+                    .entry((machine_id, activation.clone()))
+                    .or_insert_with(|| schedule.next_after(now));
+                match *next_due {
+                    Some(due) if due <= now => {
+                        *next_due = schedule.next_after(now);
+                        let key = (machine_id, activation.clone());
+                        if !self.running.lock().unwrap().insert(key.clone()) {
+                            event!(
+                                Level::WARN,
+                                machine_id = machine_id.to_string(),
+                                activation,
+                                due,
+                                "skipping scheduled activation because its last run hasn't finished"
+                            );
+                            continue;
+                        }
+                        let running = self.running.clone();
+                        let run = run_scheduled_activation(
+                            self.state.clone(),
+                            self.config.clone(),
+                            machine_id,
+                            activation,
+                            due,
+                        );
+                        started.push(tokio::spawn(async move {
+                            run.await;
+                            running.lock().unwrap().remove(&key);
+                        }));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        started
+    }
+
+    async fn schedules(&mut self, binary_id: BinaryId) -> Vec<(String, Schedule)> {
+        if let Some(schedules) = self.schedules.get(&binary_id) {
+            return schedules.clone();
+        }
+        let compiled_binary = match self.state.exec.get_binary(binary_id) {
+            Some(compiled_binary) => compiled_binary,
+            None => return vec![],
+        };
+        let binary_api = match self
+            .state
+            .exec
+            .executor()
+            .get_binary_api(&compiled_binary)
+            .await
+        {
+            Ok(binary_api) => binary_api,
+            Err(e) => {
+                event!(
+                    Level::ERROR,
+                    binary_id = binary_id.to_string(),
+                    error = e.to_string(),
+                    "couldn't get the binary API to find scheduled activations"
+                );
+                return vec![];
+            }
+        };
+        let mut schedules = vec![];
+        for activation in binary_api.activations {
+            let Some(schedule) = activation.schedule else {
+                continue;
+            };
+            match schedule.parse::<Schedule>() {
+                Ok(schedule) => schedules.push((activation.name, schedule)),
+                Err(e) => event!(
+                    Level::WARN,
+                    binary_id = binary_id.to_string(),
+                    activation = activation.name,
+                    error = e.to_string(),
+                    "ignoring activation with an invalid schedule"
+                ),
+            }
+        }
+        self.schedules.insert(binary_id, schedules.clone());
+        schedules
+    }
+}
+
+async fn run_scheduled_activation(
+    state: State,
+    config: SchedulerConfig,
+    machine_id: MachineId,
+    activation: String,
+    due: u64,
+) {
+    let result = async {
+        let (binary_id, params) = state
+            .exec
+            .get_machine(machine_id)
+            .context("machine no longer exists")?;
+        let compiled_binary = state
+            .exec
+            .get_binary(binary_id)
+            .context("binary no longer exists")?;
+        let _permit = state
+            .exec
+            .admit_machine(machine_id)
+            .context("machine is too busy")?;
+        let outcome = state
+            .exec
+            .executor()
+            .activate_machine(state.clone(), &compiled_binary, &params, &activation, &[])
+            .await??;
+        let storage = state.exec.storage();
+        storage.put_scheduled_output(machine_id, &activation, due, &outcome.output)?;
+        storage.prune_scheduled_outputs(machine_id, &activation, config.max_outputs_per_activation)
+    }
+    .await;
+
+    match result {
+        Ok(()) => event!(
+            Level::INFO,
+            machine_id = machine_id.to_string(),
+            activation,
+            due,
+            "ran scheduled activation"
+        ),
+        Err(e) => event!(
+            Level::ERROR,
+            machine_id = machine_id.to_string(),
+            activation,
+            due,
+            error = e.to_string(),
+            "scheduled activation failed"
+        ),
+    }
+}
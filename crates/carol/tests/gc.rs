@@ -0,0 +1,36 @@
+use carol::gc::unreferenced_binaries;
+use carol_core::{BinaryId, MachineId};
+use carol_host::{MemoryStorage, Storage};
+
+#[test]
+fn only_old_binaries_without_machines_are_collected() {
+    let storage = MemoryStorage::default();
+    let [in_use, old, new] = [&b"in use"[..], b"old", b"new"].map(BinaryId::new);
+    storage.put_binary(in_use, b"in use", 100).unwrap();
+    storage.put_binary(old, b"old", 100).unwrap();
+    storage.put_binary(new, b"new", 900).unwrap();
+    storage
+        .put_machine(MachineId::new(in_use, b""), in_use, b"", 100)
+        .unwrap();
+
+    assert_eq!(
+        unreferenced_binaries(&storage, 500, 1_000).unwrap(),
+        vec![old]
+    );
+    let mut all_unused = vec![old, new];
+    all_unused.sort();
+    assert_eq!(
+        unreferenced_binaries(&storage, 100, 1_000).unwrap(),
+        all_unused
+    );
+    // a binary exactly min_age_secs old is old enough
+    assert_eq!(
+        unreferenced_binaries(&storage, 900, 1_000).unwrap(),
+        vec![old]
+    );
+
+    storage.delete_machine(MachineId::new(in_use, b"")).unwrap();
+    assert!(unreferenced_binaries(&storage, 500, 1_000)
+        .unwrap()
+        .contains(&in_use));
+}
@@ -0,0 +1,120 @@
+use carol::config::SchedulerConfig;
+use carol::scheduler::Scheduler;
+use carol_core::BinaryId;
+use carol_host::rate_limit::MachineLimits;
+use carol_host::{Executor, ExecutorState, State};
+
+#[path = "../../carol_host/tests/common/mod.rs"]
+mod common;
+use common::Guest;
+
+/// A guest with a `tick` activation scheduled `@every 60s` which returns `tock`.
+fn scheduled_guest_component() -> Vec<u8> {
+    Guest {
+        module_fields: r#"
+      ;; binary-api: the activations list
+      (data (i32.const 3000) "\1c\0c\00\00\01\00\00\00")
+      ;; the activation: name, some(schedule)
+      (data (i32.const 3100) "\80\0c\00\00\04\00\00\00\01\00\00\00\e4\0c\00\00\0a\00\00\00")
+      (data (i32.const 3200) "tick")
+      (data (i32.const 3300) "@every 60s")
+      ;; the output list
+      (data (i32.const 3400) "\50\0d\00\00\04\00\00\00")
+      (data (i32.const 3408) "tock")"#,
+        get_binary_api: "i32.const 3000",
+        activate: "i32.const 3400",
+        ..Default::default()
+    }
+    .build()
+}
+
+#[tokio::test]
+async fn scheduled_activations_run_when_due() {
+    let executor = Executor::new();
+    let binary = scheduled_guest_component();
+    let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+    let exec = ExecutorState::new(executor);
+    exec.insert_binary(&binary, compiled_binary).unwrap();
+    let (_, machine_id) = exec.insert_machine(BinaryId::new(&binary), vec![]).unwrap();
+    let state = State {
+        exec: exec.clone(),
+        ..State::new(
+            carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+            carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        )
+    };
+    let mut scheduler = Scheduler::new(
+        state,
+        SchedulerConfig {
+            max_outputs_per_activation: 2,
+        },
+    );
+    let outputs = || {
+        exec.storage()
+            .list_scheduled_outputs(machine_id, "tick", 0)
+            .unwrap()
+    };
+
+    // the machine is noticed at 30 so the first run is due at 60
+    assert!(scheduler.tick(30).await.is_empty());
+    assert!(scheduler.tick(59).await.is_empty());
+    let first = scheduler.tick(60).await;
+    assert_eq!(first.len(), 1);
+    // the run due at 120 is skipped since the one due at 60 hasn't finished
+    assert!(scheduler.tick(120).await.is_empty());
+    for run in first {
+        run.await.unwrap();
+    }
+    assert_eq!(outputs(), vec![(60, b"tock".to_vec())]);
+
+    for now in [180, 240] {
+        for run in scheduler.tick(now).await {
+            run.await.unwrap();
+        }
+    }
+    // only the two most recent outputs are kept
+    assert_eq!(
+        outputs(),
+        vec![(180, b"tock".to_vec()), (240, b"tock".to_vec())]
+    );
+}
+
+#[tokio::test]
+async fn scheduled_activations_are_subject_to_machine_limits() {
+    let executor = Executor::new();
+    let binary = scheduled_guest_component();
+    let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+    let exec = ExecutorState::new(executor).limit_machines(MachineLimits {
+        rate: None,
+        max_concurrent: Some(1),
+    });
+    exec.insert_binary(&binary, compiled_binary).unwrap();
+    let (_, machine_id) = exec.insert_machine(BinaryId::new(&binary), vec![]).unwrap();
+    let state = State {
+        exec: exec.clone(),
+        ..State::new(
+            carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+            carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        )
+    };
+    let mut scheduler = Scheduler::new(state, SchedulerConfig::default());
+    let outputs = || {
+        exec.storage()
+            .list_scheduled_outputs(machine_id, "tick", 0)
+            .unwrap()
+    };
+
+    // something else is running the machine so the run due at 60 is refused
+    let permit = exec.admit_machine(machine_id).unwrap();
+    assert!(scheduler.tick(30).await.is_empty());
+    for run in scheduler.tick(60).await {
+        run.await.unwrap();
+    }
+    assert!(outputs().is_empty());
+
+    drop(permit);
+    for run in scheduler.tick(120).await {
+        run.await.unwrap();
+    }
+    assert_eq!(outputs(), vec![(120, b"tock".to_vec())]);
+}
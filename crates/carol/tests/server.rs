@@ -0,0 +1,630 @@
+use carol::config::{ApiAccess, HttpServerConfig, RateLimit, RateLimitConfig};
+use carol_core::{BinaryId, MachineId};
+use carol_host::rate_limit::{self, MachineLimits};
+use carol_host::{Executor, ExecutorConfig, ExecutorState, State};
+use carol_http::api;
+use hyper::body::HttpBody;
+use hyper::{Body, Client, HeaderMap, Method, Request, StatusCode};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+#[path = "../../carol_host/tests/common/mod.rs"]
+mod common;
+use common::{guest_component, Guest};
+
+/// Fuel is limited so guests that loop forever fail quickly.
+fn test_state() -> State {
+    State {
+        exec: ExecutorState::new(Executor::with_config(ExecutorConfig {
+            fuel_per_activation: Some(1_000_000),
+            ..Default::default()
+        })),
+        ..State::new(
+            carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+            carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        )
+    }
+}
+
+/// Starts a server for `state` on a random port.
+fn start_server(config: HttpServerConfig, state: State) -> SocketAddr {
+    let (addr, server) = carol::http::server::start(
+        HttpServerConfig {
+            listen: SocketAddr::from_str("127.0.0.1:0").unwrap(),
+            ..config
+        },
+        state,
+    )
+    .unwrap();
+    tokio::spawn(server);
+    addr
+}
+
+/// Uploads a binary and creates a machine from it without going through the server.
+fn insert_machine(exec: &ExecutorState, binary: &[u8], params: Vec<u8>) -> MachineId {
+    let compiled_binary = exec
+        .executor()
+        .load_binary_from_wasm_binary(binary)
+        .unwrap();
+    exec.insert_binary(binary, compiled_binary).unwrap();
+    exec.insert_machine(BinaryId::new(binary), params)
+        .unwrap()
+        .1
+}
+
+async fn request(
+    addr: SocketAddr,
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Vec<u8>,
+) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(format!("http://{addr}{path}"));
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    let response = Client::new()
+        .request(builder.body(Body::from(body)).unwrap())
+        .await
+        .unwrap();
+    let (parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap();
+    (parts.status, parts.headers, body.to_vec())
+}
+
+async fn get_json<T: serde::de::DeserializeOwned>(addr: SocketAddr, path: &str) -> T {
+    let response = Client::new()
+        .get(format!("http://{addr}{path}").parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+/// Reads an event stream one SSE message at a time.
+struct EventStream {
+    body: Body,
+    buf: String,
+}
+
+impl EventStream {
+    async fn request(
+        addr: SocketAddr,
+        machine_id: MachineId,
+        last_event_id: Option<u64>,
+    ) -> hyper::Response<Body> {
+        let mut builder =
+            Request::get(format!("http://{addr}/machines/{machine_id}/events/stream"));
+        if let Some(last_event_id) = last_event_id {
+            builder = builder.header("last-event-id", last_event_id.to_string());
+        }
+        Client::new()
+            .request(builder.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn open(addr: SocketAddr, machine_id: MachineId, last_event_id: Option<u64>) -> Self {
+        let response = Self::request(addr, machine_id, last_event_id).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        Self {
+            body: response.into_body(),
+            buf: String::new(),
+        }
+    }
+
+    async fn next_event(&mut self) -> (u64, api::Event) {
+        loop {
+            if let Some(end) = self.buf.find("\n\n") {
+                let message = self.buf[..end].to_string();
+                self.buf.drain(..end + 2);
+                let (mut id, mut data) = (None, None);
+                for line in message.lines() {
+                    if let Some(value) = line.strip_prefix("id: ") {
+                        id = Some(u64::from_str(value).unwrap());
+                    } else if let Some(value) = line.strip_prefix("data: ") {
+                        data = Some(serde_json::from_str(value).unwrap());
+                    }
+                }
+                match (id, data) {
+                    (Some(id), Some(data)) => return (id, data),
+                    // keep-alive comment
+                    _ => continue,
+                }
+            }
+            let chunk = tokio::time::timeout(Duration::from_secs(10), self.body.data())
+                .await
+                .expect("event should arrive")
+                .expect("stream should not end")
+                .unwrap();
+            self.buf += std::str::from_utf8(&chunk).unwrap();
+        }
+    }
+}
+
+#[tokio::test]
+async fn event_stream_sends_backlog_then_live_events_and_resumes() {
+    let state = test_state();
+    let machine_id = insert_machine(&state.exec, &guest_component(""), vec![]);
+    let first = state
+        .exec
+        .publish_event(machine_id, 1, "topic", b"one")
+        .unwrap();
+    let second = state
+        .exec
+        .publish_event(machine_id, 2, "topic", b"two")
+        .unwrap();
+    let addr = start_server(HttpServerConfig::default(), state.clone());
+
+    let mut stream = EventStream::open(addr, machine_id, None).await;
+    let (id, event) = stream.next_event().await;
+    assert_eq!(
+        (id, event.id, &event.data.0[..]),
+        (first, first, &b"one"[..])
+    );
+    assert_eq!(stream.next_event().await.0, second);
+
+    let third = state
+        .exec
+        .publish_event(machine_id, 3, "topic", b"three")
+        .unwrap();
+    let (id, event) = stream.next_event().await;
+    assert_eq!((id, event.topic.as_str(), event.time), (third, "topic", 3));
+    drop(stream);
+
+    // a reconnecting client only gets what it hasn't seen
+    let mut stream = EventStream::open(addr, machine_id, Some(second)).await;
+    assert_eq!(stream.next_event().await.0, third);
+}
+
+#[tokio::test]
+async fn event_streams_are_capped() {
+    let state = test_state();
+    let machine_id = insert_machine(&state.exec, &guest_component(""), vec![]);
+    let addr = start_server(
+        HttpServerConfig {
+            max_event_streams: Some(1),
+            ..Default::default()
+        },
+        state.clone(),
+    );
+
+    let stream = EventStream::open(addr, machine_id, None).await;
+    let response = EventStream::request(addr, machine_id, None).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], "1");
+
+    // the server notices the client has gone away when it fails to send something down the
+    // stream
+    drop(stream);
+    let mut status = StatusCode::TOO_MANY_REQUESTS;
+    for time in 0..50 {
+        state
+            .exec
+            .publish_event(machine_id, time, "topic", b"data")
+            .unwrap();
+        status = EventStream::request(addr, machine_id, None).await.status();
+        if status != StatusCode::TOO_MANY_REQUESTS {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn binaries_are_listed_a_page_at_a_time() {
+    let state = test_state();
+    let mut binary_ids = (0..3)
+        .map(|n| {
+            let binary = guest_component(&format!("i32.const {n}"));
+            insert_machine(&state.exec, &binary, vec![]);
+            BinaryId::new(&binary)
+        })
+        .collect::<Vec<_>>();
+    binary_ids.sort();
+    let addr = start_server(HttpServerConfig::default(), state);
+    let ids = |binaries: &api::Binaries| {
+        binaries
+            .binaries
+            .iter()
+            .map(|binary| binary.id)
+            .collect::<Vec<_>>()
+    };
+
+    let first: api::Binaries = get_json(addr, "/binaries?limit=2").await;
+    assert_eq!(ids(&first), binary_ids[..2]);
+    assert_eq!(first.next_cursor, Some(binary_ids[1]));
+
+    let last: api::Binaries =
+        get_json(addr, &format!("/binaries?limit=2&cursor={}", binary_ids[1])).await;
+    assert_eq!(ids(&last), binary_ids[2..]);
+    assert_eq!(last.next_cursor, None);
+
+    // a full last page doesn't point to an empty one
+    let all: api::Binaries = get_json(addr, "/binaries?limit=3").await;
+    assert_eq!(ids(&all), binary_ids);
+    assert_eq!(all.next_cursor, None);
+}
+
+#[tokio::test]
+async fn machine_pages_are_capped() {
+    let state = test_state();
+    let binary = guest_component("");
+    insert_machine(&state.exec, &binary, vec![]);
+    for n in 1..1001u32 {
+        state
+            .exec
+            .insert_machine(BinaryId::new(&binary), n.to_le_bytes().to_vec())
+            .unwrap();
+    }
+    let other_machine = insert_machine(&state.exec, &guest_component("i32.const 0"), vec![]);
+    let addr = start_server(HttpServerConfig::default(), state);
+
+    let first: api::Machines = get_json(addr, "/machines?limit=5000").await;
+    assert_eq!(first.machines.len(), 1000);
+    let cursor = first.next_cursor.expect("there is another page");
+    assert_eq!(cursor, first.machines[999].id);
+    let last: api::Machines =
+        get_json(addr, &format!("/machines?limit=5000&cursor={cursor}")).await;
+    assert_eq!(last.machines.len(), 2);
+    assert_eq!(last.next_cursor, None);
+
+    let others: api::Machines = get_json(
+        addr,
+        &format!(
+            "/binaries/{}/machines",
+            BinaryId::new(&guest_component("i32.const 0"))
+        ),
+    )
+    .await;
+    assert_eq!(others.machines.len(), 1);
+    assert_eq!(others.machines[0].id, other_machine);
+    assert_eq!(others.next_cursor, None);
+}
+
+#[tokio::test]
+async fn deleting_needs_the_admin_token() {
+    let state = test_state();
+    let binary = guest_component("");
+    let machine_id = insert_machine(&state.exec, &binary, vec![]);
+    let binary_path = format!("/binaries/{}", BinaryId::new(&binary));
+    let machine_path = format!("/machines/{machine_id}");
+    let delete = |addr, path: &str, token: Option<&str>| {
+        let authorization = token.map(|token| format!("Bearer {token}"));
+        let path = path.to_string();
+        async move {
+            let headers = match &authorization {
+                Some(authorization) => vec![("authorization", authorization.as_str())],
+                None => vec![],
+            };
+            request(addr, Method::DELETE, &path, &headers, vec![])
+                .await
+                .0
+        }
+    };
+
+    let disabled = start_server(HttpServerConfig::default(), state.clone());
+    assert_eq!(
+        delete(disabled, &machine_path, Some("admin")).await,
+        StatusCode::FORBIDDEN
+    );
+
+    let addr = start_server(
+        HttpServerConfig {
+            admin_token: Some("admin".into()),
+            ..Default::default()
+        },
+        state.clone(),
+    );
+    assert_eq!(
+        delete(addr, &machine_path, None).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        delete(addr, &machine_path, Some("admim")).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert!(state.exec.get_machine(machine_id).is_some());
+
+    // the binary can't go while a machine runs it
+    assert_eq!(
+        delete(addr, &binary_path, Some("admin")).await,
+        StatusCode::CONFLICT
+    );
+    assert_eq!(
+        delete(addr, &machine_path, Some("admin")).await,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        delete(addr, &binary_path, Some("admin")).await,
+        StatusCode::NO_CONTENT
+    );
+    assert_eq!(
+        delete(addr, &binary_path, Some("admin")).await,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn params_are_converted_to_json() {
+    let state = test_state();
+    let binary = Guest {
+        module_fields: r#"
+      ;; ok("{\"a\":1}")
+      (data (i32.const 3000) "\00\00\00\00\1c\0c\00\00\07\00\00\00")
+      (data (i32.const 3100) "{\"a\":1}")"#,
+        params_to_json: "i32.const 3000",
+        ..Default::default()
+    }
+    .build();
+    let machine_id = insert_machine(&state.exec, &binary, vec![]);
+    let addr = start_server(HttpServerConfig::default(), state);
+
+    for _ in 0..2 {
+        let machine: serde_json::Value = get_json(addr, &format!("/machines/{machine_id}")).await;
+        assert_eq!(machine["params_json"], serde_json::json!({ "a": 1 }));
+    }
+}
+
+#[tokio::test]
+async fn guest_errors_converting_params_are_reported_as_such() {
+    let state = test_state();
+    let binary = Guest {
+        params_from_json: "(loop br 0) unreachable",
+        params_to_json: "(loop br 0) unreachable",
+        ..Default::default()
+    }
+    .build();
+    let machine_id = insert_machine(&state.exec, &binary, vec![]);
+    let addr = start_server(HttpServerConfig::default(), state);
+    let out_of_fuel = |(status, _, body): (StatusCode, HeaderMap, Vec<u8>)| {
+        let problem: api::Problem = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            problem.problem_type(),
+            Some(api::ProblemType::GuestOutOfFuel)
+        );
+    };
+
+    out_of_fuel(
+        request(
+            addr,
+            Method::GET,
+            &format!("/machines/{machine_id}"),
+            &[],
+            vec![],
+        )
+        .await,
+    );
+    out_of_fuel(
+        request(
+            addr,
+            Method::POST,
+            &format!("/binaries/{}", BinaryId::new(&binary)),
+            &[("content-type", "application/json")],
+            b"{}".to_vec(),
+        )
+        .await,
+    );
+}
+
+#[tokio::test]
+async fn errors_are_problem_json() {
+    let addr = start_server(HttpServerConfig::default(), test_state());
+    let machine_id = MachineId::new(BinaryId::new(b"binary"), b"params");
+    let path = format!("/machines/{machine_id}");
+
+    let (status, headers, body) = request(addr, Method::GET, &path, &[], vec![]).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(headers["content-type"], api::PROBLEM_CONTENT_TYPE);
+    let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        problem,
+        serde_json::json!({
+            "type": "tag:carol.computer,2023:problems/machine-not-found",
+            "title": "Machine not found",
+            "status": 404,
+            "detail": format!("machine {machine_id} not found"),
+            "instance": path,
+        })
+    );
+
+    let (status, headers, body) = request(addr, Method::PUT, "/machines", &[], vec![]).await;
+    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(headers["allow"], "GET");
+    assert_eq!(headers["content-type"], api::PROBLEM_CONTENT_TYPE);
+    let problem: api::Problem = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        problem.problem_type(),
+        Some(api::ProblemType::MethodNotAllowed)
+    );
+    assert_eq!(problem.status, 405);
+    assert_eq!(problem.instance.as_deref(), Some("/machines"));
+}
+
+#[tokio::test]
+async fn closed_api_needs_a_token() {
+    let state = test_state();
+    let addr = start_server(
+        HttpServerConfig {
+            api_access: ApiAccess::Closed,
+            api_tokens: vec!["api".into()],
+            admin_token: Some("admin".into()),
+            ..Default::default()
+        },
+        state.clone(),
+    );
+    let post = |path: String, token: Option<&'static str>, body: Vec<u8>| async move {
+        let authorization = token.map(|token| format!("Bearer {token}"));
+        let headers = match &authorization {
+            Some(authorization) => vec![("authorization", authorization.as_str())],
+            None => vec![],
+        };
+        let (status, headers, _) = request(addr, Method::POST, &path, &headers, body).await;
+        (status, headers)
+    };
+    let binary = guest_component("");
+    let machine_path = format!("/binaries/{}", BinaryId::new(&binary));
+
+    for token in [None, Some("wrong"), Some("")] {
+        let (status, headers) = post("/binaries".into(), token, binary.clone()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(headers["www-authenticate"], "Bearer");
+    }
+    assert!(state.exec.list_binaries(None, 10).is_empty());
+    assert_eq!(
+        post("/binaries".into(), Some("api"), binary.clone())
+            .await
+            .0,
+        StatusCode::CREATED
+    );
+    assert_eq!(
+        post(
+            "/binaries".into(),
+            Some("admin"),
+            guest_component("i32.const 0")
+        )
+        .await
+        .0,
+        StatusCode::CREATED
+    );
+
+    for token in [None, Some("wrong")] {
+        assert_eq!(
+            post(machine_path.clone(), token, vec![]).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+    assert!(state.exec.list_machines(None, None, 10).is_empty());
+    assert_eq!(
+        post(machine_path.clone(), Some("api"), vec![1]).await.0,
+        StatusCode::CREATED
+    );
+    assert_eq!(
+        post(machine_path, Some("admin"), vec![2]).await.0,
+        StatusCode::CREATED
+    );
+}
+
+#[tokio::test]
+async fn open_api_needs_no_token() {
+    let addr = start_server(HttpServerConfig::default(), test_state());
+    let binary = guest_component("");
+    let (status, _, _) = request(addr, Method::POST, "/binaries", &[], binary.clone()).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _, _) = request(
+        addr,
+        Method::POST,
+        &format!("/binaries/{}", BinaryId::new(&binary)),
+        &[],
+        vec![],
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn empty_tokens_are_rejected() {
+    for config in [
+        HttpServerConfig {
+            admin_token: Some("".into()),
+            ..Default::default()
+        },
+        HttpServerConfig {
+            api_tokens: vec!["api".into(), "".into()],
+            ..Default::default()
+        },
+    ] {
+        assert!(carol::http::server::start(config, test_state()).is_err());
+    }
+}
+
+#[tokio::test]
+async fn everything_that_runs_a_guest_counts_against_its_limits() {
+    let state = test_state();
+    let state = State {
+        exec: state.exec.limit_machines(MachineLimits {
+            rate: Some(rate_limit::RateLimit {
+                per_second: 0.001,
+                burst: 2,
+            }),
+            max_concurrent: None,
+        }),
+        ..state
+    };
+    let binary = Guest {
+        module_fields: r#"
+      ;; binary-api with no activations and also ok([])
+      (data (i32.const 3000) "\00\00\00\00\00\00\00\00")
+      ;; ok("{}")
+      (data (i32.const 3100) "\00\00\00\00\80\0c\00\00\02\00\00\00")
+      (data (i32.const 3200) "{}")"#,
+        get_binary_api: "i32.const 3000",
+        params_to_json: "i32.const 3100",
+        params_from_json: "i32.const 3000",
+        ..Default::default()
+    }
+    .build();
+    let binary_id = BinaryId::new(&binary);
+    let machine_id = insert_machine(&state.exec, &binary, vec![]);
+    let addr = start_server(HttpServerConfig::default(), state);
+    let status = |method: Method,
+                  path: String,
+                  headers: &'static [(&'static str, &'static str)]| async move {
+        request(addr, method, &path, headers, b"{}".to_vec())
+            .await
+            .0
+    };
+
+    // looking at the machine only runs it the first time
+    for _ in 0..3 {
+        assert_eq!(
+            status(Method::GET, format!("/machines/{machine_id}"), &[]).await,
+            StatusCode::OK
+        );
+    }
+    drop(EventStream::open(addr, machine_id, None).await);
+    assert_eq!(
+        EventStream::request(addr, machine_id, None).await.status(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // the binary has limits of its own
+    assert_eq!(
+        status(Method::GET, format!("/binaries/{binary_id}"), &[]).await,
+        StatusCode::OK
+    );
+    let json = &[("content-type", "application/json")];
+    // the params convert to the same empty ones the machine was created with
+    assert_eq!(
+        status(Method::POST, format!("/binaries/{binary_id}"), json).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        status(Method::POST, format!("/binaries/{binary_id}"), json).await,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[tokio::test]
+async fn rate_limits_must_refill() {
+    for per_second in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        let config = HttpServerConfig {
+            rate_limits: RateLimitConfig {
+                per_client: Some(RateLimit {
+                    per_second,
+                    burst: 10,
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(carol::http::server::start(config, test_state()).is_err());
+    }
+}
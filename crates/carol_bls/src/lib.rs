@@ -8,7 +8,10 @@ use bls12_381::{
     hash_to_curve::{ExpandMsgXmd, HashToCurve},
     G1Affine, G2Affine, G2Projective, Scalar,
 };
-use carol_core::{impl_display_debug_serialize, impl_fromstr_deserialize, MachineId};
+use carol_core::{
+    impl_display_debug_serialize, impl_fromstr_deserialize, transcript::Transcript, MachineId,
+};
+use sha2::Digest;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct KeyPair {
@@ -36,6 +39,21 @@ impl KeyPair {
     pub fn secret_key(&self) -> bls12_381::Scalar {
         self.sk
     }
+
+    /// Deterministically derives the keypair a particular machine signs with from the node's root
+    /// keypair.
+    ///
+    /// Knowing a derived secret key reveals nothing about the root secret key or the keys of other
+    /// machines.
+    pub fn derive_for_machine(&self, machine_id: MachineId) -> KeyPair {
+        let mut hash = sha2::Sha512::default();
+        hash.update(b"carol/bls/machine-key");
+        hash.update(self.sk.to_bytes());
+        hash.update(machine_id.as_ref());
+        let mut bytes = [0u8; 64];
+        bytes.copy_from_slice(&hash.finalize()[..]);
+        Self::new(Scalar::from_bytes_wide(&bytes))
+    }
 }
 
 #[derive(Clone, Copy, Eq, PartialEq)]
@@ -71,16 +89,25 @@ impl_fromstr_deserialize! {
     }
 }
 
+/// The domain separation tag for transcript signatures.
+///
+/// Machines can sign anything under their own id with the node's key so this must never be a
+/// valid machine id. Machine ids are 32 bytes long so this can't be.
+const TRANSCRIPT_DST: &[u8] = b"carol/activation-transcript";
+
 pub fn sign(keypair: KeyPair, machine_id: MachineId, message: &[u8]) -> Signature {
-    let message_point = hash_to_curve(machine_id, message);
+    let message_point = hash_to_curve(machine_id.as_ref(), message);
     Signature(G2Affine::from(message_point * keypair.secret_key()))
 }
 
-fn hash_to_curve(machine_id: MachineId, message: &[u8]) -> G2Projective {
-    <G2Projective as HashToCurve<ExpandMsgXmd<sha2::Sha256>>>::hash_to_curve(
-        message,
-        machine_id.as_ref(),
-    )
+/// Signs an activation transcript with the node's keypair.
+pub fn sign_transcript(keypair: KeyPair, transcript: &Transcript) -> Signature {
+    let message_point = hash_to_curve(TRANSCRIPT_DST, &transcript.to_bytes());
+    Signature(G2Affine::from(message_point * keypair.secret_key()))
+}
+
+fn hash_to_curve(dst: &[u8], message: &[u8]) -> G2Projective {
+    <G2Projective as HashToCurve<ExpandMsgXmd<sha2::Sha256>>>::hash_to_curve(message, dst)
 }
 
 #[must_use]
@@ -90,7 +117,30 @@ pub fn verify(
     signature: Signature,
     message: &[u8],
 ) -> bool {
-    let message_point = G2Affine::from(hash_to_curve(machine_id, message));
+    verify_with_dst(carol_public_key, machine_id.as_ref(), signature, message)
+}
+
+#[must_use]
+pub fn verify_transcript(
+    carol_public_key: PublicKey,
+    signature: Signature,
+    transcript: &Transcript,
+) -> bool {
+    verify_with_dst(
+        carol_public_key,
+        TRANSCRIPT_DST,
+        signature,
+        &transcript.to_bytes(),
+    )
+}
+
+fn verify_with_dst(
+    carol_public_key: PublicKey,
+    dst: &[u8],
+    signature: Signature,
+    message: &[u8],
+) -> bool {
+    let message_point = G2Affine::from(hash_to_curve(dst, message));
     bls12_381::pairing(&carol_public_key.0, &message_point)
         == bls12_381::pairing(&G1Affine::generator(), &signature.0)
 }
@@ -124,5 +174,50 @@ mod test {
             prop_assert!(verify(kp.public_key(), machine_id, signature, &message))
 
         }
+
+        #[test]
+        fn derived_machine_keys(sk in any::<[u8;64]>(), message in any::<[u8;32]>(), machine_id in any::<[u8;32]>(), other_machine_id in any::<[u8;32]>()) {
+            prop_assume!(machine_id != other_machine_id);
+            let kp = KeyPair::new(Scalar::from_bytes_wide(&sk));
+            let machine_id = MachineId::from_bytes(machine_id);
+            let other_machine_id = MachineId::from_bytes(other_machine_id);
+
+            let machine_kp = kp.derive_for_machine(machine_id);
+            prop_assert_eq!(machine_kp, kp.derive_for_machine(machine_id));
+            prop_assert_ne!(machine_kp, kp);
+            prop_assert_ne!(machine_kp, kp.derive_for_machine(other_machine_id));
+
+            let signature = sign(machine_kp, machine_id, &message[..]);
+            prop_assert!(verify(machine_kp.public_key(), machine_id, signature, &message));
+            prop_assert!(!verify(kp.public_key(), machine_id, signature, &message));
+        }
+    }
+
+    proptest! {
+        // each case does six pairings
+        #![proptest_config(ProptestConfig::with_cases(32))]
+        #[test]
+        fn transcript_signatures(sk in any::<[u8;64]>(), machine_id in any::<[u8;32]>(), input in any::<Vec<u8>>(), output in any::<Vec<u8>>()) {
+            let kp = KeyPair::new(Scalar::from_bytes_wide(&sk));
+            let machine_id = MachineId::from_bytes(machine_id);
+            let transcript = Transcript {
+                machine_id,
+                activation: "attest".into(),
+                input: input.into(),
+                http: vec![],
+                output: output.into(),
+            };
+
+            let signature = sign_transcript(kp, &transcript);
+            prop_assert!(verify_transcript(kp.public_key(), signature, &transcript));
+
+            let mut tampered = transcript.clone();
+            tampered.output.0.push(42);
+            prop_assert!(!verify_transcript(kp.public_key(), signature, &tampered));
+
+            // a machine signing the same bytes with the node's key doesn't produce a transcript signature
+            let machine_signature = sign(kp, machine_id, &transcript.to_bytes());
+            prop_assert!(!verify_transcript(kp.public_key(), machine_signature, &transcript));
+        }
     }
 }
@@ -14,8 +14,12 @@ pub use serde;
 /// Re-export `bincode`
 pub use bincode;
 
+mod bytes;
+pub use bytes::Bytes;
 pub mod hex;
 mod macros;
+pub mod schedule;
+pub mod transcript;
 use sha2::{Digest, Sha256};
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd, Default)]
@@ -0,0 +1,310 @@
+//! When a machine wants one of its activations to be run by the node.
+//!
+//! A schedule is either a fixed interval like `@every 30s` or a five field cron expression
+//! (`minute hour day-of-month month day-of-week`) like `*/5 * * * *`. Every time is in UTC and is
+//! given as seconds since the unix epoch.
+use alloc::{string::String, vec::Vec};
+use core::{fmt, str::FromStr};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Run every this many seconds. The runs line up with multiples of the interval since the
+    /// epoch so `@every 1m` runs at the start of each minute.
+    Every(u64),
+    Cron(Cron),
+}
+
+/// A parsed cron expression. Each field is a bitmask of the values it matches.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u32,
+    days_of_month: u32,
+    months: u16,
+    days_of_week: u8,
+    /// Whether day-of-month and day-of-week were both restricted in which case a day matching
+    /// either of them matches (as in every other cron).
+    either_day: bool,
+    source: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScheduleError(String);
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid schedule: {}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ScheduleError {}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+/// How far ahead we look for a time a cron expression matches. It's long enough to find
+/// `0 0 29 2 *` (midnight on the 29th of February).
+const MAX_DAYS_AHEAD: u64 = 8 * 366;
+
+impl Schedule {
+    /// The first time strictly after `time` when the activation should run.
+    ///
+    /// `None` if it will never run (e.g. `0 0 31 2 *`).
+    pub fn next_after(&self, time: u64) -> Option<u64> {
+        match self {
+            Schedule::Every(interval) => Some((time / interval + 1) * interval),
+            Schedule::Cron(cron) => cron.next_after(time),
+        }
+    }
+}
+
+impl Cron {
+    fn next_after(&self, time: u64) -> Option<u64> {
+        let start = (time / 60 + 1) * 60;
+        let start_day = start / SECONDS_PER_DAY;
+        for day in start_day..start_day + MAX_DAYS_AHEAD {
+            if !self.matches_day(day) {
+                continue;
+            }
+            let first_minute = if day == start_day {
+                (start % SECONDS_PER_DAY) / 60
+            } else {
+                0
+            };
+            for minute_of_day in first_minute..24 * 60 {
+                let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                if self.hours & (1 << hour) != 0 && self.minutes & (1 << minute) != 0 {
+                    return Some(day * SECONDS_PER_DAY + minute_of_day * 60);
+                }
+            }
+        }
+        None
+    }
+
+    fn matches_day(&self, days_since_epoch: u64) -> bool {
+        let (_, month, day_of_month) = civil_from_days(days_since_epoch);
+        // the epoch was a Thursday
+        let day_of_week = (days_since_epoch + 4) % 7;
+        let dom = self.days_of_month & (1 << day_of_month) != 0;
+        let dow = self.days_of_week & (1 << day_of_week) != 0;
+        let day_matches = if self.either_day {
+            dom || dow
+        } else {
+            dom && dow
+        };
+        day_matches && self.months & (1 << month) != 0
+    }
+}
+
+/// Turns days since the epoch into a (year, month, day) date.
+///
+/// From Howard Hinnant's `civil_from_days`: <http://howardhinnant.github.io/date_algorithms.html>
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Parses one cron field into a bitmask of the values in `min..=max` it matches.
+///
+/// Returns whether the field was `*` too.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<(u64, bool), ScheduleError> {
+    let mut mask = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| ScheduleError(format!("invalid step in ‘{part}’")))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let parse_value = |value: &str| {
+            value
+                .parse::<u32>()
+                .ok()
+                .filter(|value| (min..=max).contains(value))
+                .ok_or_else(|| {
+                    ScheduleError(format!("‘{value}’ must be a number from {min} to {max}"))
+                })
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (parse_value(start)?, parse_value(end)?),
+                // like other crons `5/15` means from 5 to the max every 15
+                None if step > 1 => (parse_value(range)?, max),
+                None => {
+                    let value = parse_value(range)?;
+                    (value, value)
+                }
+            },
+        };
+        if start > end {
+            return Err(ScheduleError(format!("‘{range}’ is an empty range")));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok((mask, field == "*"))
+}
+
+/// Parses a duration like `90s`, `5m`, `1h` or `1d` into seconds.
+fn parse_interval(interval: &str) -> Result<u64, ScheduleError> {
+    let unit_at = interval
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| ScheduleError(format!("‘{interval}’ needs a unit (s, m, h or d)")))?;
+    let (amount, unit) = interval.split_at(unit_at);
+    let amount = amount
+        .parse::<u64>()
+        .map_err(|_| ScheduleError(format!("‘{interval}’ doesn't start with a number")))?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => SECONDS_PER_DAY,
+        unit => return Err(ScheduleError(format!("unknown unit ‘{unit}’"))),
+    };
+    match amount.checked_mul(unit) {
+        Some(0) | None => Err(ScheduleError(format!("‘{interval}’ is out of range"))),
+        Some(seconds) => Ok(seconds),
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = ScheduleError;
+
+    fn from_str(schedule: &str) -> Result<Self, Self::Err> {
+        let schedule = schedule.trim();
+        if let Some(interval) = schedule.strip_prefix("@every ") {
+            return Ok(Schedule::Every(parse_interval(interval.trim())?));
+        }
+        let expression = match schedule {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" => "0 0 1 1 *",
+            schedule => schedule,
+        };
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(ScheduleError(format!(
+                "‘{schedule}’ should be ‘@every <interval>’ or a cron expression with five fields"
+            )));
+        }
+        let (minutes, _) = parse_field(fields[0], 0, 59)?;
+        let (hours, _) = parse_field(fields[1], 0, 23)?;
+        let (days_of_month, any_day_of_month) = parse_field(fields[2], 1, 31)?;
+        let (months, _) = parse_field(fields[3], 1, 12)?;
+        let (mut days_of_week, any_day_of_week) = parse_field(fields[4], 0, 7)?;
+        // 7 is also Sunday
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+        Ok(Schedule::Cron(Cron {
+            minutes,
+            hours: hours as u32,
+            days_of_month: days_of_month as u32,
+            months: months as u16,
+            days_of_week: (days_of_week & 0x7f) as u8,
+            either_day: !any_day_of_month && !any_day_of_week,
+            source: schedule.into(),
+        }))
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schedule::Every(seconds) => write!(f, "@every {seconds}s"),
+            Schedule::Cron(cron) => write!(f, "{}", cron.source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 2023-06-01T00:00:00Z, a Thursday
+    const JUNE_1: u64 = 1_685_577_600;
+
+    fn next(schedule: &str, time: u64) -> Option<u64> {
+        Schedule::from_str(schedule).unwrap().next_after(time)
+    }
+
+    #[test]
+    fn intervals() {
+        assert_eq!(next("@every 1m", JUNE_1), Some(JUNE_1 + 60));
+        assert_eq!(next("@every 1m", JUNE_1 + 59), Some(JUNE_1 + 60));
+        assert_eq!(next("@every 90s", 100), Some(180));
+        assert_eq!(next("@every 1d", JUNE_1 - 1), Some(JUNE_1));
+        assert!(Schedule::from_str("@every 0s").is_err());
+        assert!(Schedule::from_str("@every 10").is_err());
+        assert!(Schedule::from_str("@every 10y").is_err());
+    }
+
+    #[test]
+    fn cron() {
+        assert_eq!(next("* * * * *", JUNE_1), Some(JUNE_1 + 60));
+        assert_eq!(next("*/15 * * * *", JUNE_1 + 60), Some(JUNE_1 + 15 * 60));
+        assert_eq!(
+            next("30 9 * * *", JUNE_1),
+            Some(JUNE_1 + 9 * 3600 + 30 * 60)
+        );
+        assert_eq!(
+            next("0 9-17/4 * * *", JUNE_1 + 10 * 3600),
+            Some(JUNE_1 + 13 * 3600)
+        );
+        // next Monday is the 5th
+        assert_eq!(
+            next("0 0 * * 1", JUNE_1),
+            Some(JUNE_1 + 4 * SECONDS_PER_DAY)
+        );
+        // Sunday can be 0 or 7
+        assert_eq!(
+            next("0 0 * * 7", JUNE_1),
+            Some(JUNE_1 + 3 * SECONDS_PER_DAY)
+        );
+        // day of month or day of week
+        assert_eq!(
+            next("0 0 3 * 1", JUNE_1),
+            Some(JUNE_1 + 2 * SECONDS_PER_DAY)
+        );
+        assert_eq!(
+            next("@monthly", JUNE_1),
+            Some(JUNE_1 + 30 * SECONDS_PER_DAY)
+        );
+        // 2024-02-29T00:00:00Z
+        assert_eq!(next("0 0 29 2 *", JUNE_1), Some(1_709_164_800));
+        assert_eq!(next("0 0 31 2 *", JUNE_1), None);
+    }
+
+    #[test]
+    fn invalid_cron() {
+        for schedule in [
+            "",
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(Schedule::from_str(schedule).is_err(), "{schedule}");
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! A record of what went into and came out of an activation.
+//!
+//! A transcript lets anyone check which upstream HTTP responses a machine based its output on
+//! after the fact. The node signs the [bincode] encoding of the transcript (with the standard
+//! config) so it can't be changed afterwards.
+use crate::{Bytes, MachineId};
+use alloc::{string::String, vec::Vec};
+
+#[derive(
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    bincode::Encode,
+    bincode::Decode,
+)]
+pub struct Transcript {
+    pub machine_id: MachineId,
+    /// The name of the activation method that was called.
+    pub activation: String,
+    pub input: Bytes,
+    /// Every HTTP request the machine made in the order it made them.
+    pub http: Vec<HttpExchange>,
+    pub output: Bytes,
+}
+
+impl Transcript {
+    /// The bytes the node signs.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::encode_to_vec(self, bincode::config::standard())
+            .expect("encoding to a vec can't fail")
+    }
+}
+
+/// An outbound HTTP request made by a machine and what it got back.
+#[derive(
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    bincode::Encode,
+    bincode::Decode,
+)]
+pub struct HttpExchange {
+    pub request: HttpRequest,
+    /// The response or why the request failed.
+    pub response: Result<HttpResponse, String>,
+}
+
+#[derive(
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    bincode::Encode,
+    bincode::Decode,
+)]
+pub struct HttpRequest {
+    pub method: String,
+    pub uri: String,
+    /// The values of `Authorization`, `Proxy-Authorization` and `Cookie` are replaced with
+    /// `[redacted]` so the machine's credentials aren't given away.
+    pub headers: Vec<(String, Bytes)>,
+    pub body: Bytes,
+}
+
+#[derive(
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    bincode::Encode,
+    bincode::Decode,
+)]
+pub struct HttpResponse {
+    pub status: u16,
+    /// The values of `Set-Cookie` are replaced with `[redacted]`.
+    pub headers: Vec<(String, Bytes)>,
+    pub body: Bytes,
+}
@@ -3,4 +3,8 @@ pub use carol_bls::*;
 pub trait Cap {
     fn bls_static_public_key(&self) -> carol_bls::PublicKey;
     fn bls_static_sign(&self, message: &[u8]) -> carol_bls::Signature;
+    /// The public key the node derived specifically for this machine.
+    fn bls_machine_public_key(&self) -> carol_bls::PublicKey;
+    /// Sign with the key the node derived specifically for this machine.
+    fn bls_machine_sign(&self, message: &[u8]) -> carol_bls::Signature;
 }
@@ -0,0 +1,9 @@
+use core::time::Duration;
+
+pub trait Cap {
+    /// The host's wall-clock time as a duration since the unix epoch.
+    fn clock_now(&self) -> Duration;
+    /// A clock that never goes backwards. Only useful for measuring how much time has elapsed
+    /// between two readings.
+    fn clock_monotonic_now(&self) -> Duration;
+}
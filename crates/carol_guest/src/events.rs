@@ -0,0 +1,5 @@
+/// Publishing events to the machine's append-only log which anyone can read from the host.
+pub trait Cap {
+    /// Appends an event under `topic` to the machine's log and returns its sequence number.
+    fn events_publish(&self, topic: &str, data: &[u8]) -> u64;
+}
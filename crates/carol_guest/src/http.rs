@@ -15,6 +15,43 @@ pub trait Cap {
     }
 }
 
+/// The headers of a request to a machine's HTTP handler.
+///
+/// An `#[activate(http(...))]` method receives these by marking an argument of this type with
+/// `#[http_headers]`. The argument isn't read from the query string or body. Header names are
+/// compared case-insensitively.
+#[derive(Clone, Debug, Default, PartialEq, Eq, bincode::Encode, bincode::Decode)]
+pub struct Headers(pub Vec<(String, Vec<u8>)>);
+
+impl Headers {
+    /// The value of the first header called `name`.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.0
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// The value of the first header called `name` if it is valid UTF-8.
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        core::str::from_utf8(self.get(name)?).ok()
+    }
+
+    /// The values of every header called `name` in the order they appeared in the request.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.0
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_slice())
+    }
+}
+
+impl From<Vec<(String, Vec<u8>)>> for Headers {
+    fn from(headers: Vec<(String, Vec<u8>)>) -> Self {
+        Headers(headers)
+    }
+}
+
 impl From<Method> for http_crate::Method {
     fn from(value: Method) -> Self {
         use Method::*;
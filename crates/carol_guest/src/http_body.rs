@@ -0,0 +1,73 @@
+//! Streaming the bodies of the request an HTTP handler is handling and of its response.
+//!
+//! The host doesn't put the request body in the [`Request`](crate::http::Request) passed to the
+//! handler so it has to be read from here. `#[machine]` does this for `#[activate(http(...))]`
+//! methods.
+use crate::bind::carol::machine::http_body;
+use alloc::{string::String, vec::Vec};
+
+pub trait Cap {
+    /// The next chunk of the request body. Empty once the whole body has been read.
+    fn http_body_read(&self) -> Result<Vec<u8>, Error>;
+    /// Sends the response status and headers right away so the body can be streamed with
+    /// [`http_body_write`](Cap::http_body_write).
+    ///
+    /// After this the status and headers of the response returned from the handler are ignored
+    /// and its body is sent after everything written.
+    fn http_body_start_response(&self, status: u16, headers: &[(String, Vec<u8>)]);
+    /// Sends the next chunk of the response body. Waits until the client has made room for it.
+    ///
+    /// Starts the response with status 200 and no headers if it hasn't been started.
+    fn http_body_write(&self, chunk: &[u8]) -> Result<(), Error>;
+
+    /// Reads the rest of the request body onto the end of `body`.
+    fn http_body_read_to_end(&self, body: &mut Vec<u8>) -> Result<(), Error> {
+        loop {
+            let chunk = self.http_body_read()?;
+            if chunk.is_empty() {
+                return Ok(());
+            }
+            body.extend_from_slice(&chunk);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The request body is larger than the host allows.
+    TooLarge { max: u64 },
+    /// The client went away or the body couldn't be read.
+    Closed(String),
+}
+
+impl Error {
+    /// The status code to respond with when reading the request body fails.
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::TooLarge { .. } => 413,
+            Error::Closed(_) => 400,
+        }
+    }
+}
+
+impl From<http_body::Error> for Error {
+    fn from(value: http_body::Error) -> Self {
+        match value {
+            http_body::Error::TooLarge(max) => Error::TooLarge { max },
+            http_body::Error::Closed(reason) => Error::Closed(reason),
+        }
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::TooLarge { max } => {
+                write!(f, "request body is larger than the limit of {} bytes", max)
+            }
+            Error::Closed(reason) => write!(f, "HTTP body stream closed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
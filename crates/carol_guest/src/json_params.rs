@@ -0,0 +1,63 @@
+//! Converting machine parameters to and from JSON for the `params-from-json` and `params-to-json`
+//! exports that [`machine`](crate::machine) generates.
+//!
+//! Parameters can only be written as JSON if the machine type is serde (de)serializable as well as
+//! bincode encodable (e.g. it is a [`codec`](crate::codec)). `#[machine]` can't see how the type
+//! was declared so it calls the conversions through [`JsonParams`] which picks [`Codec`] for types
+//! that support it and falls back to [`NoCodec`] otherwise.
+use alloc::{format, string::String, vec::Vec};
+use core::marker::PhantomData;
+
+/// Stands in for a machine type `T` when converting its parameters.
+///
+/// Call the conversions on a `&JsonParams<T>` with both [`Codec`] and [`NoCodec`] in scope.
+pub struct JsonParams<T>(PhantomData<T>);
+
+impl<T> JsonParams<T> {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+/// The conversions for machine types that can go to and from JSON.
+pub trait Codec {
+    fn params_from_json(&self, json: &str) -> Result<Vec<u8>, String>;
+    fn params_to_json(&self, params: &[u8]) -> Result<String, String>;
+}
+
+impl<T> Codec for JsonParams<T>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned + bincode::Encode + bincode::Decode,
+{
+    fn params_from_json(&self, json: &str) -> Result<Vec<u8>, String> {
+        let params = serde_json::from_str::<T>(json)
+            .map_err(|e| format!("invalid machine parameters: {e}"))?;
+        bincode::encode_to_vec(params, bincode::config::standard())
+            .map_err(|e| format!("encoding machine parameters: {e}"))
+    }
+
+    fn params_to_json(&self, params: &[u8]) -> Result<String, String> {
+        let (params, _) = bincode::decode_from_slice::<T, _>(params, bincode::config::standard())
+            .map_err(|e| format!("decoding machine parameters: {e}"))?;
+        serde_json::to_string(&params).map_err(|e| format!("machine parameters aren't JSON: {e}"))
+    }
+}
+
+/// The fallback for machine types that can't go to and from JSON.
+pub trait NoCodec {
+    fn params_from_json(&self, json: &str) -> Result<Vec<u8>, String>;
+    fn params_to_json(&self, params: &[u8]) -> Result<String, String>;
+}
+
+impl<T> NoCodec for &JsonParams<T> {
+    fn params_from_json(&self, _json: &str) -> Result<Vec<u8>, String> {
+        Err(UNSUPPORTED.into())
+    }
+
+    fn params_to_json(&self, _params: &[u8]) -> Result<String, String> {
+        Err(UNSUPPORTED.into())
+    }
+}
+
+const UNSUPPORTED: &str = "this machine's parameters can't be written as JSON";
@@ -21,9 +21,17 @@ pub mod bind {
 
 pub mod bls;
 mod client;
+pub mod clock;
+pub mod events;
 pub mod http;
+pub mod http_body;
+pub mod json_params;
 pub mod log;
 pub mod machines;
+pub mod oracle;
+pub mod random;
+pub mod schnorr;
+pub mod state;
 pub use client::*;
 
 #[cfg(target_arch = "wasm32")]
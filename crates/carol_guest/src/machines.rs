@@ -3,6 +3,44 @@ use carol_core::MachineId;
 
 pub trait Cap {
     fn self_activate(&self, method: &str, input: &[u8]) -> Result<Vec<u8>, Error>;
+    /// Activate `method` on any machine on the same host.
+    ///
+    /// To call the methods of another machine with types use the `client::Client` generated by
+    /// `#[machine]` which works with anything that implements this trait.
+    fn activate_machine(
+        &self,
+        machine_id: MachineId,
+        method: &str,
+        input: &[u8],
+    ) -> Result<Vec<u8>, Error>;
+}
+
+impl<C: Cap + ?Sized> Cap for &C {
+    fn self_activate(&self, method: &str, input: &[u8]) -> Result<Vec<u8>, Error> {
+        (**self).self_activate(method, input)
+    }
+
+    fn activate_machine(
+        &self,
+        machine_id: MachineId,
+        method: &str,
+        input: &[u8],
+    ) -> Result<Vec<u8>, Error> {
+        (**self).activate_machine(machine_id, method, input)
+    }
+}
+
+impl<C: Cap + ?Sized> crate::Client for C {
+    type Error = ClientError;
+
+    fn activate(
+        &self,
+        machine_id: MachineId,
+        method_name: &str,
+        input: &[u8],
+    ) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.activate_machine(machine_id, method_name, input)?)
+    }
 }
 
 #[derive(
@@ -20,6 +58,9 @@ pub trait Cap {
 )]
 pub enum Error {
     Panic { reason: String, machine: MachineId },
+    NotFound { machine: Vec<u8> },
+    DepthLimitExceeded { max_depth: u32 },
+    Busy { machine: MachineId },
 }
 
 impl From<machines::Error> for Error {
@@ -29,6 +70,13 @@ impl From<machines::Error> for Error {
                 reason,
                 machine: MachineId::from_slice(&machine[..]).unwrap(),
             },
+            machines::Error::NotFound(machine) => Error::NotFound { machine },
+            machines::Error::DepthLimitExceeded(max_depth) => {
+                Error::DepthLimitExceeded { max_depth }
+            }
+            machines::Error::Busy(machine) => Error::Busy {
+                machine: MachineId::from_slice(&machine[..]).unwrap(),
+            },
         }
     }
 }
@@ -41,8 +89,61 @@ impl core::fmt::Display for Error {
                 "call to machine {} failed because it panicked: {}",
                 machine, reason
             ),
+            Error::NotFound { machine } => write!(
+                f,
+                "there is no machine {}",
+                carol_core::hex::encode(machine)
+            ),
+            Error::DepthLimitExceeded { max_depth } => write!(
+                f,
+                "activations can't be nested more than {} deep",
+                max_depth
+            ),
+            Error::Busy { machine } => write!(
+                f,
+                "machine {} is too busy to be activated right now",
+                machine
+            ),
         }
     }
 }
 
 impl std::error::Error for Error {}
+
+/// The error returned when using a `#[machine]` generated `client::Client` through [`Cap`].
+#[derive(Debug)]
+pub enum ClientError {
+    Activate(Error),
+    Encode(bincode::error::EncodeError),
+    Decode(bincode::error::DecodeError),
+}
+
+impl From<Error> for ClientError {
+    fn from(value: Error) -> Self {
+        ClientError::Activate(value)
+    }
+}
+
+impl From<bincode::error::EncodeError> for ClientError {
+    fn from(value: bincode::error::EncodeError) -> Self {
+        ClientError::Encode(value)
+    }
+}
+
+impl From<bincode::error::DecodeError> for ClientError {
+    fn from(value: bincode::error::DecodeError) -> Self {
+        ClientError::Decode(value)
+    }
+}
+
+impl core::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Activate(e) => e.fmt(f),
+            ClientError::Encode(e) => write!(f, "failed to encode activation input: {}", e),
+            ClientError::Decode(e) => write!(f, "failed to decode activation output: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
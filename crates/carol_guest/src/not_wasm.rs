@@ -1,5 +1,12 @@
 use super::*;
 use carol_core::{BinaryId, MachineId};
+use rand_chacha::{
+    rand_core::{RngCore, SeedableRng},
+    ChaCha20Rng,
+};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 pub struct ActivateCap;
 
 impl http::Cap for ActivateCap {
@@ -16,6 +23,38 @@ impl bls::Cap for ActivateCap {
     fn bls_static_sign(&self, _message: &[u8]) -> bls::Signature {
         panic!("cannot call activate outside of WASM guest environment")
     }
+
+    fn bls_machine_public_key(&self) -> bls::PublicKey {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+
+    fn bls_machine_sign(&self, _message: &[u8]) -> bls::Signature {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+}
+
+impl schnorr::Cap for ActivateCap {
+    fn schnorr_public_key(&self) -> schnorr::PublicKey {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+
+    fn schnorr_sign(&self, _message: &[u8]) -> schnorr::Signature {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+}
+
+impl oracle::Cap for ActivateCap {
+    fn oracle_announce_nonce(&self, _event_id: &[u8]) -> Result<schnorr::Nonce, oracle::Error> {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+
+    fn oracle_attest(
+        &self,
+        _event_id: &[u8],
+        _outcome: &[u8],
+    ) -> Result<schnorr::Signature, oracle::Error> {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
 }
 
 impl log::Cap for ActivateCap {
@@ -28,11 +67,68 @@ impl machines::Cap for ActivateCap {
     fn self_activate(&self, _method_name: &str, _input: &[u8]) -> Result<Vec<u8>, machines::Error> {
         panic!("cannot call activate outside of WASM guest environment")
     }
+
+    fn activate_machine(
+        &self,
+        _machine_id: MachineId,
+        _method: &str,
+        _input: &[u8],
+    ) -> Result<Vec<u8>, machines::Error> {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+}
+
+impl clock::Cap for ActivateCap {
+    fn clock_now(&self) -> Duration {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+
+    fn clock_monotonic_now(&self) -> Duration {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+}
+
+impl random::Cap for ActivateCap {
+    fn random_fill(&self, _dest: &mut [u8]) {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+}
+
+impl state::Cap for ActivateCap {
+    fn state_get(&self, _key: &[u8]) -> Option<Vec<u8>> {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+
+    fn state_set(&self, _key: &[u8], _value: &[u8]) {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+
+    fn state_delete(&self, _key: &[u8]) {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+
+    fn state_list_prefix(&self, _prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
+}
+
+impl events::Cap for ActivateCap {
+    fn events_publish(&self, _topic: &str, _data: &[u8]) -> u64 {
+        panic!("cannot call activate outside of WASM guest environment")
+    }
 }
 
 pub struct TestCap {
     http_client: reqwest::blocking::Client,
     bls_keypair: carol_bls::KeyPair,
+    schnorr_keypair: carol_schnorr::KeyPair,
+    state: Mutex<BTreeMap<Vec<u8>, Vec<u8>>>,
+    attestations: Mutex<BTreeMap<Vec<u8>, Vec<u8>>>,
+    events: Mutex<Vec<(String, Vec<u8>)>>,
+    rng: Mutex<ChaCha20Rng>,
+    /// When set [`clock::Cap`] reports this instead of the system time.
+    fake_now: Mutex<Option<Duration>>,
+    created: Instant,
 }
 
 impl Default for TestCap {
@@ -41,7 +137,14 @@ impl Default for TestCap {
             bls_keypair: carol_bls::KeyPair::new(carol_bls::bls12_381::Scalar::from_bytes_wide(
                 &[42u8; 64],
             )),
+            schnorr_keypair: carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
             http_client: Default::default(),
+            state: Default::default(),
+            attestations: Default::default(),
+            events: Default::default(),
+            rng: Mutex::new(ChaCha20Rng::from_seed([42u8; 32])),
+            fake_now: Default::default(),
+            created: Instant::now(),
         }
     }
 }
@@ -50,9 +153,45 @@ impl TestCap {
     pub fn new(bls_keypair: carol_bls::KeyPair) -> Self {
         Self {
             bls_keypair,
+            schnorr_keypair: carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
             http_client: reqwest::blocking::Client::default(),
+            state: Default::default(),
+            attestations: Default::default(),
+            events: Default::default(),
+            rng: Mutex::new(ChaCha20Rng::from_seed([42u8; 32])),
+            fake_now: Default::default(),
+            created: Instant::now(),
         }
     }
+
+    /// Makes [`clock::Cap`] report `now` (since the unix epoch) instead of the system time until
+    /// it is changed again.
+    pub fn set_clock(&self, now: Duration) {
+        *self.fake_now.lock().unwrap() = Some(now);
+    }
+
+    /// Moves the clock forward by `by`. If the clock hasn't been set it starts from the current
+    /// system time.
+    pub fn advance_clock(&self, by: Duration) {
+        let mut fake_now = self.fake_now.lock().unwrap();
+        *fake_now = Some(fake_now.unwrap_or_else(system_now) + by);
+    }
+
+    /// Makes [`random::Cap`] produce the same bytes every time for a given `seed`.
+    pub fn with_random_seed(self, seed: [u8; 32]) -> Self {
+        *self.rng.lock().unwrap() = ChaCha20Rng::from_seed(seed);
+        self
+    }
+
+    /// The topic and data of every event published through [`events::Cap`] in order.
+    pub fn published_events(&self) -> Vec<(String, Vec<u8>)> {
+        self.events.lock().unwrap().clone()
+    }
+
+    pub fn with_schnorr_keypair(mut self, schnorr_keypair: carol_schnorr::KeyPair) -> Self {
+        self.schnorr_keypair = schnorr_keypair;
+        self
+    }
 }
 
 impl http::Cap for TestCap {
@@ -91,6 +230,94 @@ impl bls::Cap for TestCap {
         let machine_id = MachineId::new(BinaryId::new(b"test"), &[]);
         carol_bls::sign(self.bls_keypair, machine_id, message)
     }
+
+    fn bls_machine_public_key(&self) -> bls::PublicKey {
+        let machine_id = MachineId::new(BinaryId::new(b"test"), &[]);
+        self.bls_keypair.derive_for_machine(machine_id).public_key()
+    }
+
+    fn bls_machine_sign(&self, message: &[u8]) -> bls::Signature {
+        let machine_id = MachineId::new(BinaryId::new(b"test"), &[]);
+        carol_bls::sign(
+            self.bls_keypair.derive_for_machine(machine_id),
+            machine_id,
+            message,
+        )
+    }
+}
+
+impl schnorr::Cap for TestCap {
+    fn schnorr_public_key(&self) -> schnorr::PublicKey {
+        let machine_id = MachineId::new(BinaryId::new(b"test"), &[]);
+        self.schnorr_keypair
+            .derive_for_machine(machine_id)
+            .public_key()
+    }
+
+    fn schnorr_sign(&self, message: &[u8]) -> schnorr::Signature {
+        let machine_id = MachineId::new(BinaryId::new(b"test"), &[]);
+        carol_schnorr::sign(
+            &self.schnorr_keypair.derive_for_machine(machine_id),
+            message,
+        )
+    }
+}
+
+impl oracle::Cap for TestCap {
+    fn oracle_announce_nonce(&self, event_id: &[u8]) -> Result<schnorr::Nonce, oracle::Error> {
+        let machine_id = MachineId::new(BinaryId::new(b"test"), &[]);
+        Ok(self
+            .schnorr_keypair
+            .derive_for_machine(machine_id)
+            .event_nonce(event_id))
+    }
+
+    fn oracle_attest(
+        &self,
+        event_id: &[u8],
+        outcome: &[u8],
+    ) -> Result<schnorr::Signature, oracle::Error> {
+        let machine_id = MachineId::new(BinaryId::new(b"test"), &[]);
+        let mut attestations = self.attestations.lock().unwrap();
+        let existing = attestations
+            .entry(event_id.to_vec())
+            .or_insert_with(|| outcome.to_vec());
+        if existing != outcome {
+            return Err(oracle::Error::AlreadyAttested {
+                outcome: existing.clone(),
+            });
+        }
+        Ok(carol_schnorr::sign_with_event_nonce(
+            &self.schnorr_keypair.derive_for_machine(machine_id),
+            event_id,
+            outcome,
+        ))
+    }
+}
+
+fn system_now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is after the unix epoch")
+}
+
+impl clock::Cap for TestCap {
+    fn clock_now(&self) -> Duration {
+        self.fake_now.lock().unwrap().unwrap_or_else(system_now)
+    }
+
+    fn clock_monotonic_now(&self) -> Duration {
+        match *self.fake_now.lock().unwrap() {
+            Some(fake_now) => fake_now,
+            None => self.created.elapsed(),
+        }
+    }
+}
+
+impl random::Cap for TestCap {
+    fn random_fill(&self, dest: &mut [u8]) {
+        self.rng.lock().unwrap().fill_bytes(dest)
+    }
 }
 
 impl log::Cap for TestCap {
@@ -99,10 +326,109 @@ impl log::Cap for TestCap {
     }
 }
 
+impl state::Cap for TestCap {
+    fn state_get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().get(key).cloned()
+    }
+
+    fn state_set(&self, key: &[u8], value: &[u8]) {
+        self.state
+            .lock()
+            .unwrap()
+            .insert(key.to_vec(), value.to_vec());
+    }
+
+    fn state_delete(&self, key: &[u8]) {
+        self.state.lock().unwrap().remove(key);
+    }
+
+    fn state_list_prefix(&self, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.state
+            .lock()
+            .unwrap()
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+impl events::Cap for TestCap {
+    fn events_publish(&self, topic: &str, data: &[u8]) -> u64 {
+        let mut events = self.events.lock().unwrap();
+        events.push((topic.into(), data.to_vec()));
+        events.len() as u64 - 1
+    }
+}
+
 pub struct HttpHandlerCap;
 
 impl machines::Cap for HttpHandlerCap {
     fn self_activate(&self, _method_name: &str, _input: &[u8]) -> Result<Vec<u8>, machines::Error> {
         todo!("we can't do activations outside of carol guest environments yet")
     }
+
+    fn activate_machine(
+        &self,
+        _machine_id: MachineId,
+        _method: &str,
+        _input: &[u8],
+    ) -> Result<Vec<u8>, machines::Error> {
+        panic!("activating other machines is only possible inside a carol WASM guest")
+    }
+}
+
+impl clock::Cap for HttpHandlerCap {
+    fn clock_now(&self) -> Duration {
+        panic!("the clock is only available inside a carol WASM guest")
+    }
+
+    fn clock_monotonic_now(&self) -> Duration {
+        panic!("the clock is only available inside a carol WASM guest")
+    }
+}
+
+impl random::Cap for HttpHandlerCap {
+    fn random_fill(&self, _dest: &mut [u8]) {
+        panic!("randomness is only available inside a carol WASM guest")
+    }
+}
+
+impl state::Cap for HttpHandlerCap {
+    fn state_get(&self, _key: &[u8]) -> Option<Vec<u8>> {
+        panic!("machine state is only available inside a carol WASM guest")
+    }
+
+    fn state_set(&self, _key: &[u8], _value: &[u8]) {
+        panic!("machine state is only available inside a carol WASM guest")
+    }
+
+    fn state_delete(&self, _key: &[u8]) {
+        panic!("machine state is only available inside a carol WASM guest")
+    }
+
+    fn state_list_prefix(&self, _prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        panic!("machine state is only available inside a carol WASM guest")
+    }
+}
+
+impl events::Cap for HttpHandlerCap {
+    fn events_publish(&self, _topic: &str, _data: &[u8]) -> u64 {
+        panic!("publishing events is only possible inside a carol WASM guest")
+    }
+}
+
+impl http_body::Cap for HttpHandlerCap {
+    fn http_body_read(&self) -> Result<Vec<u8>, http_body::Error> {
+        // outside of a guest the whole body is passed in the request
+        Ok(vec![])
+    }
+
+    fn http_body_start_response(&self, _status: u16, _headers: &[(String, Vec<u8>)]) {
+        panic!("streaming responses is only possible inside a carol WASM guest")
+    }
+
+    fn http_body_write(&self, _chunk: &[u8]) -> Result<(), http_body::Error> {
+        panic!("streaming responses is only possible inside a carol WASM guest")
+    }
 }
@@ -0,0 +1,65 @@
+use crate::bind::carol::machine::oracle;
+use crate::schnorr;
+
+pub trait Cap {
+    /// The nonce [`Cap::oracle_attest`] will use to sign the outcome of `event_id`.
+    ///
+    /// Announce this before the event so others can anticipate the signature on each outcome.
+    ///
+    /// Fails if the node's storage isn't durable (see [`Error::StorageNotDurable`]).
+    fn oracle_announce_nonce(&self, event_id: &[u8]) -> Result<schnorr::Nonce, Error>;
+    /// BIP340 sign `outcome` with the machine's schnorr key and the nonce announced for `event_id`.
+    ///
+    /// Fails if a different outcome has already been attested to for `event_id` or the node's
+    /// storage isn't durable.
+    fn oracle_attest(&self, event_id: &[u8], outcome: &[u8]) -> Result<schnorr::Signature, Error>;
+}
+
+#[derive(
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    serde::Deserialize,
+    bincode::Encode,
+    bincode::Decode,
+)]
+pub enum Error {
+    AlreadyAttested {
+        outcome: Vec<u8>,
+    },
+    /// The node keeps its storage in memory so it could forget which outcome it attested to and
+    /// sign a second one with the same nonce.
+    StorageNotDurable,
+}
+
+impl From<oracle::Error> for Error {
+    fn from(value: oracle::Error) -> Self {
+        match value {
+            oracle::Error::AlreadyAttested(outcome) => Error::AlreadyAttested { outcome },
+            oracle::Error::StorageNotDurable => Error::StorageNotDurable,
+        }
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::AlreadyAttested { .. } => {
+                write!(
+                    f,
+                    "a different outcome was already attested to for this event"
+                )
+            }
+            Error::StorageNotDurable => {
+                write!(f, "the node's storage isn't durable so it can't attest")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
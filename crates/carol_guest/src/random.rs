@@ -0,0 +1,10 @@
+pub trait Cap {
+    /// Fill `dest` with bytes from the host's cryptographically secure random number generator.
+    fn random_fill(&self, dest: &mut [u8]);
+
+    fn random_bytes<const N: usize>(&self) -> [u8; N] {
+        let mut bytes = [0u8; N];
+        self.random_fill(&mut bytes);
+        bytes
+    }
+}
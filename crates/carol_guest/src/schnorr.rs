@@ -0,0 +1,8 @@
+pub use carol_schnorr::*;
+
+pub trait Cap {
+    /// The x-only public key the node derived specifically for this machine.
+    fn schnorr_public_key(&self) -> carol_schnorr::PublicKey;
+    /// Create a BIP340 signature over `message` with the key the node derived for this machine.
+    fn schnorr_sign(&self, message: &[u8]) -> carol_schnorr::Signature;
+}
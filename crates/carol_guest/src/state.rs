@@ -0,0 +1,8 @@
+/// Key-value state that belongs to the machine and persists between activations.
+pub trait Cap {
+    fn state_get(&self, key: &[u8]) -> Option<Vec<u8>>;
+    fn state_set(&self, key: &[u8], value: &[u8]);
+    fn state_delete(&self, key: &[u8]);
+    /// Every key-value pair whose key starts with `prefix` in key order.
+    fn state_list_prefix(&self, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)>;
+}
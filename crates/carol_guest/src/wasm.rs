@@ -3,11 +3,25 @@ pub struct ActivateCap;
 pub struct HttpHandlerCap;
 pub use bind::__link_section;
 use bind::carol::machine;
+use carol_core::MachineId;
 
 impl machines::Cap for ActivateCap {
     fn self_activate(&self, method: &str, input: &[u8]) -> Result<Vec<u8>, machines::Error> {
         Ok(machine::machines::self_activate(method, input)?)
     }
+
+    fn activate_machine(
+        &self,
+        machine_id: MachineId,
+        method: &str,
+        input: &[u8],
+    ) -> Result<Vec<u8>, machines::Error> {
+        Ok(machine::machines::activate(
+            &machine_id.to_bytes().to_vec(),
+            method,
+            input,
+        )?)
+    }
 }
 
 impl http::Cap for ActivateCap {
@@ -33,6 +47,49 @@ impl bls::Cap for ActivateCap {
             carol_bls::bls12_381::G2Affine::from_uncompressed_unchecked(&bytes).unwrap(),
         )
     }
+
+    fn bls_machine_public_key(&self) -> bls::PublicKey {
+        let mut bytes = [0u8; 96];
+        bytes.copy_from_slice(machine::global::bls_machine_pubkey().as_ref());
+        carol_bls::PublicKey(
+            carol_bls::bls12_381::G1Affine::from_uncompressed_unchecked(&bytes).unwrap(),
+        )
+    }
+
+    fn bls_machine_sign(&self, message: &[u8]) -> carol_bls::Signature {
+        let mut bytes = [0u8; 192];
+        let sig = machine::global::bls_machine_sign(message);
+        bytes.copy_from_slice(&sig);
+        carol_bls::Signature(
+            carol_bls::bls12_381::G2Affine::from_uncompressed_unchecked(&bytes).unwrap(),
+        )
+    }
+}
+
+impl schnorr::Cap for ActivateCap {
+    fn schnorr_public_key(&self) -> schnorr::PublicKey {
+        schnorr::PublicKey::from_slice(&machine::global::schnorr_machine_pubkey()).unwrap()
+    }
+
+    fn schnorr_sign(&self, message: &[u8]) -> schnorr::Signature {
+        schnorr::Signature::from_slice(&machine::global::schnorr_machine_sign(message)).unwrap()
+    }
+}
+
+impl oracle::Cap for ActivateCap {
+    fn oracle_announce_nonce(&self, event_id: &[u8]) -> Result<schnorr::Nonce, oracle::Error> {
+        let nonce = machine::oracle::announce_nonce(event_id)?;
+        Ok(schnorr::Nonce::from_slice(&nonce).unwrap())
+    }
+
+    fn oracle_attest(
+        &self,
+        event_id: &[u8],
+        outcome: &[u8],
+    ) -> Result<schnorr::Signature, oracle::Error> {
+        let signature = machine::oracle::attest(event_id, outcome)?;
+        Ok(schnorr::Signature::from_slice(&signature).unwrap())
+    }
 }
 
 impl log::Cap for ActivateCap {
@@ -51,4 +108,141 @@ impl machines::Cap for HttpHandlerCap {
     fn self_activate(&self, method_name: &str, input: &[u8]) -> Result<Vec<u8>, machines::Error> {
         Ok(machine::machines::self_activate(method_name, input)?)
     }
+
+    fn activate_machine(
+        &self,
+        machine_id: MachineId,
+        method: &str,
+        input: &[u8],
+    ) -> Result<Vec<u8>, machines::Error> {
+        Ok(machine::machines::activate(
+            &machine_id.to_bytes().to_vec(),
+            method,
+            input,
+        )?)
+    }
+}
+
+fn clock_now() -> core::time::Duration {
+    let now = machine::clock::now();
+    core::time::Duration::new(now.seconds, now.nanoseconds)
+}
+
+fn clock_monotonic_now() -> core::time::Duration {
+    core::time::Duration::from_nanos(machine::clock::monotonic_now())
+}
+
+impl clock::Cap for ActivateCap {
+    fn clock_now(&self) -> core::time::Duration {
+        clock_now()
+    }
+
+    fn clock_monotonic_now(&self) -> core::time::Duration {
+        clock_monotonic_now()
+    }
+}
+
+impl clock::Cap for HttpHandlerCap {
+    fn clock_now(&self) -> core::time::Duration {
+        clock_now()
+    }
+
+    fn clock_monotonic_now(&self) -> core::time::Duration {
+        clock_monotonic_now()
+    }
+}
+
+/// The most bytes the host will give us in one call.
+const MAX_RANDOM_BYTES: usize = 1 << 16;
+
+fn random_fill(dest: &mut [u8]) {
+    for chunk in dest.chunks_mut(MAX_RANDOM_BYTES) {
+        chunk.copy_from_slice(&machine::random::get_random_bytes(chunk.len() as u32));
+    }
+}
+
+fn getrandom_custom(dest: &mut [u8]) -> Result<(), getrandom::Error> {
+    random_fill(dest);
+    Ok(())
+}
+
+// Makes crates that use `getrandom` (like `rand`) get their randomness from the host.
+getrandom::register_custom_getrandom!(getrandom_custom);
+
+impl random::Cap for ActivateCap {
+    fn random_fill(&self, dest: &mut [u8]) {
+        random_fill(dest)
+    }
+}
+
+impl random::Cap for HttpHandlerCap {
+    fn random_fill(&self, dest: &mut [u8]) {
+        random_fill(dest)
+    }
+}
+
+impl state::Cap for ActivateCap {
+    fn state_get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        machine::state::get(key)
+    }
+
+    fn state_set(&self, key: &[u8], value: &[u8]) {
+        machine::state::set(key, value)
+    }
+
+    fn state_delete(&self, key: &[u8]) {
+        machine::state::delete(key)
+    }
+
+    fn state_list_prefix(&self, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        machine::state::list_prefix(prefix)
+    }
+}
+
+impl state::Cap for HttpHandlerCap {
+    fn state_get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        machine::state::get(key)
+    }
+
+    fn state_set(&self, key: &[u8], value: &[u8]) {
+        machine::state::set(key, value)
+    }
+
+    fn state_delete(&self, key: &[u8]) {
+        machine::state::delete(key)
+    }
+
+    fn state_list_prefix(&self, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        machine::state::list_prefix(prefix)
+    }
+}
+
+impl events::Cap for ActivateCap {
+    fn events_publish(&self, topic: &str, data: &[u8]) -> u64 {
+        machine::events::publish(topic, data)
+    }
+}
+
+impl events::Cap for HttpHandlerCap {
+    fn events_publish(&self, topic: &str, data: &[u8]) -> u64 {
+        machine::events::publish(topic, data)
+    }
+}
+
+impl http_body::Cap for HttpHandlerCap {
+    fn http_body_read(&self) -> Result<Vec<u8>, http_body::Error> {
+        Ok(machine::http_body::read()?)
+    }
+
+    fn http_body_start_response(&self, status: u16, headers: &[(String, Vec<u8>)]) {
+        let headers = headers
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_slice()))
+            .collect::<Vec<_>>();
+        machine::http_body::start_response(status, &headers)
+    }
+
+    fn http_body_write(&self, chunk: &[u8]) -> Result<(), http_body::Error> {
+        Ok(machine::http_body::write(chunk)?)
+    }
 }
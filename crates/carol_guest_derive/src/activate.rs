@@ -12,10 +12,12 @@ pub fn activate(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
 #[derive(Default)]
 pub struct Opts {
     pub http: Option<Http>,
+    pub schedule: Option<syn::LitStr>,
 }
 
 pub enum Opt {
     Http(Http),
+    Schedule(syn::LitStr),
 }
 
 pub struct Http {
@@ -51,6 +53,9 @@ impl Parse for Opts {
                     Opt::Http(s) => {
                         opts.http = Some(s);
                     }
+                    Opt::Schedule(s) => {
+                        opts.schedule = Some(s);
+                    }
                 }
             }
         }
@@ -76,6 +81,23 @@ impl Parse for Opt {
                     "http must be followed by parentheses e.g. http(GET)",
                 ))
             }
+        } else if l.peek(kw::schedule) {
+            input.parse::<kw::schedule>()?;
+
+            if input.peek(token::Paren) {
+                let content;
+                syn::parenthesized!(content in input);
+                let schedule = content.parse::<syn::LitStr>()?;
+                if let Err(e) = schedule.value().parse::<carol_core::schedule::Schedule>() {
+                    return Err(Error::new(schedule.span(), e));
+                }
+                Ok(Opt::Schedule(schedule))
+            } else {
+                Err(Error::new(
+                    input.span(),
+                    "schedule must be followed by parentheses e.g. schedule(\"@every 1m\")",
+                ))
+            }
         } else {
             Err(l.error())
         }
@@ -110,6 +132,7 @@ impl Parse for Http {
 
 mod kw {
     syn::custom_keyword!(http);
+    syn::custom_keyword!(schedule);
 }
 
 mod http_methods {
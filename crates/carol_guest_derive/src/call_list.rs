@@ -3,7 +3,7 @@ use proc_macro2::{Ident, Literal, Span};
 use quote::{format_ident, quote, quote_spanned, ToTokens};
 use syn::{
     parse_quote, parse_quote_spanned, spanned::Spanned, token, Arm, Expr, ExprMatch, LitStr, Path,
-    ReturnType, Signature, Token,
+    ReturnType, Token,
 };
 
 use crate::activate::HttpMethod;
@@ -30,16 +30,23 @@ pub struct Activation {
     pub http_endpoint: Option<HttpEndpoint>,
     pub sig: syn::Signature,
     pub docs: Option<String>,
+    /// Arguments marked `#[http_headers]` which take the headers of the request.
+    pub header_params: Vec<Ident>,
+    /// When the host should run the activation by itself.
+    pub schedule: Option<LitStr>,
 }
 
-fn doc_params(signature: &Signature) -> Vec<(String, String)> {
+fn doc_params(activation: &Activation) -> Vec<(String, String)> {
     let mut params = vec![];
-    for fn_arg in &signature.inputs {
+    for fn_arg in &activation.sig.inputs {
         match fn_arg {
             syn::FnArg::Typed(fn_arg) => match fn_arg.pat.as_ref() {
                 syn::Pat::Ident(pat_ident) => {
                     let ident_str = pat_ident.ident.to_string();
-                    if ident_str == "_cap" || ident_str == "cap" {
+                    if ident_str == "_cap"
+                        || ident_str == "cap"
+                        || activation.header_params.contains(&pat_ident.ident)
+                    {
                         continue;
                     }
                     params.push((ident_str, fn_arg.ty.span().source_text().unwrap()));
@@ -84,9 +91,14 @@ impl ActivationList {
             .iter()
             .map(|(method_name, endpoint)| {
                 let method_name = method_name.to_string();
+                let schedule = match &endpoint.schedule {
+                    Some(schedule) => quote! { Some(#schedule.into()) },
+                    None => quote! { None },
+                };
                 parse_quote_spanned! { endpoint.sig.span() =>
                     carol_guest::bind::exports::carol::machine::guest::ActivationDescription {
                         name: #method_name.into(),
+                        schedule: #schedule,
                     }
                 }
             })
@@ -107,7 +119,7 @@ impl ActivationList {
                     p { (PreEscaped(desc_html)) }
                     h3 { "Paramters" }
                     ol {
-                        @for (name, ty) in &doc_params(&endpoint.sig) {
+                        @for (name, ty) in &doc_params(endpoint) {
                             li { (name) ": " code { (ty) } }
                         }
                     }
@@ -210,6 +222,15 @@ impl ActivationList {
                     format!("#[activate] bincode encoding input to {}", method_name);
 
                 let method_name_str = method_name.to_string();
+                let header_params = &endpoint.header_params;
+                let set_headers = if header_params.is_empty() {
+                    quote! {}
+                } else {
+                    quote! {
+                        let mut method_struct = method_struct;
+                        #(method_struct.#header_params = ::core::convert::From::from(request.headers.clone());)*
+                    }
+                };
                 let arm_body = quote! {{
                     let method_struct = #decode_code;
                     let method_struct = match method_struct {
@@ -220,6 +241,7 @@ impl ActivationList {
                             status: 400,
                         }
                     };
+                    #set_headers
 
 
                     let binary_input: Vec<u8> = carol_guest::bincode::encode_to_vec(&method_struct, carol_guest::bincode::config::standard()).expect(#bincode_encode_error);
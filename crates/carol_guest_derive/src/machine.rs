@@ -92,6 +92,7 @@ pub fn machine(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
                 }
             }
             let mut http_doc_params: Vec<(String, String)> = vec![];
+            let mut header_params: Vec<Ident> = vec![];
             let mut activate_call_args = Punctuated::new();
             activate_call_args.push(parse_quote! { &__ctx });
             for (_, fn_arg) in inputs {
@@ -117,8 +118,16 @@ pub fn machine(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
                                             attrs.push(parse_quote!(#[serde #tokens]));
                                         }
                                     }
+                                } else if attr.path.get_ident().map(|ident| ident.to_string())
+                                    == Some("http_headers".into())
+                                {
+                                    // filled in from the request rather than the query or body
+                                    if activate_opts.http.is_some() {
+                                        attrs.push(parse_quote!(#[serde(skip)]));
+                                    }
+                                    header_params.push(fn_arg_ident.clone());
                                 } else {
-                                    return quote_spanned!(attr.span() => compile_error!("only 'with_serde' is a valid function argument attribute"));
+                                    return quote_spanned!(attr.span() => compile_error!("only 'with_serde' and 'http_headers' are valid function argument attributes"));
                                 }
                             }
 
@@ -150,6 +159,12 @@ pub fn machine(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
                 };
             }
 
+            if let Some(schedule) = &activate_opts.schedule {
+                if !struct_fields.named.is_empty() {
+                    return quote_spanned!(schedule.span() => compile_error!("scheduled activations can't take any arguments other than `cap`"));
+                }
+            }
+
             let attrs = if activate_opts.http.is_some() {
                 vec![
                     parse_quote!(#[derive(carol_guest::bincode::Decode, carol_guest::bincode::Encode, carol_guest::serde::Serialize, carol_guest::serde::Deserialize, Debug, Clone)]),
@@ -203,6 +218,8 @@ pub fn machine(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
                 http_endpoint,
                 sig: method.sig.clone(),
                 docs: method_docs,
+                header_params,
+                schedule: activate_opts.schedule.clone(),
             });
 
             let input_decode_expect = format!("#[machine] bincode decoding input to {method_name}");
@@ -231,8 +248,8 @@ pub fn machine(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
                     syn::FnArg::Receiver(recv) => &mut recv.attrs,
                 };
                 attrs.retain(|attr| {
-                    attr.path.get_ident().map(|ident| ident.to_string())
-                        != Some("with_serde".into())
+                    let ident = attr.path.get_ident().map(|ident| ident.to_string());
+                    ident != Some("with_serde".into()) && ident != Some("http_headers".into())
                 })
             }
 
@@ -362,11 +379,19 @@ pub fn machine(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
                     #match_stmt
                 }
 
-                fn handle_http(request: http::Request) -> http::Response {
+                fn handle_http(mut request: http::Request) -> http::Response {
                     #[cfg(target_arch = "wasm32")]
                     set_up_panic_hook();
                     let __ctx = carol_guest::HttpHandlerCap;
 
+                    if let Err(e) = carol_guest::http_body::Cap::http_body_read_to_end(&__ctx, &mut request.body) {
+                        return http::Response {
+                            headers: vec![],
+                            body: e.to_string().into_bytes(),
+                            status: e.status(),
+                        };
+                    }
+
                     let uri = request.uri();
                     let mut __path = uri.path();
                     let __query = uri.query().unwrap_or("");
@@ -379,6 +404,20 @@ pub fn machine(input: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
                 fn get_binary_api() -> carol_guest::bind::exports::carol::machine::guest::BinaryApi {
                     #binary_api
                 }
+
+                fn params_from_json(json: String) -> Result<Vec<u8>, String> {
+                    #[cfg(target_arch = "wasm32")]
+                    set_up_panic_hook();
+                    use carol_guest::json_params::{Codec, NoCodec, JsonParams};
+                    (&JsonParams::<#self_ty>::new()).params_from_json(&json)
+                }
+
+                fn params_to_json(machine_params: Vec<u8>) -> Result<String, String> {
+                    #[cfg(target_arch = "wasm32")]
+                    set_up_panic_hook();
+                    use carol_guest::json_params::{Codec, NoCodec, JsonParams};
+                    (&JsonParams::<#self_ty>::new()).params_to_json(&machine_params)
+                }
             }
         }
 
@@ -33,6 +33,14 @@ impl Foo {
         lhs + rhs
     }
 
+    #[activate(http(GET))]
+    pub fn get_content_type(&self, _cap: &impl Any, #[http_headers] headers: http::Headers) {
+        panic!(
+            "content-type: {}",
+            headers.get_str("content-type").unwrap_or("none")
+        )
+    }
+
     #[activate]
     pub fn no_http(&self, _cap: &impl Any, _arg: NoSerde) {
         unreachable!()
@@ -85,6 +93,31 @@ fn custom_path_post() {
     });
 }
 
+// the headers argument shouldn't be expected in the query string
+#[test]
+#[should_panic]
+fn headers_param_is_not_decoded_from_query() {
+    Foo::handle_http(http::Request {
+        method: http::Method::Get,
+        uri: "/get_content_type".into(),
+        body: vec![],
+        headers: vec![("Content-Type".into(), b"application/json".to_vec())],
+    });
+}
+
+#[test]
+#[should_panic(expected = "content-type: application/json")]
+fn headers_are_passed_to_activation() {
+    let input = bincode::encode_to_vec(
+        carol_activate::GetContentType {
+            headers: vec![("Content-Type".into(), b"application/json".to_vec())].into(),
+        },
+        bincode::config::standard(),
+    )
+    .unwrap();
+    Foo::activate(vec![], "get_content_type".into(), input);
+}
+
 #[test]
 fn post_request_invalid_params() {
     let response = Foo::handle_http(http::Request {
@@ -147,4 +180,5 @@ fn root_handler() {
 
     let body = String::from_utf8(response.body).unwrap();
     assert!(body.contains("This is a <strong>foo</strong>"));
+    assert!(!body.contains("Headers"));
 }
@@ -0,0 +1,80 @@
+use carol_guest::bind::exports::carol::machine::guest::Guest;
+use core::any::Any;
+
+pub mod with_codec {
+    use super::*;
+    use carol_guest_derive::{activate, codec, machine};
+
+    #[codec]
+    #[derive(Debug, PartialEq)]
+    pub struct Counter {
+        pub name: String,
+        pub start: u32,
+    }
+
+    #[machine]
+    impl Counter {
+        #[activate]
+        pub fn start(&self, _cap: &impl Any) -> u32 {
+            self.start
+        }
+    }
+}
+
+pub mod without_codec {
+    use super::*;
+    use carol_guest_derive::{activate, machine};
+
+    #[derive(bincode::Encode, bincode::Decode)]
+    pub struct Opaque(pub u32);
+
+    #[machine]
+    impl Opaque {
+        #[activate]
+        pub fn get(&self, _cap: &impl Any) -> u32 {
+            self.0
+        }
+    }
+}
+
+use with_codec::Counter;
+use without_codec::Opaque;
+
+#[test]
+fn params_round_trip_through_json() {
+    let params = Counter::params_from_json(r#"{"name":"a","start":7}"#.into()).unwrap();
+    assert_eq!(
+        params,
+        bincode::encode_to_vec(
+            Counter {
+                name: "a".into(),
+                start: 7
+            },
+            bincode::config::standard()
+        )
+        .unwrap()
+    );
+    let (start, _): (u32, _) = bincode::decode_from_slice(
+        &Counter::activate(params.clone(), "start".into(), vec![]),
+        bincode::config::standard(),
+    )
+    .unwrap();
+    assert_eq!(start, 7);
+    assert_eq!(
+        Counter::params_to_json(params).unwrap(),
+        r#"{"name":"a","start":7}"#
+    );
+}
+
+#[test]
+fn invalid_json_params() {
+    assert!(Counter::params_from_json(r#"{"name":"a"}"#.into()).is_err());
+    assert!(Counter::params_from_json("not json".into()).is_err());
+    assert!(Counter::params_to_json(vec![0xff]).is_err());
+}
+
+#[test]
+fn json_params_need_a_codec() {
+    assert!(Opaque::params_from_json("1".into()).is_err());
+    assert!(Opaque::params_to_json(vec![1]).is_err());
+}
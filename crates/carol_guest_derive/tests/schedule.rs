@@ -0,0 +1,37 @@
+use carol_guest::bind::exports::carol::machine::guest::Guest;
+use carol_guest_derive::{activate, codec, machine};
+use core::any::Any;
+
+#[codec]
+pub struct Foo;
+
+#[machine]
+impl Foo {
+    #[activate(schedule("@every 1m"))]
+    pub fn every_minute(&self, _cap: &impl Any) -> u32 {
+        42
+    }
+
+    #[activate(http(GET), schedule("0 9 * * 1-5"))]
+    pub fn weekday_mornings(&self, _cap: &impl Any) {}
+
+    #[activate]
+    pub fn unscheduled(&self, _cap: &impl Any) {}
+}
+
+#[test]
+fn schedules_are_in_binary_api() {
+    let schedules = Foo::get_binary_api()
+        .activations
+        .into_iter()
+        .map(|activation| (activation.name, activation.schedule))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        schedules,
+        vec![
+            ("every_minute".into(), Some("@every 1m".into())),
+            ("unscheduled".into(), None),
+            ("weekday_mornings".into(), Some("0 9 * * 1-5".into())),
+        ]
+    );
+}
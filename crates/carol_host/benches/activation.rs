@@ -0,0 +1,50 @@
+//! Measures the overhead of activating a machine that does no work of its own.
+//!
+//! Run with `cargo bench -p carol_host`. When the linker was made once per executor and components
+//! were pre-instantiated it went from around 104µs per activation to 88µs with the default
+//! allocator and 43µs with the pooling allocator.
+use carol_host::{Executor, ExecutorConfig, State};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+fn activation(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    // returns a pointer to an empty list
+    let binary = common::guest_component("i32.const 2048");
+    let state = State::new(
+        carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+    );
+
+    let configs = [
+        ("default", ExecutorConfig::default()),
+        (
+            "pooling",
+            ExecutorConfig {
+                pooling_allocator: true,
+                ..Default::default()
+            },
+        ),
+    ];
+
+    let mut group = c.benchmark_group("activate_machine");
+    for (name, config) in configs {
+        let executor = Executor::with_config(config);
+        let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+        group.bench_function(name, |b| {
+            b.to_async(&rt).iter(|| async {
+                executor
+                    .activate_machine(state.clone(), &compiled_binary, &[], "noop", &[])
+                    .await
+                    .unwrap()
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, activation);
+criterion_main!(benches);
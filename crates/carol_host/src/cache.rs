@@ -0,0 +1,193 @@
+use crate::storage::write_atomic;
+use carol_core::{hex, BinaryId};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use tracing::{event, Level};
+use wasmtime::{component::Component, Engine};
+
+/// The directory under the cache root that holds a directory of components for each engine
+/// fingerprint.
+const COMPONENTS_DIR: &str = "carol-components";
+
+/// Keeps compiled components on disk so binaries don't have to be recompiled every time carol
+/// starts.
+///
+/// Components are stored at `<root>/carol-components/<engine-fingerprint>/<binary-id>.cwasm`. The
+/// fingerprint changes whenever the engine would compile the binary differently (e.g. a different
+/// wasmtime version or fuel metering being turned on) so stale components are never loaded.
+/// Directories for other fingerprints are removed when the cache is opened. Nothing else is ever
+/// removed so `root` can be shared with other things (e.g. carol's storage).
+///
+/// Failing to read or write the cache is never fatal. We just compile the binary again.
+#[derive(Clone, Debug)]
+pub struct ComponentCache {
+    dir: PathBuf,
+}
+
+impl ComponentCache {
+    pub fn open(root: &Path, engine: &Engine) -> Self {
+        let fingerprint = engine_fingerprint(engine);
+        let components_dir = root.join(COMPONENTS_DIR);
+        if let Ok(entries) = fs::read_dir(&components_dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let is_fingerprint = entry.file_name().to_str().is_some_and(|name| {
+                    name.len() == fingerprint.len() && name.bytes().all(|b| b.is_ascii_hexdigit())
+                });
+                if path.is_dir() && is_fingerprint && entry.file_name() != fingerprint.as_str() {
+                    event!(
+                        Level::INFO,
+                        dir = path.display().to_string(),
+                        "removing component cache for old engine config"
+                    );
+                    if let Err(e) = fs::remove_dir_all(&path) {
+                        event!(
+                            Level::WARN,
+                            error = e.to_string(),
+                            "failed to remove stale component cache"
+                        );
+                    }
+                }
+            }
+        }
+        Self {
+            dir: components_dir.join(fingerprint),
+        }
+    }
+
+    pub fn path(&self, binary_id: BinaryId) -> PathBuf {
+        self.dir.join(format!("{binary_id}.cwasm"))
+    }
+
+    pub fn load(&self, engine: &Engine, binary_id: BinaryId) -> Option<Component> {
+        let path = self.path(binary_id);
+        if !path.exists() {
+            return None;
+        }
+        // SAFETY: the only thing that writes to the cache directory is `Self::store` which writes
+        // the output of `Component::serialize` for an engine with the same fingerprint.
+        match unsafe { Component::deserialize_file(engine, &path) } {
+            Ok(component) => {
+                event!(
+                    Level::DEBUG,
+                    binary_id = binary_id.to_string(),
+                    "loaded compiled component from cache"
+                );
+                Some(component)
+            }
+            Err(e) => {
+                event!(
+                    Level::WARN,
+                    binary_id = binary_id.to_string(),
+                    error = e.to_string(),
+                    "ignoring unloadable cached component"
+                );
+                None
+            }
+        }
+    }
+
+    pub fn store(&self, binary_id: BinaryId, component: &Component) {
+        let result = component.serialize().and_then(|serialized| {
+            fs::create_dir_all(&self.dir)?;
+            write_atomic(&self.path(binary_id), &serialized)
+        });
+        if let Err(e) = result {
+            event!(
+                Level::WARN,
+                binary_id = binary_id.to_string(),
+                error = e.to_string(),
+                "failed to cache compiled component"
+            );
+        }
+    }
+
+    pub fn remove(&self, binary_id: BinaryId) {
+        let path = self.path(binary_id);
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => event!(
+                Level::WARN,
+                binary_id = binary_id.to_string(),
+                error = e.to_string(),
+                "failed to remove cached component"
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// A hex digest of everything about the engine that affects whether a serialized component can be
+/// loaded by it.
+fn engine_fingerprint(engine: &Engine) -> String {
+    struct Sha256Hasher(Sha256);
+
+    impl Hasher for Sha256Hasher {
+        fn finish(&self) -> u64 {
+            unreachable!("we only use the sha256 digest")
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            self.0.update(bytes)
+        }
+    }
+
+    let mut hasher = Sha256Hasher(Sha256::default());
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    hex::encode(&hasher.0.finalize()[..16])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{Executor, ExecutorConfig};
+
+    #[test]
+    fn compiled_components_are_cached_per_engine_config() {
+        let dir = tempfile::tempdir().unwrap();
+        // things the cache didn't create are left alone
+        std::fs::create_dir_all(dir.path().join("binaries")).unwrap();
+        std::fs::create_dir_all(dir.path().join(super::COMPONENTS_DIR).join("other")).unwrap();
+        let binary = wat::parse_str("(component)").unwrap();
+        let config = ExecutorConfig {
+            cache_dir: Some(dir.path().to_owned()),
+            ..Default::default()
+        };
+
+        let executor = Executor::with_config(config.clone());
+        let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+        let cached_path = executor
+            .cache
+            .as_ref()
+            .unwrap()
+            .path(compiled_binary.binary_id());
+        assert!(cached_path.exists());
+
+        // garbage in the cache is ignored and replaced
+        std::fs::write(&cached_path, b"garbage").unwrap();
+        let executor = Executor::with_config(config.clone());
+        executor.load_binary_from_wasm_binary(&binary).unwrap();
+        assert_ne!(std::fs::read(&cached_path).unwrap(), b"garbage");
+
+        // a different engine config gets its own cache and removes the old one
+        let executor = Executor::with_config(ExecutorConfig {
+            fuel_per_activation: Some(1_000),
+            ..config
+        });
+        executor.load_binary_from_wasm_binary(&binary).unwrap();
+        let new_cached_path = executor
+            .cache
+            .as_ref()
+            .unwrap()
+            .path(compiled_binary.binary_id());
+        assert_ne!(new_cached_path, cached_path);
+        assert!(new_cached_path.exists());
+        assert!(!cached_path.exists());
+        assert!(dir.path().join("binaries").exists());
+        assert!(dir
+            .path()
+            .join(super::COMPONENTS_DIR)
+            .join("other")
+            .exists());
+    }
+}
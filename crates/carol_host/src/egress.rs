@@ -0,0 +1,229 @@
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Which outbound HTTP requests guests may make and how much they may get back.
+///
+/// The default policy allows everything.
+#[derive(Clone, Debug, Default)]
+pub struct EgressPolicy {
+    /// If not empty guests may only contact these hosts. An entry also matches its subdomains.
+    pub allow_hosts: Vec<String>,
+    /// Hosts guests may never contact. An entry also matches its subdomains.
+    pub deny_hosts: Vec<String>,
+    /// Refuse to connect to loopback, private, link-local (e.g. cloud metadata services) and
+    /// other addresses that aren't on the public internet.
+    pub block_private_addresses: bool,
+    /// How large a response body may be in bytes.
+    pub max_response_bytes: Option<u64>,
+    /// How long a single request may take including reading the response body.
+    pub request_timeout: Option<Duration>,
+    /// How many requests a guest may make in a single activation.
+    pub max_requests_per_activation: Option<u32>,
+}
+
+impl EgressPolicy {
+    /// Checks that `url` may be requested by a guest. The error explains why not.
+    pub fn check_url(&self, url: &reqwest::Url) -> Result<(), String> {
+        let host = match url.host_str() {
+            Some(host) => host.trim_matches(&['[', ']'][..]).trim_end_matches('.'),
+            None => return Err(format!("{} has no host", url)),
+        };
+        let host_str = host.to_ascii_lowercase();
+
+        if self
+            .deny_hosts
+            .iter()
+            .any(|denied| host_matches(&host_str, denied))
+        {
+            return Err(format!(
+                "requests to {host_str} are denied by the egress policy"
+            ));
+        }
+
+        if !self.allow_hosts.is_empty()
+            && !self
+                .allow_hosts
+                .iter()
+                .any(|allowed| host_matches(&host_str, allowed))
+        {
+            return Err(format!("{host_str} is not on the egress allow list"));
+        }
+
+        // domains are checked when they are resolved
+        if self.block_private_addresses {
+            if let Ok(ip) = host_str.parse::<IpAddr>() {
+                if !is_public(ip) {
+                    return Err(format!(
+                        "requests to non-public address {ip} are denied by the egress policy"
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the client guests make their requests with.
+    ///
+    /// Redirects are checked against the policy too so a public server can't be used to bounce a
+    /// request somewhere private.
+    pub fn http_client(&self) -> reqwest::Client {
+        let policy = self.clone();
+        let redirect = reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= 10 {
+                return attempt.error("too many redirects");
+            }
+            match policy.check_url(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(reason) => attempt.error(reason),
+            }
+        });
+        let mut builder = reqwest::Client::builder().redirect(redirect);
+        if self.block_private_addresses {
+            builder = builder.dns_resolver(Arc::new(PublicAddressResolver));
+        }
+        builder.build().expect("http client config is valid")
+    }
+}
+
+/// Whether `host` is `pattern` or one of its subdomains.
+fn host_matches(host: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.');
+    host.eq_ignore_ascii_case(pattern)
+        || (host.len() > pattern.len()
+            && host.as_bytes()[host.len() - pattern.len() - 1] == b'.'
+            && host[host.len() - pattern.len()..].eq_ignore_ascii_case(pattern))
+}
+
+/// Whether `ip` is routable on the public internet.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "this network"
+        || a == 0
+        // shared address space (carrier-grade NAT)
+        || (a == 100 && (64..128).contains(&b))
+        // benchmarking
+        || (a == 198 && (b == 18 || b == 19))
+        // reserved
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local
+        || (first & 0xfe00) == 0xfc00
+        // link local
+        || (first & 0xffc0) == 0xfe80
+        // documentation
+        || first == 0x2001 && ip.segments()[1] == 0x0db8)
+}
+
+/// Resolves hosts with the system resolver but throws away addresses that aren't public.
+///
+/// Checking at resolution time (rather than before making the request) means a guest can't get
+/// around the check by having its domain resolve to something else the second time.
+struct PublicAddressResolver;
+
+impl Resolve for PublicAddressResolver {
+    fn resolve(&self, name: hyper::client::connect::dns::Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr: &SocketAddr| is_public(addr.ip()))
+                .collect::<Vec<_>>();
+            if addrs.is_empty() {
+                return Err(
+                    format!("{} doesn't resolve to any public addresses", name.as_str()).into(),
+                );
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(policy: &EgressPolicy, url: &str) -> Result<(), String> {
+        policy.check_url(&reqwest::Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn allow_and_deny_lists() {
+        let policy = EgressPolicy {
+            allow_hosts: vec!["example.com".into()],
+            deny_hosts: vec!["secret.example.com".into()],
+            ..Default::default()
+        };
+        assert!(check(&policy, "https://example.com/foo").is_ok());
+        assert!(check(&policy, "https://api.EXAMPLE.com/foo").is_ok());
+        assert!(check(&policy, "https://secret.example.com/foo").is_err());
+        assert!(check(&policy, "https://a.secret.example.com/foo").is_err());
+        assert!(check(&policy, "https://notexample.com/foo").is_err());
+        assert!(check(&policy, "https://example.org/foo").is_err());
+    }
+
+    #[test]
+    fn private_addresses_are_blocked() {
+        let policy = EgressPolicy {
+            block_private_addresses: true,
+            ..Default::default()
+        };
+        for url in [
+            "http://127.0.0.1:8000/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.1.2.3/",
+            "http://192.168.0.1/",
+            "http://100.64.0.1/",
+            "http://0.0.0.0/",
+            "http://[::1]/",
+            "http://[fd00:ec2::254]/",
+            "http://[fe80::1]/",
+            "http://[::ffff:127.0.0.1]/",
+        ] {
+            assert!(check(&policy, url).is_err(), "{url} should be blocked");
+        }
+        assert!(check(&policy, "http://1.1.1.1/").is_ok());
+        assert!(check(&policy, "http://[2606:4700:4700::1111]/").is_ok());
+        assert!(check(&policy, "https://example.com/").is_ok());
+        assert!(check(&EgressPolicy::default(), "http://127.0.0.1/").is_ok());
+    }
+
+    #[tokio::test]
+    async fn private_addresses_are_not_resolved() {
+        let policy = EgressPolicy {
+            block_private_addresses: true,
+            ..Default::default()
+        };
+        let error = policy
+            .http_client()
+            .get("http://localhost:1/")
+            .send()
+            .await
+            .unwrap_err();
+        assert!(format!("{error:?}").contains("doesn't resolve to any public addresses"));
+    }
+}
@@ -1,9 +1,14 @@
-use crate::{ExecutorState, State};
-use anyhow::anyhow;
+use crate::{limiter::Limiter, EgressPolicy, ExecutorState, GuestError, Outcome, State};
+use anyhow::{anyhow, Context};
 use async_trait::async_trait;
 use carol_bls as bls;
-use carol_core::MachineId;
+use carol_core::transcript::{HttpExchange, HttpRequest, HttpResponse};
+use carol_core::{Bytes, MachineId};
+use carol_schnorr as schnorr;
 use hyper::StatusCode;
+use std::sync::OnceLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::oneshot;
 use tracing::{event, Level};
 use wasmtime::component::bindgen;
 
@@ -19,6 +24,12 @@ pub use exports::carol::machine::guest;
 pub struct Host {
     pub env: Environment,
     pub panic_message: Option<String>,
+    pub limiter: Limiter,
+    pub egress: EgressPolicy,
+    /// How many outbound HTTP requests the guest has made.
+    pub http_requests: u32,
+    /// The HTTP requests the guest has made so far if we're recording a transcript.
+    pub transcript: Option<Vec<HttpExchange>>,
 }
 
 pub enum Environment {
@@ -26,14 +37,164 @@ pub enum Environment {
         machine_id: MachineId,
         http_client: reqwest::Client,
         state: State,
+        /// How many activations deep this one is nested inside other activations.
+        depth: u32,
     },
     Http {
         machine_id: MachineId,
         state: State,
+        body: HttpBody,
     },
     BinaryApi,
 }
 
+/// What [`HttpBody`] sends to whoever is waiting for the response to a request to a guest's HTTP
+/// handler.
+pub type HttpHandlerResult =
+    anyhow::Result<Result<Outcome<http_crate::Response<hyper::Body>>, GuestError>>;
+
+/// The streamed bodies of the request a guest's HTTP handler is handling and of its response.
+pub struct HttpBody {
+    request: hyper::Body,
+    /// How much of the request body the guest has read.
+    request_bytes_read: u64,
+    max_request_bytes: Option<u64>,
+    /// Where to send the response along with the body it streams from `response_body`. `None`
+    /// once the response has been started.
+    respond: Option<(oneshot::Sender<HttpHandlerResult>, hyper::Body)>,
+    response_body: hyper::body::Sender,
+}
+
+impl HttpBody {
+    pub fn new(
+        request: hyper::Body,
+        max_request_bytes: Option<u64>,
+        respond: oneshot::Sender<HttpHandlerResult>,
+    ) -> Self {
+        let (response_body, body) = hyper::Body::channel();
+        Self {
+            request,
+            request_bytes_read: 0,
+            max_request_bytes,
+            respond: Some((respond, body)),
+            response_body,
+        }
+    }
+
+    async fn read(&mut self) -> Result<Vec<u8>, http_body::Error> {
+        use hyper::body::HttpBody as _;
+        let chunk = match self.request.data().await {
+            Some(chunk) => chunk.map_err(|e| http_body::Error::Closed(e.to_string()))?,
+            None => return Ok(vec![]),
+        };
+        self.request_bytes_read += chunk.len() as u64;
+        if let Some(max) = self.max_request_bytes {
+            if self.request_bytes_read > max {
+                return Err(http_body::Error::TooLarge(max));
+            }
+        }
+        Ok(chunk.to_vec())
+    }
+
+    /// Sends the response head so the guest can start streaming the body. Does nothing if it has
+    /// already been sent.
+    fn start_response(
+        &mut self,
+        status: u16,
+        headers: Vec<(String, Vec<u8>)>,
+    ) -> anyhow::Result<()> {
+        let (respond, body) = match self.respond.take() {
+            Some(respond) => respond,
+            None => return Ok(()),
+        };
+        let response = http::Response {
+            headers,
+            body: vec![],
+            status,
+        };
+        let response = http_crate::Response::<hyper::Body>::try_from(response)
+            .map(|response| response.map(|_| body));
+        match response {
+            Ok(response) => {
+                // fuel can't be reported since the guest is still running
+                let _ = respond.send(Ok(Ok(Outcome {
+                    output: response,
+                    fuel_consumed: None,
+                    transcript: None,
+                })));
+                Ok(())
+            }
+            Err(e) => {
+                let _ = respond.send(Ok(Err(GuestError::Other(anyhow!(
+                    "the guest started an invalid HTTP response: {e}"
+                )))));
+                Err(e)
+            }
+        }
+    }
+
+    async fn write(&mut self, chunk: Vec<u8>) -> anyhow::Result<Result<(), http_body::Error>> {
+        self.start_response(200, vec![])?;
+        if chunk.is_empty() {
+            return Ok(Ok(()));
+        }
+        Ok(self
+            .response_body
+            .send_data(chunk.into())
+            .await
+            .map_err(|e| http_body::Error::Closed(e.to_string())))
+    }
+
+    /// Finishes the response once the guest's handler has returned.
+    pub async fn finish(
+        mut self,
+        result: anyhow::Result<Result<Outcome<http::Response>, GuestError>>,
+    ) {
+        match self.respond.take() {
+            // the response hasn't been started so it can be sent all at once
+            Some((respond, _)) => {
+                let result = result.map(|result| {
+                    result.and_then(|outcome| {
+                        let output =
+                            http_crate::Response::try_from(outcome.output).map_err(|e| {
+                                event!(
+                                Level::ERROR,
+                                "the guest HTTP response couldn't be turned into a hyper::Response"
+                            );
+                                GuestError::Other(e)
+                            })?;
+                        Ok(Outcome {
+                            output,
+                            fuel_consumed: outcome.fuel_consumed,
+                            transcript: outcome.transcript,
+                        })
+                    })
+                });
+                let _ = respond.send(result);
+            }
+            None => {
+                let error = match result {
+                    Ok(Ok(outcome)) => {
+                        let body = outcome.output.body;
+                        if !body.is_empty() {
+                            let _ = self.response_body.send_data(body.into()).await;
+                        }
+                        return;
+                    }
+                    Ok(Err(guest_error)) => guest_error.to_string(),
+                    Err(e) => e.to_string(),
+                };
+                event!(
+                    Level::ERROR,
+                    error,
+                    "guest HTTP handler failed after it started streaming its response"
+                );
+                self.response_body.abort();
+            }
+        }
+    }
+}
+
 impl Environment {
     pub fn machine_id(&self) -> anyhow::Result<MachineId> {
         match self {
@@ -43,6 +204,15 @@ impl Environment {
             Environment::BinaryApi => Err(anyhow!("No machine in this environment")),
         }
     }
+    pub fn http_body(&mut self) -> anyhow::Result<&mut HttpBody> {
+        match self {
+            Environment::Http { body, .. } => Ok(body),
+            _ => Err(anyhow!(
+                "can only stream HTTP bodies in http handler environment"
+            )),
+        }
+    }
+
     pub fn http_client(&self) -> anyhow::Result<&reqwest::Client> {
         match self {
             Environment::Activation { http_client, .. } => Ok(http_client),
@@ -59,6 +229,30 @@ impl Environment {
         }
     }
 
+    pub fn machine_bls_keypair(&self) -> anyhow::Result<bls::KeyPair> {
+        Ok(self.bls_keypair()?.derive_for_machine(self.machine_id()?))
+    }
+
+    pub fn machine_schnorr_keypair(&self) -> anyhow::Result<schnorr::KeyPair> {
+        match self {
+            Environment::Activation {
+                state, machine_id, ..
+            } => Ok(state.schnorr_keypair.derive_for_machine(*machine_id)),
+            _ => Err(anyhow!(
+                "cannot access Schnorr key in http handler environment"
+            )),
+        }
+    }
+
+    /// The depth of any activation started from this environment.
+    pub fn nested_activation_depth(&self) -> anyhow::Result<u32> {
+        match self {
+            Environment::Activation { depth, .. } => Ok(depth + 1),
+            Environment::Http { .. } => Ok(1),
+            Environment::BinaryApi => Err(anyhow!("cannot activate machines in this environment")),
+        }
+    }
+
     pub fn executor_state(&self) -> anyhow::Result<ExecutorState> {
         match self {
             Environment::Activation { state, .. } | Environment::Http { state, .. } => {
@@ -85,28 +279,140 @@ impl http::Host for Host {
     async fn execute(
         &mut self,
         request: http::Request,
+    ) -> anyhow::Result<Result<http::Response, http::Error>> {
+        let recorded_request = self.transcript.is_some().then(|| HttpRequest {
+            method: http_crate::Method::from(request.method).to_string(),
+            uri: request.uri.clone(),
+            headers: transcript_headers(&request.headers),
+            body: request.body.clone().into(),
+        });
+        let result = self.execute_http_request(request).await?;
+        if let (Some(transcript), Some(request)) = (&mut self.transcript, recorded_request) {
+            let response = match &result {
+                Ok(response) => Ok(HttpResponse {
+                    status: response.status,
+                    headers: transcript_headers(&response.headers),
+                    body: response.body.clone().into(),
+                }),
+                Err(e) => Err(e.to_string()),
+            };
+            transcript.push(HttpExchange { request, response });
+        }
+        Ok(result)
+    }
+}
+
+/// Headers whose values are credentials. Anyone can ask for a transcript so their values are left
+/// out of it.
+const SECRET_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+fn transcript_headers(headers: &[(String, Vec<u8>)]) -> Vec<(String, Bytes)> {
+    headers
+        .iter()
+        .map(|(key, value)| {
+            let value = if SECRET_HEADERS
+                .iter()
+                .any(|secret| key.eq_ignore_ascii_case(secret))
+            {
+                b"[redacted]".to_vec()
+            } else {
+                value.clone()
+            };
+            (key.clone(), value.into())
+        })
+        .collect()
+}
+
+impl Host {
+    async fn execute_http_request(
+        &mut self,
+        request: http::Request,
     ) -> anyhow::Result<Result<http::Response, http::Error>> {
         let client = self.env.http_client()?;
+        if let Some(max_requests) = self.egress.max_requests_per_activation {
+            if self.http_requests >= max_requests {
+                return Ok(Err(http::Error::Connection(format!(
+                    "guests may only make {max_requests} HTTP requests per activation"
+                ))));
+            }
+        }
+        self.http_requests += 1;
+        let egress = &self.egress;
         let inner_result = (|| async {
-            let request: reqwest::Request = request.try_into()?;
-            let res = client.execute(request).await?;
+            let mut request: reqwest::Request = request.try_into()?;
+            egress
+                .check_url(request.url())
+                .map_err(http::Error::Connection)?;
+            *request.timeout_mut() = egress.request_timeout;
+            let mut res = client.execute(request).await?;
+            if let (Some(max), Some(len)) = (egress.max_response_bytes, res.content_length()) {
+                if len > max {
+                    return Err(response_too_large(max));
+                }
+            }
             let headers = res
                 .headers()
                 .into_iter()
                 .map(|(key, value)| Ok((key.to_string(), value.as_bytes().to_vec())))
                 .collect::<Result<_, http::Error>>()?;
-            let response = http::Response {
-                status: res.status().as_u16(),
-                body: res.bytes().await?.to_vec(),
+            let status = res.status().as_u16();
+            let mut body = vec![];
+            while let Some(chunk) = res.chunk().await? {
+                if let Some(max) = egress.max_response_bytes {
+                    if (body.len() + chunk.len()) as u64 > max {
+                        return Err(response_too_large(max));
+                    }
+                }
+                body.extend_from_slice(&chunk);
+            }
+            Ok(http::Response {
+                status,
+                body,
                 headers,
-            };
-            Ok(response)
+            })
         })()
         .await;
+        if let Err(e) = &inner_result {
+            event!(
+                Level::DEBUG,
+                error = e.to_string(),
+                "guest HTTP request failed"
+            );
+        }
         Ok(inner_result)
     }
 }
 
+fn response_too_large(max: u64) -> http::Error {
+    http::Error::Unexpected(format!(
+        "response body is larger than the limit of {max} bytes"
+    ))
+}
+
+#[async_trait]
+impl http_body::Host for Host {
+    async fn read(&mut self) -> anyhow::Result<Result<Vec<u8>, http_body::Error>> {
+        Ok(self.env.http_body()?.read().await)
+    }
+
+    async fn start_response(
+        &mut self,
+        status: u16,
+        headers: Vec<(String, Vec<u8>)>,
+    ) -> anyhow::Result<()> {
+        self.env.http_body()?.start_response(status, headers)
+    }
+
+    async fn write(&mut self, chunk: Vec<u8>) -> anyhow::Result<Result<(), http_body::Error>> {
+        self.env.http_body()?.write(chunk).await
+    }
+}
+
 #[async_trait]
 impl global::Host for Host {
     async fn bls_static_pubkey(&mut self) -> anyhow::Result<Vec<u8>> {
@@ -121,6 +427,44 @@ impl global::Host for Host {
                 .to_vec(),
         )
     }
+
+    async fn bls_machine_pubkey(&mut self) -> anyhow::Result<Vec<u8>> {
+        Ok(self
+            .env
+            .machine_bls_keypair()?
+            .public_key()
+            .0
+            .to_uncompressed()
+            .to_vec())
+    }
+
+    async fn bls_machine_sign(&mut self, message: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        Ok(carol_bls::sign(
+            self.env.machine_bls_keypair()?,
+            self.env.machine_id()?,
+            &message,
+        )
+        .0
+        .to_uncompressed()
+        .to_vec())
+    }
+
+    async fn schnorr_machine_pubkey(&mut self) -> anyhow::Result<Vec<u8>> {
+        Ok(self
+            .env
+            .machine_schnorr_keypair()?
+            .public_key()
+            .to_bytes()
+            .to_vec())
+    }
+
+    async fn schnorr_machine_sign(&mut self, message: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        Ok(
+            schnorr::sign(&self.env.machine_schnorr_keypair()?, &message)
+                .to_bytes()
+                .to_vec(),
+        )
+    }
 }
 
 #[async_trait]
@@ -144,31 +488,85 @@ impl machines::Host for Host {
         method_name: String,
         input: Vec<u8>,
     ) -> anyhow::Result<Result<Vec<u8>, machines::Error>> {
-        let exec_state = self.env.executor_state()?;
         let machine_id = self.env.machine_id()?;
-        let (binary_id, params) = exec_state
-            .get_machine(self.env.machine_id()?)
-            .expect("must exist since we are running it!");
+        self.activate_nested(machine_id, &method_name, &input).await
+    }
+
+    async fn activate(
+        &mut self,
+        machine_id: machines::MachineId,
+        method_name: String,
+        input: Vec<u8>,
+    ) -> anyhow::Result<Result<Vec<u8>, machines::Error>> {
+        match MachineId::from_slice(&machine_id) {
+            Some(machine_id) => self.activate_nested(machine_id, &method_name, &input).await,
+            None => Ok(Err(machines::Error::NotFound(machine_id))),
+        }
+    }
+}
+
+impl Host {
+    /// Activates a machine on behalf of the guest.
+    async fn activate_nested(
+        &mut self,
+        machine_id: MachineId,
+        method_name: &str,
+        input: &[u8],
+    ) -> anyhow::Result<Result<Vec<u8>, machines::Error>> {
+        let exec_state = self.env.executor_state()?;
+        let depth = self.env.nested_activation_depth()?;
+        if let Some(max_depth) = exec_state.executor().config().max_activation_depth {
+            if depth > max_depth {
+                event!(
+                    Level::WARN,
+                    machine_id = machine_id.to_string(),
+                    depth,
+                    "refused to nest activation any deeper"
+                );
+                return Ok(Err(machines::Error::DepthLimitExceeded(max_depth)));
+            }
+        }
+        let (binary_id, params) = match exec_state.get_machine(machine_id) {
+            Some(machine) => machine,
+            None => {
+                return Ok(Err(machines::Error::NotFound(
+                    machine_id.to_bytes().to_vec(),
+                )))
+            }
+        };
         let compiled_binary = exec_state
             .get_binary(binary_id)
-            .expect("must exist since we are running it!");
+            .expect("binary must exist if the machine does");
+        let _permit = match exec_state.admit_machine(machine_id) {
+            Ok(permit) => permit,
+            Err(busy) => {
+                event!(
+                    Level::WARN,
+                    machine_id = machine_id.to_string(),
+                    "refused nested activation because {busy}"
+                );
+                return Ok(Err(machines::Error::Busy(machine_id.to_bytes().to_vec())));
+            }
+        };
         match exec_state
             .executor()
-            .activate_machine(
+            .activate_machine_at_depth(
                 self.env.global_state()?,
                 compiled_binary.as_ref(),
                 params.as_ref(),
-                &method_name,
-                &input,
+                method_name,
+                input,
+                depth,
+                false,
             )
             .await
         {
-            Ok(Ok(output)) => Ok(Ok(output)),
+            Ok(Ok(outcome)) => Ok(Ok(outcome.output)),
             Ok(Err(e)) => {
                 event!(
                     Level::ERROR,
-                    input = carol_core::hex::encode(&input),
-                    "self_activate'd guest failed"
+                    input = carol_core::hex::encode(input),
+                    "nested activation of guest failed"
                 );
                 Ok(Err(machines::Error::Panic(machines::PanicInfo {
                     reason: e.to_string(),
@@ -178,8 +576,8 @@ impl machines::Host for Host {
             Err(e) => {
                 event!(
                     Level::ERROR,
-                    input = carol_core::hex::encode(&input),
-                    "self_activate failed due to host error"
+                    input = carol_core::hex::encode(input),
+                    "nested activation failed due to host error"
                 );
                 Err(e)
             }
@@ -187,6 +585,156 @@ impl machines::Host for Host {
     }
 }
 
+#[async_trait]
+impl state::Host for Host {
+    async fn get(&mut self, key: Vec<u8>) -> anyhow::Result<Option<Vec<u8>>> {
+        let machine_id = self.env.machine_id()?;
+        self.env
+            .executor_state()?
+            .storage()
+            .get_state(machine_id, &key)
+    }
+
+    async fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> anyhow::Result<()> {
+        let machine_id = self.env.machine_id()?;
+        self.env
+            .executor_state()?
+            .storage()
+            .set_state(machine_id, &key, &value)
+    }
+
+    async fn delete(&mut self, key: Vec<u8>) -> anyhow::Result<()> {
+        let machine_id = self.env.machine_id()?;
+        self.env
+            .executor_state()?
+            .storage()
+            .delete_state(machine_id, &key)
+    }
+
+    async fn list_prefix(&mut self, prefix: Vec<u8>) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let machine_id = self.env.machine_id()?;
+        self.env
+            .executor_state()?
+            .storage()
+            .list_state(machine_id, &prefix)
+    }
+}
+
+#[async_trait]
+impl clock::Host for Host {
+    async fn now(&mut self) -> anyhow::Result<clock::Datetime> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("system clock is set before the unix epoch")?;
+        Ok(clock::Datetime {
+            seconds: now.as_secs(),
+            nanoseconds: now.subsec_nanos(),
+        })
+    }
+
+    async fn monotonic_now(&mut self) -> anyhow::Result<u64> {
+        static START: OnceLock<Instant> = OnceLock::new();
+        Ok(START.get_or_init(Instant::now).elapsed().as_nanos() as u64)
+    }
+}
+
+/// The most random bytes a guest can ask for in one go.
+const MAX_RANDOM_BYTES: u32 = 1 << 16;
+
+#[async_trait]
+impl random::Host for Host {
+    async fn get_random_bytes(&mut self, len: u32) -> anyhow::Result<Vec<u8>> {
+        if len > MAX_RANDOM_BYTES {
+            return Err(anyhow!(
+                "guest asked for {len} random bytes but the most it can ask for is {MAX_RANDOM_BYTES}"
+            ));
+        }
+        let mut bytes = vec![0u8; len as usize];
+        getrandom::getrandom(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+#[async_trait]
+impl oracle::Host for Host {
+    async fn announce_nonce(
+        &mut self,
+        event_id: Vec<u8>,
+    ) -> anyhow::Result<Result<Vec<u8>, oracle::Error>> {
+        if !self.env.executor_state()?.storage().is_durable() {
+            return Ok(Err(oracle::Error::StorageNotDurable));
+        }
+        Ok(Ok(self
+            .env
+            .machine_schnorr_keypair()?
+            .event_nonce(&event_id)
+            .to_bytes()
+            .to_vec()))
+    }
+
+    async fn attest(
+        &mut self,
+        event_id: Vec<u8>,
+        outcome: Vec<u8>,
+    ) -> anyhow::Result<Result<Vec<u8>, oracle::Error>> {
+        let keypair = self.env.machine_schnorr_keypair()?;
+        let machine_id = self.env.machine_id()?;
+        let exec = self.env.executor_state()?;
+        let storage = exec.storage();
+        if !storage.is_durable() {
+            return Ok(Err(oracle::Error::StorageNotDurable));
+        }
+        let existing = storage.record_attestation(machine_id, &event_id, &outcome)?;
+        match existing {
+            // signing the same outcome again produces the same signature so it's harmless
+            Some(existing) if existing != outcome => {
+                event!(
+                    Level::WARN,
+                    machine_id = machine_id.to_string(),
+                    "refused to attest to a second outcome for an event"
+                );
+                Ok(Err(oracle::Error::AlreadyAttested(existing)))
+            }
+            _ => Ok(Ok(schnorr::sign_with_event_nonce(
+                &keypair, &event_id, &outcome,
+            )
+            .to_bytes()
+            .to_vec())),
+        }
+    }
+}
+
+/// The longest topic a guest can publish an event under.
+const MAX_EVENT_TOPIC_BYTES: usize = 256;
+/// The most data a guest can publish in a single event.
+const MAX_EVENT_DATA_BYTES: usize = 1 << 16;
+
+#[async_trait]
+impl events::Host for Host {
+    async fn publish(&mut self, topic: String, data: Vec<u8>) -> anyhow::Result<u64> {
+        if topic.len() > MAX_EVENT_TOPIC_BYTES {
+            return Err(anyhow!(
+                "event topic is {} bytes but the most it can be is {MAX_EVENT_TOPIC_BYTES}",
+                topic.len()
+            ));
+        }
+        if data.len() > MAX_EVENT_DATA_BYTES {
+            return Err(anyhow!(
+                "event data is {} bytes but the most it can be is {MAX_EVENT_DATA_BYTES}",
+                data.len()
+            ));
+        }
+        let machine_id = self.env.machine_id()?;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("system clock is set before the unix epoch")?
+            .as_secs();
+        self.env
+            .executor_state()?
+            .publish_event(machine_id, time, &topic, &data)
+    }
+}
+
 impl TryFrom<http::Request> for http_crate::Request<hyper::Body> {
     type Error = http::Error;
 
@@ -1,22 +1,90 @@
 #![allow(clippy::redundant_closure_call)]
+mod cache;
+mod egress;
+pub use egress::EgressPolicy;
 mod host_bindings;
+mod limiter;
+pub mod rate_limit;
 mod state;
 pub use state::*;
+mod storage;
+pub use storage::*;
 
 use anyhow::Context;
-use carol_core::{hex, BinaryId, MachineId};
+use cache::ComponentCache;
+use carol_core::{hex, transcript::Transcript, BinaryId, MachineId};
 pub use host_bindings::guest;
-use host_bindings::{Environment, Host, Machine};
+use host_bindings::{Environment, Host, HttpBody, Machine};
+use limiter::Limiter;
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tracing::{event, info_span, Instrument, Level};
-use wasmtime::{component::*, WasmBacktrace};
-use wasmtime::{Config, Engine, Store};
+use wasmtime::{component::*, Trap, WasmBacktrace};
+use wasmtime::{Config, Engine, InstanceAllocationStrategy, PoolingAllocationConfig, Store};
+
+/// How often the engine's epoch is incremented when guests have a timeout.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+const WASM_PAGE_SIZE: u64 = 64 * 1024;
+/// Headers that only apply to the connection between the client and carol so they are never
+/// forwarded to guest HTTP handlers (see RFC 9110 section 7.6.1).
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
 
 #[derive(Clone)]
 pub struct Executor {
-    engine: Engine,
+    /// The epoch ticker thread only holds a weak reference to this so it stops once every clone
+    /// of the executor is dropped.
+    engine: Arc<Engine>,
+    /// Shared by every instantiation since the host functions never change.
+    linker: Arc<Linker<Host>>,
+    config: ExecutorConfig,
+    cache: Option<ComponentCache>,
+    /// Built from the egress policy and shared by every activation.
+    http_client: reqwest::Client,
+}
+
+/// Limits on the resources a single activation (or HTTP request) of a guest may use.
+///
+/// Each activation gets a fresh budget. The default config has no limits.
+#[derive(Clone, Debug, Default)]
+pub struct ExecutorConfig {
+    /// How much fuel (roughly one unit per WASM instruction) an activation may consume.
+    pub fuel_per_activation: Option<u64>,
+    /// How long an activation may run for in wall-clock time.
+    pub activation_timeout: Option<Duration>,
+    /// How large each linear memory of the guest may grow in bytes.
+    pub max_memory_bytes: Option<usize>,
+    /// How many elements each table of the guest may grow to.
+    pub max_table_elements: Option<u32>,
+    /// How many core WASM instances a guest component may create.
+    pub max_instances: Option<usize>,
+    /// Where to keep compiled components so they don't need to be recompiled on restart.
+    pub cache_dir: Option<PathBuf>,
+    /// How deeply activations may nest when machines activate other machines (or themselves).
+    pub max_activation_depth: Option<u32>,
+    /// Request headers that aren't forwarded to guest HTTP handlers (e.g. `cookie`). Hop-by-hop
+    /// headers are never forwarded regardless. Names are matched case-insensitively.
+    pub stripped_request_headers: Vec<String>,
+    /// Which outbound HTTP requests guests may make.
+    pub egress: EgressPolicy,
+    /// How large the body of a request to a guest's HTTP handler may be in bytes.
+    pub max_request_body_bytes: Option<u64>,
+    /// Use wasmtime's pooling allocator which reserves memory for instances up front to make
+    /// instantiation faster. Each memory reserves `max_memory_bytes` so if that isn't set memories
+    /// are capped at wasmtime's default of 160 pages (10MiB).
+    pub pooling_allocator: bool,
 }
 
 impl Default for Executor {
@@ -27,8 +95,11 @@ impl Default for Executor {
 
 #[derive(Clone)]
 pub struct CompiledBinary {
-    component: Component,
+    /// The component with its imports already resolved against the executor's linker.
+    instance_pre: InstancePre<Host>,
     binary_id: BinaryId,
+    /// How many core instances instantiating the component creates.
+    core_instances: usize,
 }
 
 impl CompiledBinary {
@@ -37,12 +108,34 @@ impl CompiledBinary {
     }
 }
 
+/// The output of a guest along with the resources it used to produce it.
+#[derive(Debug)]
+pub struct Outcome<T> {
+    pub output: T,
+    /// How much fuel the guest consumed. `None` when fuel metering is disabled.
+    pub fuel_consumed: Option<u64>,
+    /// What the guest did to produce the output. Only recorded when asked for.
+    pub transcript: Option<Transcript>,
+}
+
 #[derive(Debug)]
 pub enum GuestError {
     Panic {
         backtrace: Option<WasmBacktrace>,
         message: String,
     },
+    /// The guest consumed all the fuel it was given.
+    OutOfFuel {
+        fuel: u64,
+    },
+    /// The guest didn't finish before its deadline.
+    Timeout {
+        after: Duration,
+    },
+    /// The guest tried to use more memory, table elements or instances than it is allowed.
+    ResourceLimitExceeded {
+        reason: String,
+    },
     Other(anyhow::Error),
 }
 
@@ -56,6 +149,15 @@ impl core::fmt::Display for GuestError {
                 }
                 Ok(())
             }
+            GuestError::OutOfFuel { fuel } => {
+                write!(f, "guest ran out of fuel after consuming {}", fuel)
+            }
+            GuestError::Timeout { after } => {
+                write!(f, "guest timed out after {}ms", after.as_millis())
+            }
+            GuestError::ResourceLimitExceeded { reason } => {
+                write!(f, "guest exceeded a resource limit: {}", reason)
+            }
             GuestError::Other(e) => e.fmt(f),
         }
     }
@@ -65,12 +167,174 @@ impl std::error::Error for GuestError {}
 
 impl Executor {
     pub fn new() -> Self {
+        Self::with_config(ExecutorConfig::default())
+    }
+
+    pub fn with_config(executor_config: ExecutorConfig) -> Self {
         let mut config = Config::new();
         config.async_support(true);
         config.wasm_component_model(true);
-        let engine = Engine::new(&config).expect("valid config");
+        config.consume_fuel(executor_config.fuel_per_activation.is_some());
+        config.epoch_interruption(executor_config.activation_timeout.is_some());
+        if executor_config.pooling_allocator {
+            let mut pooling = PoolingAllocationConfig::default();
+            if let Some(max_memory_bytes) = executor_config.max_memory_bytes {
+                let pages = (max_memory_bytes as u64).div_ceil(WASM_PAGE_SIZE);
+                pooling.instance_memory_pages(pages);
+            }
+            if let Some(max_table_elements) = executor_config.max_table_elements {
+                pooling.instance_table_elements(max_table_elements);
+            }
+            config.allocation_strategy(InstanceAllocationStrategy::Pooling(pooling));
+        }
+        let engine = Arc::new(Engine::new(&config).expect("valid config"));
 
-        Self { engine }
+        let mut linker = Linker::new(&engine);
+        Machine::add_to_linker(&mut linker, |host: &mut Host| host)
+            .expect("host functions are only defined once");
+
+        if executor_config.activation_timeout.is_some() {
+            let engine = Arc::downgrade(&engine);
+            std::thread::Builder::new()
+                .name("carol-epoch-ticker".into())
+                .spawn(move || loop {
+                    std::thread::sleep(EPOCH_TICK);
+                    match engine.upgrade() {
+                        Some(engine) => engine.increment_epoch(),
+                        None => break,
+                    }
+                })
+                .expect("able to spawn epoch ticker thread");
+        }
+
+        let cache = executor_config
+            .cache_dir
+            .as_ref()
+            .map(|cache_dir| ComponentCache::open(cache_dir, &engine));
+
+        let http_client = executor_config.egress.http_client();
+
+        Self {
+            engine,
+            linker: Arc::new(linker),
+            config: executor_config,
+            cache,
+            http_client,
+        }
+    }
+
+    pub fn config(&self) -> &ExecutorConfig {
+        &self.config
+    }
+
+    /// Creates a store with a fresh fuel and time budget and the configured resource limits.
+    fn new_store(&self, env: Environment) -> anyhow::Result<Store<Host>> {
+        let host = Host {
+            env,
+            panic_message: None,
+            limiter: Limiter::new(&self.config),
+            egress: self.config.egress.clone(),
+            http_requests: 0,
+            transcript: None,
+        };
+        let mut store = Store::new(&self.engine, host);
+        store.limiter(|host| &mut host.limiter);
+        if let Some(fuel) = self.config.fuel_per_activation {
+            store.add_fuel(fuel)?;
+        }
+        if let Some(timeout) = self.config.activation_timeout {
+            let ticks = (timeout.as_millis() / EPOCH_TICK.as_millis()) as u64 + 1;
+            store.set_epoch_deadline(ticks);
+            store.epoch_deadline_trap();
+        }
+        Ok(store)
+    }
+
+    /// Runs a call into the guest under the configured timeout.
+    ///
+    /// Epoch interruption stops guests spinning inside WASM but can't stop a guest waiting on a
+    /// host call so we also put a timeout on the future.
+    async fn with_timeout<T>(
+        &self,
+        call: impl core::future::Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        match self.config.activation_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, call)
+                .await
+                .unwrap_or_else(|_| Err(Trap::Interrupt.into())),
+            None => call.await,
+        }
+    }
+
+    /// Checks whether instantiating or calling into the guest failed because it exceeded a
+    /// resource limit.
+    fn resource_limit_error(&self, store: &Store<Host>) -> Option<GuestError> {
+        store
+            .data()
+            .limiter
+            .exceeded
+            .as_ref()
+            .map(|reason| GuestError::ResourceLimitExceeded {
+                reason: reason.clone(),
+            })
+    }
+
+    /// Instantiates the guest turning resource limit failures into a [`GuestError`].
+    async fn instantiate(
+        &self,
+        store: &mut Store<Host>,
+        compiled_binary: &CompiledBinary,
+    ) -> anyhow::Result<Result<Machine, GuestError>> {
+        if !store
+            .data_mut()
+            .limiter
+            .instances_allowed(compiled_binary.core_instances)
+        {
+            return Ok(Err(self
+                .resource_limit_error(store)
+                .expect("limiter recorded why")));
+        }
+        match Machine::instantiate_pre(&mut *store, &compiled_binary.instance_pre).await {
+            Ok((bindings, _)) => Ok(Ok(bindings)),
+            Err(e) => match self.resource_limit_error(store) {
+                Some(guest_error) => Ok(Err(guest_error)),
+                None => Err(e),
+            },
+        }
+    }
+
+    /// Turns an error from calling into the guest into a [`GuestError`].
+    fn guest_error(&self, store: &Store<Host>, e: anyhow::Error) -> GuestError {
+        if let Some(guest_error) = self.resource_limit_error(store) {
+            return guest_error;
+        }
+        match e.downcast_ref::<Trap>() {
+            Some(Trap::OutOfFuel) => {
+                return GuestError::OutOfFuel {
+                    fuel: store.fuel_consumed().unwrap_or_default(),
+                }
+            }
+            Some(Trap::Interrupt) => {
+                return GuestError::Timeout {
+                    after: self.config.activation_timeout.unwrap_or_default(),
+                }
+            }
+            _ => {}
+        }
+        match &store.data().panic_message {
+            Some(message) => {
+                event!(Level::ERROR, message = message, "guest panicked");
+                let backtrace = e.downcast::<WasmBacktrace>().ok();
+                GuestError::Panic {
+                    backtrace,
+                    message: message.clone(),
+                }
+            }
+            None => {
+                event!(Level::ERROR, "guest other error: {}", e);
+                GuestError::Other(e)
+            }
+        }
     }
 
     pub fn load_binary_from_wasm_file(
@@ -85,40 +349,107 @@ impl Executor {
 
     pub fn load_binary_from_wasm_binary(&self, binary: &[u8]) -> anyhow::Result<CompiledBinary> {
         let binary_id = BinaryId::new(binary);
+        let cached = self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.load(&self.engine, binary_id));
+        let component = match cached {
+            Some(component) => component,
+            None => {
+                let component = Component::from_binary(&self.engine, binary)?;
+                if let Some(cache) = &self.cache {
+                    cache.store(binary_id, &component);
+                }
+                component
+            }
+        };
+        let instance_pre = self.linker.instantiate_pre(&component)?;
         Ok(CompiledBinary {
-            component: Component::from_binary(&self.engine, binary)?,
+            instance_pre,
             binary_id,
+            core_instances: limiter::core_instance_count(binary)?,
         })
     }
 
+    /// Forgets the compiled form of a binary that is no longer needed.
+    pub fn remove_compiled_binary(&self, binary_id: BinaryId) {
+        if let Some(cache) = &self.cache {
+            cache.remove(binary_id);
+        }
+    }
+
     pub async fn get_binary_api(
         &self,
         compiled_binary: &CompiledBinary,
     ) -> anyhow::Result<host_bindings::guest::BinaryApi> {
-        let dummy_host = Host {
-            env: Environment::BinaryApi,
-            panic_message: None,
-        };
-
-        let mut linker = Linker::new(&self.engine);
-        Machine::add_to_linker(&mut linker, |state: &mut Host| state)?;
-
-        let mut store = Store::new(&self.engine, dummy_host);
+        let mut store = self.new_store(Environment::BinaryApi)?;
 
         let span = info_span!("describe_binary");
 
-        let (bindings, _) =
-            Machine::instantiate_async(&mut store, &compiled_binary.component, &linker).await?;
+        let bindings = self
+            .instantiate(&mut store, compiled_binary)
+            .await?
+            .map_err(anyhow::Error::new)?;
 
-        let output = bindings
-            .carol_machine_guest()
-            .call_get_binary_api(&mut store)
-            .instrument(span)
-            .await?;
+        let output = self
+            .with_timeout(
+                bindings
+                    .carol_machine_guest()
+                    .call_get_binary_api(&mut store)
+                    .instrument(span),
+            )
+            .await
+            .map_err(|e| anyhow::Error::new(self.guest_error(&store, e)))?;
 
         Ok(output)
     }
 
+    /// Has the binary convert machine parameters written as JSON into the encoding its
+    /// activations take them in. The inner error is the binary's explanation of why it couldn't.
+    pub async fn machine_params_from_json(
+        &self,
+        compiled_binary: &CompiledBinary,
+        json: &str,
+    ) -> anyhow::Result<Result<Vec<u8>, String>> {
+        let mut store = self.new_store(Environment::BinaryApi)?;
+        let span = info_span!("machine_params_from_json");
+        let bindings = self
+            .instantiate(&mut store, compiled_binary)
+            .await?
+            .map_err(anyhow::Error::new)?;
+        self.with_timeout(
+            bindings
+                .carol_machine_guest()
+                .call_params_from_json(&mut store, json)
+                .instrument(span),
+        )
+        .await
+        .map_err(|e| anyhow::Error::new(self.guest_error(&store, e)))
+    }
+
+    /// Has the binary convert machine parameters back into JSON. The inner error is the binary's
+    /// explanation of why it couldn't.
+    pub async fn machine_params_to_json(
+        &self,
+        compiled_binary: &CompiledBinary,
+        machine_params: &[u8],
+    ) -> anyhow::Result<Result<String, String>> {
+        let mut store = self.new_store(Environment::BinaryApi)?;
+        let span = info_span!("machine_params_to_json");
+        let bindings = self
+            .instantiate(&mut store, compiled_binary)
+            .await?
+            .map_err(anyhow::Error::new)?;
+        self.with_timeout(
+            bindings
+                .carol_machine_guest()
+                .call_params_to_json(&mut store, machine_params)
+                .instrument(span),
+        )
+        .await
+        .map_err(|e| anyhow::Error::new(self.guest_error(&store, e)))
+    }
+
     pub async fn activate_machine(
         &self,
         state: State,
@@ -126,30 +457,70 @@ impl Executor {
         machine_params: &[u8],
         activation_name: &str,
         activation_input: &[u8],
-    ) -> anyhow::Result<Result<Vec<u8>, GuestError>> {
-        let machine_id = MachineId::new(compiled_binary.binary_id, machine_params);
-        // Instantiation of bindings always happens through a `Linker`.
-        // Configuration of the linker is done through a generated `add_to_linker`
-        // method on the bindings structure.
-        let mut linker = Linker::new(&self.engine);
-        Machine::add_to_linker(&mut linker, |state: &mut Host| state)?;
+    ) -> anyhow::Result<Result<Outcome<Vec<u8>>, GuestError>> {
+        self.activate_machine_at_depth(
+            state,
+            compiled_binary,
+            machine_params,
+            activation_name,
+            activation_input,
+            0,
+            false,
+        )
+        .await
+    }
+
+    /// Like [`Self::activate_machine`] but also records a [`Transcript`] of the activation
+    /// including every HTTP request the machine made and the responses it got.
+    ///
+    /// Activations of other machines made by the guest aren't included.
+    pub async fn activate_machine_with_transcript(
+        &self,
+        state: State,
+        compiled_binary: &CompiledBinary,
+        machine_params: &[u8],
+        activation_name: &str,
+        activation_input: &[u8],
+    ) -> anyhow::Result<Result<Outcome<Vec<u8>>, GuestError>> {
+        self.activate_machine_at_depth(
+            state,
+            compiled_binary,
+            machine_params,
+            activation_name,
+            activation_input,
+            0,
+            true,
+        )
+        .await
+    }
 
+    /// Like [`Self::activate_machine`] but for an activation made from inside another guest where
+    /// `depth` is how many activations deep it is. A [`Transcript`] is recorded if
+    /// `record_transcript`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn activate_machine_at_depth(
+        &self,
+        state: State,
+        compiled_binary: &CompiledBinary,
+        machine_params: &[u8],
+        activation_name: &str,
+        activation_input: &[u8],
+        depth: u32,
+        record_transcript: bool,
+    ) -> anyhow::Result<Result<Outcome<Vec<u8>>, GuestError>> {
+        let machine_id = MachineId::new(compiled_binary.binary_id, machine_params);
         // // As with the core wasm API of Wasmtime instantiation occurs within a
-        // // `Store`. The bindings structure contains an `instantiate` method which
-        // // takes the store, component, and linker. This returns the `bindings`
-        // // structure which is an instance of `HelloWorld` and supports typed access
-        // // to the exports of the component.
-        let mut store = Store::new(
-            &self.engine,
-            Host {
-                env: Environment::Activation {
-                    http_client: reqwest::Client::new(),
-                    machine_id,
-                    state,
-                },
-                panic_message: None,
-            },
-        );
+        // // `Store`. The bindings are instantiated from the `InstancePre` we created with the
+        // // executor's linker when the binary was loaded.
+        let mut store = self.new_store(Environment::Activation {
+            http_client: self.http_client.clone(),
+            machine_id,
+            state,
+            depth,
+        })?;
+        if record_transcript {
+            store.data_mut().transcript = Some(vec![]);
+        }
 
         // struct Handler {}
         // #[async_trait]
@@ -160,8 +531,10 @@ impl Executor {
         //     }
         // }
         // store.call_hook_async(Handler {});
-        let (bindings, _) =
-            Machine::instantiate_async(&mut store, &compiled_binary.component, &linker).await?;
+        let bindings = match self.instantiate(&mut store, compiled_binary).await? {
+            Ok(bindings) => bindings,
+            Err(guest_error) => return Ok(Err(guest_error)),
+        };
         {
             let params = hex::encode(&machine_params[..machine_params.len().min(8)]);
             let input = hex::encode(&activation_input[..activation_input.len().min(8)]);
@@ -176,113 +549,175 @@ impl Executor {
         let span = info_span!("activation", machine_id = machine_id.to_string());
         // // Here our `greet` function doesn't take any parameters for the component,
         // // but in the Wasmtime embedding API the first argument is always a `Store`.
-        let output = bindings
-            .carol_machine_guest()
-            .call_activate(
-                &mut store,
-                machine_params,
-                activation_name,
-                activation_input,
+        let output = self
+            .with_timeout(
+                bindings
+                    .carol_machine_guest()
+                    .call_activate(
+                        &mut store,
+                        machine_params,
+                        activation_name,
+                        activation_input,
+                    )
+                    .instrument(span),
             )
-            .instrument(span)
             .await;
 
         match output {
-            Ok(output) => Ok(Ok(output)),
-            Err(e) => Ok(Err(match &store.data().panic_message {
-                Some(message) => {
-                    event!(Level::ERROR, message = message, "panic during activation");
-                    let backtrace = e.downcast::<WasmBacktrace>().ok();
-                    GuestError::Panic {
-                        backtrace,
-                        message: message.clone(),
-                    }
-                }
-                None => GuestError::Other(e),
-            })),
+            Ok(output) => {
+                let transcript = store.data_mut().transcript.take().map(|http| Transcript {
+                    machine_id,
+                    activation: activation_name.to_string(),
+                    input: activation_input.to_vec().into(),
+                    http,
+                    output: output.clone().into(),
+                });
+                Ok(Ok(Outcome {
+                    output,
+                    fuel_consumed: store.fuel_consumed(),
+                    transcript,
+                }))
+            }
+            Err(e) => Ok(Err(self.guest_error(&store, e))),
         }
     }
 
+    /// The request headers to pass to a guest's HTTP handler.
+    fn forwarded_headers(&self, headers: &http_crate::HeaderMap) -> Vec<(String, Vec<u8>)> {
+        // any header named in `Connection` is also hop-by-hop
+        let connection_headers = headers
+            .get_all(http_crate::header::CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .collect::<Vec<_>>();
+
+        headers
+            .iter()
+            .filter(|(name, _)| {
+                let name = name.as_str();
+                !HOP_BY_HOP_HEADERS.contains(&name)
+                    && !connection_headers.iter().any(|stripped| stripped == name)
+                    && !self
+                        .config
+                        .stripped_request_headers
+                        .iter()
+                        .any(|stripped| stripped.eq_ignore_ascii_case(name))
+            })
+            .map(|(name, value)| (name.as_str().to_string(), value.as_bytes().to_vec()))
+            .collect()
+    }
+
+    /// Has a machine's HTTP handler respond to `req`.
+    ///
+    /// The handler runs in its own task and this returns as soon as it starts its response so the
+    /// body can be streamed while the handler keeps running. Streamed responses don't report how
+    /// much fuel was consumed since the guest hasn't finished when they start.
     pub async fn machine_handle_http_request(
         &self,
         state: State,
-        compiled_binary: &CompiledBinary,
+        compiled_binary: Arc<CompiledBinary>,
         machine_params: &[u8],
-        mut req: http_crate::Request<hyper::Body>,
-    ) -> anyhow::Result<Result<http_crate::Response<hyper::Body>, GuestError>> {
-        use hyper::body::HttpBody;
+        req: http_crate::Request<hyper::Body>,
+    ) -> anyhow::Result<Result<Outcome<http_crate::Response<hyper::Body>>, GuestError>> {
         let machine_id = MachineId::new(compiled_binary.binary_id, machine_params);
-        let mut linker = Linker::new(&self.engine);
-        Machine::add_to_linker(&mut linker, |host: &mut Host| host)?;
-        let mut store = Store::new(
-            &self.engine,
-            Host {
-                env: Environment::Http { machine_id, state },
-                panic_message: None,
-            },
-        );
+        let (parts, request_body) = req.into_parts();
+        let (respond, response) = tokio::sync::oneshot::channel();
+        let body = HttpBody::new(request_body, self.config.max_request_body_bytes, respond);
+        let mut store = self.new_store(Environment::Http {
+            machine_id,
+            state,
+            body,
+        })?;
+        let request = host_bindings::http::Request {
+            method: parts.method.try_into()?,
+            uri: parts.uri.to_string(),
+            headers: self.forwarded_headers(&parts.headers),
+            // the guest streams the body with `http-body.read`
+            body: vec![],
+        };
 
-        let (bindings, _) =
-            Machine::instantiate_async(&mut store, &compiled_binary.component, &linker).await?;
+        let executor = self.clone();
+        tokio::spawn(async move {
+            let result = executor
+                .run_http_handler(&mut store, &compiled_binary, machine_id, request)
+                .await;
+            let env = std::mem::replace(&mut store.data_mut().env, Environment::BinaryApi);
+            if let Environment::Http { body, .. } = env {
+                body.finish(result).await;
+            }
+        });
 
-        let body_stream = req.body_mut();
-        let mut body = Vec::with_capacity(body_stream.size_hint().upper().unwrap_or(0) as usize);
+        response
+            .await
+            .context("HTTP handler task stopped before responding")?
+    }
 
-        while let Some(chunk) = body_stream.data().await {
-            let chunk = chunk.with_context(|| {
-                format!("failed to finished reading http request to {}", machine_id)
-            })?;
-            body.extend_from_slice(chunk.as_ref());
-        }
+    async fn run_http_handler(
+        &self,
+        store: &mut Store<Host>,
+        compiled_binary: &CompiledBinary,
+        machine_id: MachineId,
+        request: host_bindings::http::Request,
+    ) -> anyhow::Result<Result<Outcome<host_bindings::http::Response>, GuestError>> {
+        let bindings = match self.instantiate(&mut *store, compiled_binary).await? {
+            Ok(bindings) => bindings,
+            Err(guest_error) => return Ok(Err(guest_error)),
+        };
 
         let span = info_span!(
             "machine_handle_http_request",
             machine_id = machine_id.to_string()
         );
-        let response = bindings
-            .carol_machine_guest()
-            .call_handle_http(
-                &mut store,
-                &host_bindings::http::Request {
-                    method: req.method().clone().try_into()?,
-                    uri: req.uri().to_string(),
-                    headers: vec![], // TODO: support headers
-                    body,
-                },
+        let response = self
+            .with_timeout(
+                bindings
+                    .carol_machine_guest()
+                    .call_handle_http(&mut *store, &request)
+                    .instrument(span),
             )
-            .instrument(span.clone())
-            .await
-            .map(|response| response.try_into());
-
-        let _enter = span.enter();
+            .await;
 
         match response {
-            Ok(Ok(response)) => Ok(Ok(response)),
-            Ok(Err(e)) => {
-                event!(
-                    Level::ERROR,
-                    "the guest HTTP response couldn't be turned into a hyper::Response"
-                );
-                Ok(Err(GuestError::Other(e)))
-            }
-            Err(e) => Ok(Err(match &store.data().panic_message {
-                Some(message) => {
-                    event!(
-                        Level::ERROR,
-                        message = message,
-                        "panic while handling http request"
-                    );
-                    let backtrace = e.downcast::<WasmBacktrace>().ok();
-                    GuestError::Panic {
-                        backtrace,
-                        message: message.clone(),
-                    }
-                }
-                None => {
-                    event!(Level::ERROR, "guest other error: {}", e);
-                    GuestError::Other(e)
-                }
+            Ok(response) => Ok(Ok(Outcome {
+                output: response,
+                fuel_consumed: store.fuel_consumed(),
+                transcript: None,
             })),
+            Err(e) => Ok(Err(self.guest_error(store, e))),
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use http_crate::header::{HeaderMap, HeaderValue};
+
+    #[test]
+    fn hop_by_hop_and_configured_headers_are_not_forwarded() {
+        let executor = Executor::with_config(ExecutorConfig {
+            stripped_request_headers: vec!["Cookie".into()],
+            ..Default::default()
+        });
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("text/plain"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer foo"));
+        headers.insert("cookie", HeaderValue::from_static("a=b"));
+        headers.insert(
+            "connection",
+            HeaderValue::from_static("keep-alive, x-private"),
+        );
+        headers.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        headers.insert("x-private", HeaderValue::from_static("secret"));
+
+        assert_eq!(
+            executor.forwarded_headers(&headers),
+            vec![
+                ("content-type".to_string(), b"text/plain".to_vec()),
+                ("authorization".to_string(), b"Bearer foo".to_vec()),
+            ]
+        );
+    }
+}
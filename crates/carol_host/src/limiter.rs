@@ -0,0 +1,226 @@
+use crate::ExecutorConfig;
+use wasmparser::{
+    ComponentAlias, ComponentExternalKind, ComponentInstance, ComponentOuterAliasKind,
+    ComponentTypeRef, Encoding, Instance, Parser, Payload,
+};
+use wasmtime::ResourceLimiter;
+
+/// Enforces the memory, table and instance limits in [`ExecutorConfig`] on a single store.
+///
+/// Growing past a limit traps the guest rather than making `memory.grow` fail so the guest can't
+/// carry on in a confused state. The reason is remembered so it can be reported as a
+/// [`GuestError::ResourceLimitExceeded`](crate::GuestError::ResourceLimitExceeded).
+pub struct Limiter {
+    max_memory_bytes: Option<usize>,
+    max_table_elements: Option<u32>,
+    max_instances: Option<usize>,
+    pub exceeded: Option<String>,
+}
+
+impl Limiter {
+    pub fn new(config: &ExecutorConfig) -> Self {
+        Self {
+            max_memory_bytes: config.max_memory_bytes,
+            max_table_elements: config.max_table_elements,
+            max_instances: config.max_instances,
+            exceeded: None,
+        }
+    }
+
+    /// Checks a component that creates `core_instances` core instances when it is instantiated
+    /// (see [`core_instance_count`]) against the instance limit.
+    ///
+    /// wasmtime enforces the limit too but only tells us with an error message so we check it
+    /// ourselves first to know that's why instantiation failed.
+    pub fn instances_allowed(&mut self, core_instances: usize) -> bool {
+        let limit = self.instances();
+        if core_instances > limit {
+            self.exceeded = Some(format!(
+                "{core_instances} core instances exceeds the limit of {limit} instances"
+            ));
+            return false;
+        }
+        true
+    }
+}
+
+/// How many core instances instantiating the component in `binary` creates.
+///
+/// Components can't create instances at runtime so this is just the instances each component
+/// creates plus those of the components it instantiates. It doesn't include the adapter modules
+/// wasmtime may generate to connect components to each other.
+pub fn core_instance_count(binary: &[u8]) -> anyhow::Result<usize> {
+    struct Frame {
+        is_component: bool,
+        /// How many core instances instantiating each component in the index space creates.
+        components: Vec<usize>,
+        instances: usize,
+    }
+    /// The component `count` levels out from the innermost one.
+    fn frame_at(stack: &mut [Frame], count: u32) -> Option<&mut Frame> {
+        let i = stack.len().checked_sub(1 + count as usize)?;
+        stack.get_mut(i)
+    }
+    let mut stack: Vec<Frame> = vec![];
+    for payload in Parser::new(0).parse_all(binary) {
+        match payload? {
+            Payload::Version { encoding, .. } => stack.push(Frame {
+                is_component: encoding == Encoding::Component,
+                components: vec![],
+                instances: 0,
+            }),
+            Payload::InstanceSection(reader) => {
+                for instance in reader {
+                    if let Instance::Instantiate { .. } = instance? {
+                        frame_at(&mut stack, 0).expect("in a component").instances += 1;
+                    }
+                }
+            }
+            Payload::ComponentInstanceSection(reader) => {
+                for instance in reader {
+                    if let ComponentInstance::Instantiate {
+                        component_index, ..
+                    } = instance?
+                    {
+                        let frame = frame_at(&mut stack, 0).expect("in a component");
+                        frame.instances += frame
+                            .components
+                            .get(component_index as usize)
+                            .copied()
+                            .unwrap_or_default();
+                    }
+                }
+            }
+            Payload::ComponentAliasSection(reader) => {
+                for alias in reader {
+                    let instances = match alias? {
+                        ComponentAlias::Outer {
+                            kind: ComponentOuterAliasKind::Component,
+                            count,
+                            index,
+                        } => frame_at(&mut stack, count)
+                            .and_then(|outer| outer.components.get(index as usize).copied())
+                            .unwrap_or_default(),
+                        ComponentAlias::InstanceExport {
+                            kind: ComponentExternalKind::Component,
+                            ..
+                        } => 0,
+                        _ => continue,
+                    };
+                    frame_at(&mut stack, 0)
+                        .expect("in a component")
+                        .components
+                        .push(instances);
+                }
+            }
+            Payload::ComponentImportSection(reader) => {
+                for import in reader {
+                    if let ComponentTypeRef::Component(_) = import?.ty {
+                        frame_at(&mut stack, 0)
+                            .expect("in a component")
+                            .components
+                            .push(0);
+                    }
+                }
+            }
+            Payload::ComponentExportSection(reader) => {
+                for export in reader {
+                    let export = export?;
+                    if export.kind == ComponentExternalKind::Component {
+                        let frame = frame_at(&mut stack, 0).expect("in a component");
+                        let instances = frame
+                            .components
+                            .get(export.index as usize)
+                            .copied()
+                            .unwrap_or_default();
+                        frame.components.push(instances);
+                    }
+                }
+            }
+            Payload::End(_) => {
+                let frame = stack.pop().expect("every end has a version");
+                match stack.last_mut() {
+                    Some(parent) if frame.is_component => parent.components.push(frame.instances),
+                    Some(_) => {}
+                    None => return Ok(frame.instances),
+                }
+            }
+            _ => {}
+        }
+    }
+    Err(anyhow::anyhow!("binary ended before the component did"))
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> anyhow::Result<bool> {
+        if let Some(limit) = self.max_memory_bytes {
+            if desired > limit {
+                let reason =
+                    format!("memory of {desired} bytes exceeds the limit of {limit} bytes");
+                self.exceeded = Some(reason.clone());
+                return Err(anyhow::anyhow!(reason));
+            }
+        }
+        Ok(maximum.map(|max| desired <= max).unwrap_or(true))
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        desired: u32,
+        maximum: Option<u32>,
+    ) -> anyhow::Result<bool> {
+        if let Some(limit) = self.max_table_elements {
+            if desired > limit {
+                let reason =
+                    format!("table of {desired} elements exceeds the limit of {limit} elements");
+                self.exceeded = Some(reason.clone());
+                return Err(anyhow::anyhow!(reason));
+            }
+        }
+        Ok(maximum.map(|max| desired <= max).unwrap_or(true))
+    }
+
+    fn instances(&self) -> usize {
+        self.max_instances
+            .unwrap_or(wasmtime::DEFAULT_INSTANCE_LIMIT)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_core_instances_of_nested_components() {
+        let binary = wat::parse_str(
+            r#"
+(component
+  (core module $m)
+  (core instance (instantiate $m))
+  (component $inner
+    (core module $m)
+    (core instance (instantiate $m))
+    (core instance (instantiate $m))
+    (core instance)
+  )
+  (instance (instantiate $inner))
+  (component $outer
+    (alias outer 1 $inner (component $aliased))
+    (instance (instantiate $aliased))
+  )
+  (instance (instantiate $outer))
+)
+"#,
+        )
+        .unwrap();
+        // one at the top, two in each instance of `$inner` and none for the instance made
+        // from exports
+        assert_eq!(core_instance_count(&binary).unwrap(), 5);
+    }
+}
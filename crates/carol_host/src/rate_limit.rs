@@ -0,0 +1,261 @@
+//! Limits on how often and how many times at once something (e.g. a machine or a client of the
+//! HTTP server) can make the node do work.
+use carol_core::{BinaryId, MachineId};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Once we are keeping track of this many keys we forget the ones whose buckets have refilled.
+const PRUNE_AT: usize = 10_000;
+
+/// A token bucket that holds up to `burst` tokens and refills at `per_second`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// A token bucket for each key (e.g. client IP or machine).
+#[derive(Clone)]
+pub struct RateLimiter<K> {
+    limit: RateLimit,
+    buckets: Arc<Mutex<HashMap<K, Bucket>>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn tokens_at(&self, limit: &RateLimit, now: Instant) -> f64 {
+        let refilled = now.duration_since(self.updated).as_secs_f64() * limit.per_second;
+        (self.tokens + refilled).min(f64::from(limit.burst))
+    }
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Takes a token from `key`'s bucket.
+    ///
+    /// If the bucket is empty it returns how long until there will be a token in it.
+    pub fn check(&self, key: K) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: K, now: Instant) -> Result<(), Duration> {
+        let limit = &self.limit;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_AT {
+            buckets.retain(|_, bucket| bucket.tokens_at(limit, now) < f64::from(limit.burst));
+        }
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        });
+        let tokens = bucket.tokens_at(limit, now);
+        bucket.updated = now;
+        if tokens >= 1.0 {
+            bucket.tokens = tokens - 1.0;
+            Ok(())
+        } else {
+            bucket.tokens = tokens;
+            Err(
+                Duration::try_from_secs_f64((1.0 - tokens) / limit.per_second)
+                    .unwrap_or(Duration::MAX),
+            )
+        }
+    }
+}
+
+/// Counts how many things are running for each key (e.g. machine) so they can be capped.
+#[derive(Clone)]
+pub struct ConcurrencyLimiter<K> {
+    max: u32,
+    running: Arc<Mutex<HashMap<K, u32>>>,
+}
+
+/// One of the places under a [`ConcurrencyLimiter`]'s cap. It's given back when dropped.
+pub struct ConcurrencyPermit<K: Hash + Eq> {
+    key: K,
+    running: Arc<Mutex<HashMap<K, u32>>>,
+}
+
+impl<K: Hash + Eq + Clone> ConcurrencyLimiter<K> {
+    pub fn new(max: u32) -> Self {
+        Self {
+            max,
+            running: Default::default(),
+        }
+    }
+
+    /// A permit to run something for `key` unless `max` are already running.
+    pub fn try_acquire(&self, key: K) -> Option<ConcurrencyPermit<K>> {
+        let mut running = self.running.lock().unwrap();
+        let count = running.entry(key.clone()).or_default();
+        if *count >= self.max {
+            if *count == 0 {
+                running.remove(&key);
+            }
+            return None;
+        }
+        *count += 1;
+        Some(ConcurrencyPermit {
+            key,
+            running: self.running.clone(),
+        })
+    }
+}
+
+impl<K: Hash + Eq> Drop for ConcurrencyPermit<K> {
+    fn drop(&mut self) {
+        let mut running = self.running.lock().unwrap();
+        if let Some(count) = running.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                running.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// How often each machine may be run and how many times at once however it came to be run (e.g.
+/// a HTTP request, a nested activation or its schedule).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MachineLimits {
+    /// No limit if unset.
+    pub rate: Option<RateLimit>,
+    /// No limit if unset.
+    pub max_concurrent: Option<u32>,
+}
+
+/// What running guest code counts against. Binaries are run without a machine to describe
+/// themselves and to convert machine parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Runner {
+    Machine(MachineId),
+    Binary(BinaryId),
+}
+
+/// Enforces [`MachineLimits`].
+#[derive(Clone, Default)]
+pub(crate) struct Admission {
+    rate: Option<RateLimiter<Runner>>,
+    concurrency: Option<ConcurrencyLimiter<Runner>>,
+}
+
+impl Admission {
+    pub(crate) fn new(limits: MachineLimits) -> Self {
+        Self {
+            rate: limits.rate.map(RateLimiter::new),
+            concurrency: limits.max_concurrent.map(ConcurrencyLimiter::new),
+        }
+    }
+
+    pub(crate) fn admit(&self, runner: Runner) -> Result<ActivationPermit, Busy> {
+        if let Some(rate) = &self.rate {
+            rate.check(runner)
+                .map_err(|retry_after| Busy::RateLimited { retry_after })?;
+        }
+        let permit = match &self.concurrency {
+            Some(concurrency) => Some(
+                concurrency
+                    .try_acquire(runner)
+                    .ok_or(Busy::TooManyRunning)?,
+            ),
+            None => None,
+        };
+        Ok(ActivationPermit { _permit: permit })
+    }
+}
+
+/// One of the places under a machine's limit on how many times it may be running at once. It's
+/// given back when dropped so hold on to it while the machine runs.
+pub struct ActivationPermit {
+    _permit: Option<ConcurrencyPermit<Runner>>,
+}
+
+/// Why a machine can't be run right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Busy {
+    /// It has been run too often lately. It can be run again after `retry_after`.
+    RateLimited { retry_after: Duration },
+    /// It is already running as many times at once as it may.
+    TooManyRunning,
+}
+
+impl core::fmt::Display for Busy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Busy::RateLimited { .. } => write!(f, "it is being run too often"),
+            Busy::TooManyRunning => write!(f, "it is running too many times at once"),
+        }
+    }
+}
+
+impl std::error::Error for Busy {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_second: 2.0,
+            burst: 3,
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.check_at("a", start).unwrap();
+        }
+        assert_eq!(
+            limiter.check_at("a", start),
+            Err(Duration::from_millis(500))
+        );
+        // other keys have their own bucket
+        limiter.check_at("b", start).unwrap();
+
+        // a failed check doesn't take anything from the bucket
+        let later = start + Duration::from_millis(250);
+        assert_eq!(
+            limiter.check_at("a", later),
+            Err(Duration::from_millis(250))
+        );
+        let later = start + Duration::from_millis(500);
+        limiter.check_at("a", later).unwrap();
+        assert!(limiter.check_at("a", later).is_err());
+
+        // it never holds more than `burst`
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            limiter.check_at("a", much_later).unwrap();
+        }
+        assert!(limiter.check_at("a", much_later).is_err());
+    }
+
+    #[test]
+    fn permits_are_given_back_when_dropped() {
+        let limiter = ConcurrencyLimiter::new(2);
+        let first = limiter.try_acquire("a").unwrap();
+        let second = limiter.try_acquire("a").unwrap();
+        assert!(limiter.try_acquire("a").is_none());
+        assert!(limiter.try_acquire("b").is_some());
+
+        drop(first);
+        let third = limiter.try_acquire("a").unwrap();
+        assert!(limiter.try_acquire("a").is_none());
+
+        drop(second);
+        drop(third);
+        // nothing is kept for keys with nothing running
+        assert!(limiter.running.lock().unwrap().is_empty());
+    }
+}
@@ -1,67 +1,381 @@
 #![allow(clippy::type_complexity)]
-use crate::{BinaryId, CompiledBinary, Executor, MachineId};
+use crate::rate_limit::{ActivationPermit, Admission, Busy, MachineLimits, Runner};
+use crate::{BinaryId, CompiledBinary, Executor, MachineId, MemoryStorage, Storage};
+use anyhow::Context;
 use carol_bls as bls;
+use carol_schnorr as schnorr;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::{event, Level};
+
+/// How many event notifications can be queued for a slow subscriber before it starts missing them.
+const EVENT_NOTIFICATION_CAPACITY: usize = 1024;
 
 #[derive(Clone)]
 pub struct State {
     pub bls_keypair: bls::KeyPair,
+    pub schnorr_keypair: schnorr::KeyPair,
     pub exec: ExecutorState,
 }
 
 impl State {
-    pub fn new(bls_keypair: bls::KeyPair) -> Self {
+    pub fn new(bls_keypair: bls::KeyPair, schnorr_keypair: schnorr::KeyPair) -> Self {
         Self {
             bls_keypair,
+            schnorr_keypair,
             exec: ExecutorState::default(),
         }
     }
 }
 
-#[derive(Clone, Default)]
+/// What a node knows about a binary without looking inside it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BinaryInfo {
+    pub binary_id: BinaryId,
+    /// The size of the WASM component in bytes.
+    pub size: u64,
+    /// When the binary was uploaded in seconds since the unix epoch.
+    pub created: u64,
+}
+
+/// What a node knows about a machine without looking at its parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MachineInfo {
+    pub machine_id: MachineId,
+    pub binary_id: BinaryId,
+    /// The size of the machine's parameters in bytes.
+    pub params_size: u64,
+    /// When the machine was created in seconds since the unix epoch.
+    pub created: u64,
+}
+
+/// Why a binary couldn't be removed.
+#[derive(Debug)]
+pub enum RemoveBinaryError {
+    NotFound,
+    /// Machines that run the binary still exist.
+    InUse {
+        n_machines: usize,
+    },
+    Other(anyhow::Error),
+}
+
+impl core::fmt::Display for RemoveBinaryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoveBinaryError::NotFound => write!(f, "binary not found"),
+            RemoveBinaryError::InUse { n_machines } => {
+                write!(f, "binary is still used by {} machine(s)", n_machines)
+            }
+            RemoveBinaryError::Other(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RemoveBinaryError {}
+
+#[derive(Clone)]
 pub struct ExecutorState {
     executor: Executor,
-    binaries: Arc<Mutex<HashMap<BinaryId, Arc<CompiledBinary>>>>,
-    machines: Arc<Mutex<HashMap<MachineId, (BinaryId, Arc<Vec<u8>>)>>>,
+    storage: Arc<dyn Storage>,
+    binaries: Arc<Mutex<HashMap<BinaryId, (Arc<CompiledBinary>, BinaryInfo)>>>,
+    machines: Arc<Mutex<HashMap<MachineId, (BinaryId, Arc<Vec<u8>>, u64)>>>,
+    /// What each machine's binary gave when asked to convert its params to JSON.
+    params_json: Arc<Mutex<HashMap<MachineId, Result<String, String>>>>,
+    /// Sent the machine id and event id of every event that is published.
+    events_published: broadcast::Sender<(MachineId, u64)>,
+    admission: Admission,
+}
+
+impl Default for ExecutorState {
+    fn default() -> Self {
+        Self::new(Executor::default())
+    }
 }
 
 impl ExecutorState {
+    /// Creates an executor state that only lives in memory.
     pub fn new(executor: Executor) -> Self {
         Self {
             executor,
+            storage: Arc::new(MemoryStorage::default()),
             binaries: Default::default(),
             machines: Default::default(),
+            params_json: Default::default(),
+            events_published: broadcast::channel(EVENT_NOTIFICATION_CAPACITY).0,
+            admission: Admission::default(),
         }
     }
 
+    /// Creates an executor state backed by `storage` recompiling every binary and loading every
+    /// machine that was previously stored there.
+    pub fn with_storage(executor: Executor, storage: Arc<dyn Storage>) -> anyhow::Result<Self> {
+        let mut binaries = HashMap::new();
+        for binary_id in storage.list_binaries()? {
+            let binary = storage
+                .get_binary(binary_id)?
+                .with_context(|| format!("binary {binary_id} was listed but is missing"))?;
+            let compiled_binary = executor
+                .load_binary_from_wasm_binary(&binary)
+                .with_context(|| format!("recompiling stored binary {binary_id}"))?;
+            let created = storage.binary_created(binary_id)?.unwrap_or_default();
+            let info = BinaryInfo {
+                binary_id,
+                size: binary.len() as u64,
+                created,
+            };
+            binaries.insert(binary_id, (Arc::new(compiled_binary), info));
+        }
+
+        let mut machines = HashMap::new();
+        for machine_id in storage.list_machines()? {
+            let (binary_id, params) = storage
+                .get_machine(machine_id)?
+                .with_context(|| format!("machine {machine_id} was listed but is missing"))?;
+            let created = storage.machine_created(machine_id)?.unwrap_or_default();
+            machines.insert(machine_id, (binary_id, Arc::new(params), created));
+        }
+
+        event!(
+            Level::INFO,
+            n_binaries = binaries.len(),
+            n_machines = machines.len(),
+            "loaded executor state from storage"
+        );
+
+        Ok(Self {
+            executor,
+            storage,
+            binaries: Arc::new(Mutex::new(binaries)),
+            machines: Arc::new(Mutex::new(machines)),
+            params_json: Default::default(),
+            events_published: broadcast::channel(EVENT_NOTIFICATION_CAPACITY).0,
+            admission: Admission::default(),
+        })
+    }
+
+    /// Limits how often and how many times at once each machine can be run. No limits are applied
+    /// unless this is called.
+    pub fn limit_machines(mut self, limits: MachineLimits) -> Self {
+        self.admission = Admission::new(limits);
+        self
+    }
+
+    /// Checks the machine is within its limits and takes one of its places for running at once.
+    ///
+    /// Every activation of the machine (and every HTTP request to it) should be run while holding
+    /// on to the permit.
+    pub fn admit_machine(&self, machine_id: MachineId) -> Result<ActivationPermit, Busy> {
+        self.admission.admit(Runner::Machine(machine_id))
+    }
+
+    /// Like [`Self::admit_machine`] but for running a binary without a machine e.g. to convert
+    /// machine parameters from JSON. Each binary has limits of its own like a machine does.
+    pub fn admit_binary(&self, binary_id: BinaryId) -> Result<ActivationPermit, Busy> {
+        self.admission.admit(Runner::Binary(binary_id))
+    }
+
     pub fn get_binary(&self, binary_id: BinaryId) -> Option<Arc<CompiledBinary>> {
-        self.binaries.lock().unwrap().get(&binary_id).cloned()
+        self.binaries
+            .lock()
+            .unwrap()
+            .get(&binary_id)
+            .map(|(compiled_binary, _)| compiled_binary.clone())
     }
 
-    pub fn insert_binary(&self, compiled_binary: CompiledBinary) {
+    /// Up to `limit` binaries in order of their ids starting after `after`.
+    pub fn list_binaries(&self, after: Option<BinaryId>, limit: usize) -> Vec<BinaryInfo> {
+        let mut binaries = self
+            .binaries
+            .lock()
+            .unwrap()
+            .values()
+            .map(|(_, info)| *info)
+            .filter(|info| Some(info.binary_id) > after)
+            .collect::<Vec<_>>();
+        binaries.sort_unstable_by_key(|info| info.binary_id);
+        binaries.truncate(limit);
+        binaries
+    }
+
+    /// Stores `binary` and makes its compiled form available to [`get_binary`].
+    ///
+    /// [`get_binary`]: Self::get_binary
+    pub fn insert_binary(
+        &self,
+        binary: &[u8],
+        compiled_binary: CompiledBinary,
+    ) -> anyhow::Result<()> {
+        debug_assert_eq!(BinaryId::new(binary), compiled_binary.binary_id);
+        let info = BinaryInfo {
+            binary_id: compiled_binary.binary_id,
+            size: binary.len() as u64,
+            created: now(),
+        };
+        self.storage
+            .put_binary(info.binary_id, binary, info.created)
+            .with_context(|| format!("storing binary {}", info.binary_id))?;
         self.binaries
             .lock()
             .unwrap()
-            .insert(compiled_binary.binary_id, Arc::new(compiled_binary));
+            .insert(info.binary_id, (Arc::new(compiled_binary), info));
+        Ok(())
+    }
+
+    /// Removes a binary from storage. Binaries can only be removed once every machine running them
+    /// has been removed.
+    pub fn remove_binary(&self, binary_id: BinaryId) -> Result<(), RemoveBinaryError> {
+        // hold the machine lock so no machine can be created for the binary while we remove it
+        let machines = self.machines.lock().unwrap();
+        let n_machines = machines
+            .values()
+            .filter(|(machine_binary_id, _, _)| *machine_binary_id == binary_id)
+            .count();
+        if n_machines > 0 {
+            return Err(RemoveBinaryError::InUse { n_machines });
+        }
+        let mut binaries = self.binaries.lock().unwrap();
+        if !binaries.contains_key(&binary_id) {
+            return Err(RemoveBinaryError::NotFound);
+        }
+        self.storage
+            .delete_binary(binary_id)
+            .with_context(|| format!("deleting binary {binary_id}"))
+            .map_err(RemoveBinaryError::Other)?;
+        binaries.remove(&binary_id);
+        self.executor.remove_compiled_binary(binary_id);
+        Ok(())
     }
 
     pub fn get_machine(&self, machine_id: MachineId) -> Option<(BinaryId, Arc<Vec<u8>>)> {
-        self.machines.lock().unwrap().get(&machine_id).cloned()
+        self.machines
+            .lock()
+            .unwrap()
+            .get(&machine_id)
+            .map(|(binary_id, params, _)| (*binary_id, params.clone()))
     }
 
-    pub fn insert_machine(&self, binary_id: BinaryId, params: Vec<u8>) -> (bool, MachineId) {
-        let machine_id = MachineId::new(binary_id, &params);
-        let already_existed = self
+    /// Up to `limit` machines in order of their ids starting after `after`. If `binary_id` is
+    /// given only machines running that binary are listed.
+    pub fn list_machines(
+        &self,
+        binary_id: Option<BinaryId>,
+        after: Option<MachineId>,
+        limit: usize,
+    ) -> Vec<MachineInfo> {
+        let mut machines = self
             .machines
             .lock()
             .unwrap()
-            .insert(machine_id, (binary_id, Arc::new(params)))
-            .is_some();
-        (already_existed, machine_id)
+            .iter()
+            .filter(|(machine_id, (machine_binary_id, _, _))| {
+                (binary_id.is_none() || binary_id == Some(*machine_binary_id))
+                    && Some(**machine_id) > after
+            })
+            .map(|(machine_id, (binary_id, params, created))| MachineInfo {
+                machine_id: *machine_id,
+                binary_id: *binary_id,
+                params_size: params.len() as u64,
+                created: *created,
+            })
+            .collect::<Vec<_>>();
+        machines.sort_unstable_by_key(|info| info.machine_id);
+        machines.truncate(limit);
+        machines
+    }
+
+    pub fn insert_machine(
+        &self,
+        binary_id: BinaryId,
+        params: Vec<u8>,
+    ) -> anyhow::Result<(bool, MachineId)> {
+        let machine_id = MachineId::new(binary_id, &params);
+        let mut machines = self.machines.lock().unwrap();
+        if machines.contains_key(&machine_id) {
+            return Ok((true, machine_id));
+        }
+        if self.get_binary(binary_id).is_none() {
+            return Err(anyhow::anyhow!("binary {binary_id} doesn't exist"));
+        }
+        let created = now();
+        self.storage
+            .put_machine(machine_id, binary_id, &params, created)
+            .with_context(|| format!("storing machine {machine_id}"))?;
+        machines.insert(machine_id, (binary_id, Arc::new(params), created));
+        Ok((false, machine_id))
+    }
+
+    /// Removes a machine along with its state, scheduled outputs and event log. Returns whether
+    /// the machine existed.
+    pub fn remove_machine(&self, machine_id: MachineId) -> anyhow::Result<bool> {
+        let mut machines = self.machines.lock().unwrap();
+        if !machines.contains_key(&machine_id) {
+            return Ok(false);
+        }
+        self.storage
+            .delete_machine(machine_id)
+            .with_context(|| format!("deleting machine {machine_id}"))?;
+        machines.remove(&machine_id);
+        self.params_json.lock().unwrap().remove(&machine_id);
+        Ok(true)
+    }
+
+    /// What a machine's binary gave when its params were last [cached](Self::cache_params_json)
+    /// as JSON. `None` if they haven't been converted yet.
+    pub fn cached_params_json(&self, machine_id: MachineId) -> Option<Result<String, String>> {
+        self.params_json.lock().unwrap().get(&machine_id).cloned()
+    }
+
+    /// Remembers what a machine's binary gave when converting its params to JSON. A machine's
+    /// params never change so they only ever need converting once.
+    pub fn cache_params_json(&self, machine_id: MachineId, converted: Result<String, String>) {
+        self.params_json
+            .lock()
+            .unwrap()
+            .insert(machine_id, converted);
+    }
+
+    /// Appends an event to a machine's event log and lets everyone who has
+    /// [subscribed](Self::subscribe_events) know about it.
+    pub fn publish_event(
+        &self,
+        machine_id: MachineId,
+        time: u64,
+        topic: &str,
+        data: &[u8],
+    ) -> anyhow::Result<u64> {
+        let event_id = self
+            .storage
+            .append_event(machine_id, time, topic, data)
+            .with_context(|| format!("appending to the event log of {machine_id}"))?;
+        // it's fine if no one is listening
+        let _ = self.events_published.send((machine_id, event_id));
+        Ok(event_id)
+    }
+
+    /// Get notified of the machine id and event id of every event published from now on.
+    ///
+    /// The notifications don't include the events themselves. Read them from
+    /// [`Storage::list_events`]. If the receiver falls too far behind it gets
+    /// [`broadcast::error::RecvError::Lagged`] and should check the logs it's interested in again.
+    pub fn subscribe_events(&self) -> broadcast::Receiver<(MachineId, u64)> {
+        self.events_published.subscribe()
     }
 
     pub fn executor(&self) -> &Executor {
         &self.executor
     }
+
+    pub fn storage(&self) -> &Arc<dyn Storage> {
+        &self.storage
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is after the unix epoch")
+        .as_secs()
 }
@@ -0,0 +1,1343 @@
+use anyhow::Context;
+use carol_core::{BinaryId, MachineId};
+use fs2::FileExt;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Durable storage for everything a carol node needs to survive a restart.
+///
+/// [`ExecutorState`](crate::ExecutorState) keeps its own in-memory cache of compiled binaries and
+/// machines and writes through to a `Storage` whenever something new is inserted. On startup the
+/// cache is rebuilt from the `Storage` by recompiling every stored binary.
+pub trait Storage: Send + Sync {
+    /// Whether what is stored survives carol restarting.
+    ///
+    /// Machines may only announce nonces and attest to outcomes if it does. Otherwise the record
+    /// of which outcome was attested to for an event would be lost on restart and the machine
+    /// could be made to sign a second outcome with the same nonce, revealing its secret key.
+    fn is_durable(&self) -> bool;
+    /// Store the raw WASM component bytes of a binary that was uploaded at `created` (seconds
+    /// since the unix epoch).
+    fn put_binary(&self, binary_id: BinaryId, binary: &[u8], created: u64) -> anyhow::Result<()>;
+    /// Get the raw WASM component bytes of a binary.
+    fn get_binary(&self, binary_id: BinaryId) -> anyhow::Result<Option<Vec<u8>>>;
+    /// When a binary was uploaded in seconds since the unix epoch.
+    fn binary_created(&self, binary_id: BinaryId) -> anyhow::Result<Option<u64>>;
+    /// List the ids of every stored binary.
+    fn list_binaries(&self) -> anyhow::Result<Vec<BinaryId>>;
+    /// Remove a binary. Removing a binary that isn't stored does nothing.
+    fn delete_binary(&self, binary_id: BinaryId) -> anyhow::Result<()>;
+    /// Store the binary and parameters a machine was created from at `created` (seconds since the
+    /// unix epoch).
+    fn put_machine(
+        &self,
+        machine_id: MachineId,
+        binary_id: BinaryId,
+        params: &[u8],
+        created: u64,
+    ) -> anyhow::Result<()>;
+    /// Get the binary and parameters a machine was created from.
+    fn get_machine(&self, machine_id: MachineId) -> anyhow::Result<Option<(BinaryId, Vec<u8>)>>;
+    /// When a machine was created in seconds since the unix epoch.
+    fn machine_created(&self, machine_id: MachineId) -> anyhow::Result<Option<u64>>;
+    /// List the ids of every stored machine.
+    fn list_machines(&self) -> anyhow::Result<Vec<MachineId>>;
+    /// Remove a machine along with its state, scheduled outputs and event log.
+    ///
+    /// The outcomes it attested to are kept. The machine would sign with the same keys if it were
+    /// created again and must never attest to a different outcome for the same event.
+    fn delete_machine(&self, machine_id: MachineId) -> anyhow::Result<()>;
+    /// Get the value stored under `key` in a machine's key-value state.
+    fn get_state(&self, machine_id: MachineId, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>>;
+    /// Store `value` under `key` in a machine's key-value state.
+    fn set_state(&self, machine_id: MachineId, key: &[u8], value: &[u8]) -> anyhow::Result<()>;
+    /// Remove `key` from a machine's key-value state.
+    fn delete_state(&self, machine_id: MachineId, key: &[u8]) -> anyhow::Result<()>;
+    /// List every key-value pair in a machine's state whose key starts with `prefix` in key order.
+    fn list_state(
+        &self,
+        machine_id: MachineId,
+        prefix: &[u8],
+    ) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>>;
+    /// Record that a machine signed `outcome` as the outcome of `event_id`.
+    ///
+    /// If an outcome was already recorded for the event it is left as it is and returned.
+    fn record_attestation(
+        &self,
+        machine_id: MachineId,
+        event_id: &[u8],
+        outcome: &[u8],
+    ) -> anyhow::Result<Option<Vec<u8>>>;
+    /// Store the output of a machine's scheduled activation that was due at `time` (seconds since
+    /// the unix epoch).
+    fn put_scheduled_output(
+        &self,
+        machine_id: MachineId,
+        activation: &str,
+        time: u64,
+        output: &[u8],
+    ) -> anyhow::Result<()>;
+    /// Remove all but the `keep` most recent outputs of a machine's scheduled activation.
+    fn prune_scheduled_outputs(
+        &self,
+        machine_id: MachineId,
+        activation: &str,
+        keep: usize,
+    ) -> anyhow::Result<()>;
+    /// List the outputs of a machine's scheduled activation that were due after `since` in time
+    /// order.
+    fn list_scheduled_outputs(
+        &self,
+        machine_id: MachineId,
+        activation: &str,
+        since: u64,
+    ) -> anyhow::Result<Vec<(u64, Vec<u8>)>>;
+    /// Append an event to a machine's event log and return its sequence number.
+    ///
+    /// The first event a machine publishes is 0 and each one after that is one more than the last.
+    fn append_event(
+        &self,
+        machine_id: MachineId,
+        time: u64,
+        topic: &str,
+        data: &[u8],
+    ) -> anyhow::Result<u64>;
+    /// List up to `limit` events from a machine's event log in order starting after the event
+    /// numbered `after` (or from the start if `None`).
+    fn list_events(
+        &self,
+        machine_id: MachineId,
+        after: Option<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Event>>;
+}
+
+/// An entry in a machine's append-only event log.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// The event's sequence number in the machine's log.
+    pub id: u64,
+    /// When the event was published in seconds since the unix epoch.
+    pub time: u64,
+    pub topic: String,
+    pub data: Vec<u8>,
+}
+
+fn events_after(events: &[Event], after: Option<u64>, limit: usize) -> Vec<Event> {
+    let start = after.map(|after| after.saturating_add(1)).unwrap_or(0);
+    events
+        .iter()
+        .skip(usize::try_from(start).unwrap_or(usize::MAX))
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+/// The key-value state of a single machine.
+type MachineState = BTreeMap<Vec<u8>, Vec<u8>>;
+
+fn list_prefix(state: &MachineState, prefix: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+    state
+        .range(prefix.to_vec()..)
+        .take_while(|(key, _)| key.starts_with(prefix))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Scheduled outputs are kept in the same format as state under the activation name followed by a
+/// zero byte and the big-endian time so they are listed in time order.
+fn scheduled_output_key(activation: &str, time: u64) -> Vec<u8> {
+    let mut key = activation.as_bytes().to_vec();
+    key.push(0);
+    key.extend_from_slice(&time.to_be_bytes());
+    key
+}
+
+fn scheduled_outputs_since(
+    outputs: &MachineState,
+    activation: &str,
+    since: u64,
+) -> Vec<(u64, Vec<u8>)> {
+    let prefix = scheduled_output_key(activation, 0);
+    let prefix = &prefix[..prefix.len() - 8];
+    outputs
+        .range(scheduled_output_key(activation, since.saturating_add(1))..)
+        .take_while(|(key, _)| key.len() == prefix.len() + 8 && key.starts_with(prefix))
+        .map(|(key, output)| {
+            let time = u64::from_be_bytes(key[prefix.len()..].try_into().expect("8 bytes"));
+            (time, output.clone())
+        })
+        .collect()
+}
+
+/// Keeps everything in memory so nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryStorage {
+    binaries: Mutex<HashMap<BinaryId, (Vec<u8>, u64)>>,
+    #[allow(clippy::type_complexity)]
+    machines: Mutex<HashMap<MachineId, (BinaryId, Vec<u8>, u64)>>,
+    state: Mutex<HashMap<MachineId, MachineState>>,
+    attestations: Mutex<HashMap<MachineId, MachineState>>,
+    scheduled_outputs: Mutex<HashMap<MachineId, MachineState>>,
+    events: Mutex<HashMap<MachineId, Vec<Event>>>,
+}
+
+impl Storage for MemoryStorage {
+    fn is_durable(&self) -> bool {
+        false
+    }
+
+    fn put_binary(&self, binary_id: BinaryId, binary: &[u8], created: u64) -> anyhow::Result<()> {
+        self.binaries
+            .lock()
+            .unwrap()
+            .insert(binary_id, (binary.to_vec(), created));
+        Ok(())
+    }
+
+    fn get_binary(&self, binary_id: BinaryId) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .binaries
+            .lock()
+            .unwrap()
+            .get(&binary_id)
+            .map(|(binary, _)| binary.clone()))
+    }
+
+    fn binary_created(&self, binary_id: BinaryId) -> anyhow::Result<Option<u64>> {
+        Ok(self
+            .binaries
+            .lock()
+            .unwrap()
+            .get(&binary_id)
+            .map(|(_, created)| *created))
+    }
+
+    fn list_binaries(&self) -> anyhow::Result<Vec<BinaryId>> {
+        Ok(self.binaries.lock().unwrap().keys().copied().collect())
+    }
+
+    fn delete_binary(&self, binary_id: BinaryId) -> anyhow::Result<()> {
+        self.binaries.lock().unwrap().remove(&binary_id);
+        Ok(())
+    }
+
+    fn put_machine(
+        &self,
+        machine_id: MachineId,
+        binary_id: BinaryId,
+        params: &[u8],
+        created: u64,
+    ) -> anyhow::Result<()> {
+        self.machines
+            .lock()
+            .unwrap()
+            .insert(machine_id, (binary_id, params.to_vec(), created));
+        Ok(())
+    }
+
+    fn get_machine(&self, machine_id: MachineId) -> anyhow::Result<Option<(BinaryId, Vec<u8>)>> {
+        Ok(self
+            .machines
+            .lock()
+            .unwrap()
+            .get(&machine_id)
+            .map(|(binary_id, params, _)| (*binary_id, params.clone())))
+    }
+
+    fn machine_created(&self, machine_id: MachineId) -> anyhow::Result<Option<u64>> {
+        Ok(self
+            .machines
+            .lock()
+            .unwrap()
+            .get(&machine_id)
+            .map(|(_, _, created)| *created))
+    }
+
+    fn list_machines(&self) -> anyhow::Result<Vec<MachineId>> {
+        Ok(self.machines.lock().unwrap().keys().copied().collect())
+    }
+
+    fn delete_machine(&self, machine_id: MachineId) -> anyhow::Result<()> {
+        self.machines.lock().unwrap().remove(&machine_id);
+        self.state.lock().unwrap().remove(&machine_id);
+        self.scheduled_outputs.lock().unwrap().remove(&machine_id);
+        self.events.lock().unwrap().remove(&machine_id);
+        Ok(())
+    }
+
+    fn get_state(&self, machine_id: MachineId, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .get(&machine_id)
+            .and_then(|state| state.get(key).cloned()))
+    }
+
+    fn set_state(&self, machine_id: MachineId, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .entry(machine_id)
+            .or_default()
+            .insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete_state(&self, machine_id: MachineId, key: &[u8]) -> anyhow::Result<()> {
+        if let Some(state) = self.state.lock().unwrap().get_mut(&machine_id) {
+            state.remove(key);
+        }
+        Ok(())
+    }
+
+    fn list_state(
+        &self,
+        machine_id: MachineId,
+        prefix: &[u8],
+    ) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .get(&machine_id)
+            .map(|state| list_prefix(state, prefix))
+            .unwrap_or_default())
+    }
+
+    fn record_attestation(
+        &self,
+        machine_id: MachineId,
+        event_id: &[u8],
+        outcome: &[u8],
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut attestations = self.attestations.lock().unwrap();
+        let attestations = attestations.entry(machine_id).or_default();
+        Ok(record_attestation(attestations, event_id, outcome))
+    }
+
+    fn put_scheduled_output(
+        &self,
+        machine_id: MachineId,
+        activation: &str,
+        time: u64,
+        output: &[u8],
+    ) -> anyhow::Result<()> {
+        self.scheduled_outputs
+            .lock()
+            .unwrap()
+            .entry(machine_id)
+            .or_default()
+            .insert(scheduled_output_key(activation, time), output.to_vec());
+        Ok(())
+    }
+
+    fn prune_scheduled_outputs(
+        &self,
+        machine_id: MachineId,
+        activation: &str,
+        keep: usize,
+    ) -> anyhow::Result<()> {
+        if let Some(outputs) = self.scheduled_outputs.lock().unwrap().get_mut(&machine_id) {
+            let times = scheduled_outputs_since(outputs, activation, 0);
+            for (time, _) in &times[..times.len().saturating_sub(keep)] {
+                outputs.remove(&scheduled_output_key(activation, *time));
+            }
+        }
+        Ok(())
+    }
+
+    fn list_scheduled_outputs(
+        &self,
+        machine_id: MachineId,
+        activation: &str,
+        since: u64,
+    ) -> anyhow::Result<Vec<(u64, Vec<u8>)>> {
+        Ok(self
+            .scheduled_outputs
+            .lock()
+            .unwrap()
+            .get(&machine_id)
+            .map(|outputs| scheduled_outputs_since(outputs, activation, since))
+            .unwrap_or_default())
+    }
+
+    fn append_event(
+        &self,
+        machine_id: MachineId,
+        time: u64,
+        topic: &str,
+        data: &[u8],
+    ) -> anyhow::Result<u64> {
+        let mut events = self.events.lock().unwrap();
+        let events = events.entry(machine_id).or_default();
+        let id = events.len() as u64;
+        events.push(Event {
+            id,
+            time,
+            topic: topic.into(),
+            data: data.to_vec(),
+        });
+        Ok(id)
+    }
+
+    fn list_events(
+        &self,
+        machine_id: MachineId,
+        after: Option<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Event>> {
+        Ok(self
+            .events
+            .lock()
+            .unwrap()
+            .get(&machine_id)
+            .map(|events| events_after(events, after, limit))
+            .unwrap_or_default())
+    }
+}
+
+fn record_attestation(
+    attestations: &mut MachineState,
+    event_id: &[u8],
+    outcome: &[u8],
+) -> Option<Vec<u8>> {
+    match attestations.get(event_id) {
+        Some(existing) => Some(existing.clone()),
+        None => {
+            attestations.insert(event_id.to_vec(), outcome.to_vec());
+            None
+        }
+    }
+}
+
+/// Stores everything as plain files under a directory.
+///
+/// Binaries are stored at `binaries/<binary-id>.wasm` and machines at `machines/<machine-id>`
+/// where a machine file is the 32 byte binary id followed by the machine parameters. When each was
+/// created is kept next to it in a `.created` file as an 8 byte big-endian unix timestamp. The key-value
+/// state of each machine is kept in a single file at `state/<machine-id>` which is rewritten on
+/// every change. The outcomes a machine has attested to are kept at `attestations/<machine-id>` in
+/// the same format. Each output of a scheduled activation gets its own file at
+/// `scheduled/<machine-id>/<hex-activation-name>/<time>` so storing one doesn't mean rewriting the
+/// others.
+///
+/// A machine's event log is kept at `events/<machine-id>`. Since it is append-only new events are
+/// appended to the end of the file rather than rewriting it. Each one is encoded as the 8 byte
+/// big-endian time followed by the topic and data in the same format as a state entry. If carol
+/// crashed in the middle of appending an event the partially written event at the end of the log
+/// is ignored and cut off before the next one is appended.
+pub struct DiskStorage {
+    dir: PathBuf,
+    /// Held while a state or attestations file is being read, modified and written back.
+    state_lock: Mutex<()>,
+    /// How many events are in each event log we've appended to so we don't have to read the log
+    /// to number the next one. Held while reading or appending to an event log.
+    event_counts: Mutex<HashMap<MachineId, u64>>,
+    /// Locked for as long as the storage is open so a node and `carol gc` (or two nodes) can't
+    /// use the same directory at once.
+    _lock_file: fs::File,
+}
+
+impl DiskStorage {
+    /// Opens the storage in `dir`, creating it if it doesn't exist.
+    ///
+    /// Fails if something else has it open.
+    pub fn open(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("creating storage directory {}", dir.display()))?;
+        let lock_path = dir.join("lock");
+        let lock_file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("opening {}", lock_path.display()))?;
+        lock_file.try_lock_exclusive().with_context(|| {
+            format!(
+                "locking {}. Is carol (or carol gc) already using this storage?",
+                lock_path.display()
+            )
+        })?;
+        for sub_dir in [
+            "binaries",
+            "machines",
+            "state",
+            "attestations",
+            "scheduled",
+            "events",
+        ] {
+            let path = dir.join(sub_dir);
+            fs::create_dir_all(&path)
+                .with_context(|| format!("creating storage directory {}", path.display()))?;
+        }
+        Ok(Self {
+            dir,
+            state_lock: Mutex::new(()),
+            event_counts: Mutex::new(HashMap::new()),
+            _lock_file: lock_file,
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn binary_path(&self, binary_id: BinaryId) -> PathBuf {
+        self.dir.join("binaries").join(format!("{binary_id}.wasm"))
+    }
+
+    fn machine_path(&self, machine_id: MachineId) -> PathBuf {
+        self.dir.join("machines").join(machine_id.to_string())
+    }
+
+    /// Writes when whatever is at `path` was created.
+    fn put_created(&self, path: &Path, created: u64) -> anyhow::Result<()> {
+        write_atomic(&path.with_extension("created"), &created.to_be_bytes())
+    }
+
+    /// When whatever is at `path` was created. The creation time is written before the thing
+    /// itself so it's an error for it to be missing when the thing exists.
+    fn get_created(&self, path: &Path) -> anyhow::Result<Option<u64>> {
+        let created_path = path.with_extension("created");
+        match read_if_exists(&created_path)? {
+            Some(created) => {
+                let created = <[u8; 8]>::try_from(created.as_slice()).map_err(|_| {
+                    anyhow::anyhow!("creation time file {} is corrupt", created_path.display())
+                })?;
+                Ok(Some(u64::from_be_bytes(created)))
+            }
+            None if path.exists() => Err(anyhow::anyhow!(
+                "creation time file {} is missing",
+                created_path.display()
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn state_path(&self, machine_id: MachineId) -> PathBuf {
+        self.dir.join("state").join(machine_id.to_string())
+    }
+
+    fn attestations_path(&self, machine_id: MachineId) -> PathBuf {
+        self.dir.join("attestations").join(machine_id.to_string())
+    }
+
+    fn scheduled_outputs_dir(&self, machine_id: MachineId, activation: &str) -> PathBuf {
+        self.dir
+            .join("scheduled")
+            .join(machine_id.to_string())
+            .join(carol_core::hex::encode(activation.as_bytes()))
+    }
+
+    /// The times of the stored outputs of a scheduled activation in order.
+    fn scheduled_output_times(
+        &self,
+        machine_id: MachineId,
+        activation: &str,
+    ) -> anyhow::Result<Vec<u64>> {
+        let dir = self.scheduled_outputs_dir(machine_id, activation);
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut times = list_ids::<u64>(&dir, None)?;
+        times.sort();
+        Ok(times)
+    }
+
+    fn events_path(&self, machine_id: MachineId) -> PathBuf {
+        self.dir.join("events").join(machine_id.to_string())
+    }
+
+    /// Reads a machine's event log along with how many bytes of it are complete events.
+    fn read_events(&self, machine_id: MachineId) -> anyhow::Result<(Vec<Event>, u64)> {
+        let path = self.events_path(machine_id);
+        match read_if_exists(&path)? {
+            Some(contents) => decode_events(&contents)
+                .with_context(|| format!("event log {} is corrupt", path.display())),
+            None => Ok((vec![], 0)),
+        }
+    }
+
+    fn read_state(&self, machine_id: MachineId) -> anyhow::Result<MachineState> {
+        read_state_file(&self.state_path(machine_id))
+    }
+
+    fn modify_state(
+        &self,
+        machine_id: MachineId,
+        modify: impl FnOnce(&mut MachineState),
+    ) -> anyhow::Result<()> {
+        let _guard = self.state_lock.lock().unwrap();
+        let mut state = self.read_state(machine_id)?;
+        modify(&mut state);
+        write_atomic(&self.state_path(machine_id), &encode_state(&state))
+    }
+}
+
+fn read_state_file(path: &Path) -> anyhow::Result<MachineState> {
+    match read_if_exists(path)? {
+        Some(contents) => decode_state(&contents)
+            .with_context(|| format!("state file {} is corrupt", path.display())),
+        None => Ok(MachineState::new()),
+    }
+}
+
+/// Encodes each entry as a 4 byte big-endian key length, the key, a 4 byte big-endian value length
+/// and the value.
+fn encode_state(state: &MachineState) -> Vec<u8> {
+    let mut bytes = vec![];
+    for (key, value) in state {
+        for field in [key, value] {
+            bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(field);
+        }
+    }
+    bytes
+}
+
+fn take_field(bytes: &mut &[u8]) -> anyhow::Result<Vec<u8>> {
+    if bytes.len() < 4 {
+        return Err(anyhow::anyhow!("truncated length"));
+    }
+    let (len, rest) = bytes.split_at(4);
+    let len = u32::from_be_bytes(len.try_into().expect("correct length")) as usize;
+    if rest.len() < len {
+        return Err(anyhow::anyhow!("truncated field"));
+    }
+    let (field, rest) = rest.split_at(len);
+    *bytes = rest;
+    Ok(field.to_vec())
+}
+
+fn decode_state(mut bytes: &[u8]) -> anyhow::Result<MachineState> {
+    let mut state = MachineState::new();
+    while !bytes.is_empty() {
+        let key = take_field(&mut bytes)?;
+        let value = take_field(&mut bytes)?;
+        state.insert(key, value);
+    }
+    Ok(state)
+}
+
+fn encode_event(time: u64, topic: &str, data: &[u8]) -> Vec<u8> {
+    let mut bytes = time.to_be_bytes().to_vec();
+    for field in [topic.as_bytes(), data] {
+        bytes.extend_from_slice(&(field.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(field);
+    }
+    bytes
+}
+
+/// The length of the event at the start of `bytes` or `None` if it isn't all there.
+fn event_len(bytes: &[u8]) -> Option<usize> {
+    let mut len = 8;
+    for _ in 0..2 {
+        let field_len = bytes.get(len..len + 4)?;
+        len += 4 + u32::from_be_bytes(field_len.try_into().expect("correct length")) as usize;
+    }
+    (len <= bytes.len()).then_some(len)
+}
+
+/// Decodes the events in a log along with how many bytes they take up. Anything after that is an
+/// event that was only partly written.
+fn decode_events(mut bytes: &[u8]) -> anyhow::Result<(Vec<Event>, u64)> {
+    let mut events = vec![];
+    let mut complete_len = 0;
+    while let Some(len) = event_len(bytes) {
+        complete_len += len as u64;
+        let (time, rest) = bytes.split_at(8);
+        bytes = rest;
+        let time = u64::from_be_bytes(time.try_into().expect("correct length"));
+        let topic = String::from_utf8(take_field(&mut bytes)?).context("topic isn't utf-8")?;
+        let data = take_field(&mut bytes)?;
+        events.push(Event {
+            id: events.len() as u64,
+            time,
+            topic,
+            data,
+        });
+    }
+    Ok((events, complete_len))
+}
+
+/// Write to a temporary file first and then move it into place so a crash never leaves a partially
+/// written file behind. The file and then the directory it's in are synced to disk before
+/// returning so once this returns `Ok` the new contents survive a crash or power loss.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    // every write gets its own temporary file so concurrent writes to the same file (or files that
+    // only differ by extension) can't move each other's contents into place
+    static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut tmp_name = path
+        .file_name()
+        .with_context(|| format!("{} has no file name", path.display()))?
+        .to_os_string();
+    tmp_name.push(format!(
+        ".{}-{}.tmp",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = path.with_file_name(tmp_name);
+    let mut tmp_file =
+        fs::File::create(&tmp_path).with_context(|| format!("creating {}", tmp_path.display()))?;
+    tmp_file
+        .write_all(contents)
+        .and_then(|()| tmp_file.sync_all())
+        .with_context(|| format!("writing to {}", tmp_path.display()))?;
+    drop(tmp_file);
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("moving {} to {}", tmp_path.display(), path.display()))?;
+    // the rename is only durable once the directory entry is
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir)?,
+        _ => sync_dir(Path::new("."))?,
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> anyhow::Result<()> {
+    fs::File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("syncing directory {}", dir.display()))
+}
+
+/// Directories can't be opened (or synced) on other platforms.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> anyhow::Result<()> {
+    Ok(())
+}
+
+pub(crate) fn read_if_exists(path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+fn remove_if_exists(path: &Path) -> anyhow::Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("removing {}", path.display())),
+    }
+}
+
+/// Lists the files in `dir` whose names parse as `T` once `extension` is stripped.
+fn list_ids<T: FromStr>(dir: &Path, extension: Option<&str>) -> anyhow::Result<Vec<T>> {
+    let mut ids = vec![];
+    for entry in fs::read_dir(dir).with_context(|| format!("listing {}", dir.display()))? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != extension {
+            continue;
+        }
+        if let Some(id) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| T::from_str(stem).ok())
+        {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+impl Storage for DiskStorage {
+    fn is_durable(&self) -> bool {
+        true
+    }
+
+    fn put_binary(&self, binary_id: BinaryId, binary: &[u8], created: u64) -> anyhow::Result<()> {
+        let path = self.binary_path(binary_id);
+        self.put_created(&path, created)?;
+        write_atomic(&path, binary)
+    }
+
+    fn get_binary(&self, binary_id: BinaryId) -> anyhow::Result<Option<Vec<u8>>> {
+        read_if_exists(&self.binary_path(binary_id))
+    }
+
+    fn binary_created(&self, binary_id: BinaryId) -> anyhow::Result<Option<u64>> {
+        self.get_created(&self.binary_path(binary_id))
+    }
+
+    fn delete_binary(&self, binary_id: BinaryId) -> anyhow::Result<()> {
+        let path = self.binary_path(binary_id);
+        remove_if_exists(&path)?;
+        remove_if_exists(&path.with_extension("created"))
+    }
+
+    fn list_binaries(&self) -> anyhow::Result<Vec<BinaryId>> {
+        list_ids(&self.dir.join("binaries"), Some("wasm"))
+    }
+
+    fn put_machine(
+        &self,
+        machine_id: MachineId,
+        binary_id: BinaryId,
+        params: &[u8],
+        created: u64,
+    ) -> anyhow::Result<()> {
+        let path = self.machine_path(machine_id);
+        let mut contents = binary_id.to_bytes().to_vec();
+        contents.extend_from_slice(params);
+        self.put_created(&path, created)?;
+        write_atomic(&path, &contents)
+    }
+
+    fn get_machine(&self, machine_id: MachineId) -> anyhow::Result<Option<(BinaryId, Vec<u8>)>> {
+        let path = self.machine_path(machine_id);
+        let contents = match read_if_exists(&path)? {
+            Some(contents) => contents,
+            None => return Ok(None),
+        };
+        if contents.len() < 32 {
+            return Err(anyhow::anyhow!(
+                "machine file {} is corrupt: too short",
+                path.display()
+            ));
+        }
+        let binary_id = BinaryId::from_slice(&contents[..32]).expect("correct length");
+        Ok(Some((binary_id, contents[32..].to_vec())))
+    }
+
+    fn list_machines(&self) -> anyhow::Result<Vec<MachineId>> {
+        list_ids(&self.dir.join("machines"), None)
+    }
+
+    fn machine_created(&self, machine_id: MachineId) -> anyhow::Result<Option<u64>> {
+        self.get_created(&self.machine_path(machine_id))
+    }
+
+    fn delete_machine(&self, machine_id: MachineId) -> anyhow::Result<()> {
+        let path = self.machine_path(machine_id);
+        // remove the machine first so it's never loaded with half of its data missing
+        remove_if_exists(&path)?;
+        remove_if_exists(&path.with_extension("created"))?;
+        {
+            let _guard = self.state_lock.lock().unwrap();
+            remove_if_exists(&self.state_path(machine_id))?;
+        }
+        let scheduled_dir = self.dir.join("scheduled").join(machine_id.to_string());
+        match fs::remove_dir_all(&scheduled_dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("removing {}", scheduled_dir.display()))
+            }
+            _ => {}
+        }
+        let mut event_counts = self.event_counts.lock().unwrap();
+        event_counts.remove(&machine_id);
+        remove_if_exists(&self.events_path(machine_id))
+    }
+
+    fn get_state(&self, machine_id: MachineId, key: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let _guard = self.state_lock.lock().unwrap();
+        Ok(self.read_state(machine_id)?.remove(key))
+    }
+
+    fn set_state(&self, machine_id: MachineId, key: &[u8], value: &[u8]) -> anyhow::Result<()> {
+        self.modify_state(machine_id, |state| {
+            state.insert(key.to_vec(), value.to_vec());
+        })
+    }
+
+    fn delete_state(&self, machine_id: MachineId, key: &[u8]) -> anyhow::Result<()> {
+        self.modify_state(machine_id, |state| {
+            state.remove(key);
+        })
+    }
+
+    fn list_state(
+        &self,
+        machine_id: MachineId,
+        prefix: &[u8],
+    ) -> anyhow::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let _guard = self.state_lock.lock().unwrap();
+        Ok(list_prefix(&self.read_state(machine_id)?, prefix))
+    }
+
+    fn record_attestation(
+        &self,
+        machine_id: MachineId,
+        event_id: &[u8],
+        outcome: &[u8],
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let _guard = self.state_lock.lock().unwrap();
+        let path = self.attestations_path(machine_id);
+        let mut attestations = read_state_file(&path)?;
+        let existing = record_attestation(&mut attestations, event_id, outcome);
+        if existing.is_none() {
+            write_atomic(&path, &encode_state(&attestations))?;
+        }
+        Ok(existing)
+    }
+
+    fn put_scheduled_output(
+        &self,
+        machine_id: MachineId,
+        activation: &str,
+        time: u64,
+        output: &[u8],
+    ) -> anyhow::Result<()> {
+        let dir = self.scheduled_outputs_dir(machine_id, activation);
+        fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+        write_atomic(&dir.join(time.to_string()), output)
+    }
+
+    fn prune_scheduled_outputs(
+        &self,
+        machine_id: MachineId,
+        activation: &str,
+        keep: usize,
+    ) -> anyhow::Result<()> {
+        let dir = self.scheduled_outputs_dir(machine_id, activation);
+        let times = self.scheduled_output_times(machine_id, activation)?;
+        for time in &times[..times.len().saturating_sub(keep)] {
+            remove_if_exists(&dir.join(time.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn list_scheduled_outputs(
+        &self,
+        machine_id: MachineId,
+        activation: &str,
+        since: u64,
+    ) -> anyhow::Result<Vec<(u64, Vec<u8>)>> {
+        let dir = self.scheduled_outputs_dir(machine_id, activation);
+        let mut outputs = vec![];
+        for time in self.scheduled_output_times(machine_id, activation)? {
+            if time <= since {
+                continue;
+            }
+            // it may have been pruned since we listed it
+            if let Some(output) = read_if_exists(&dir.join(time.to_string()))? {
+                outputs.push((time, output));
+            }
+        }
+        Ok(outputs)
+    }
+
+    fn append_event(
+        &self,
+        machine_id: MachineId,
+        time: u64,
+        topic: &str,
+        data: &[u8],
+    ) -> anyhow::Result<u64> {
+        let mut event_counts = self.event_counts.lock().unwrap();
+        let path = self.events_path(machine_id);
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        let id = match event_counts.get(&machine_id) {
+            Some(count) => *count,
+            None => {
+                let (events, complete_len) = self.read_events(machine_id)?;
+                // cut off an event we crashed in the middle of appending
+                file.set_len(complete_len)
+                    .with_context(|| format!("truncating {}", path.display()))?;
+                events.len() as u64
+            }
+        };
+        let result = file
+            .write_all(&encode_event(time, topic, data))
+            .and_then(|_| file.sync_data())
+            .with_context(|| format!("appending to {}", path.display()));
+        match result {
+            Ok(()) => {
+                event_counts.insert(machine_id, id + 1);
+                Ok(id)
+            }
+            Err(e) => {
+                // part of the event may have been written so check the log again next time
+                event_counts.remove(&machine_id);
+                Err(e)
+            }
+        }
+    }
+
+    fn list_events(
+        &self,
+        machine_id: MachineId,
+        after: Option<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Event>> {
+        let _guard = self.event_counts.lock().unwrap();
+        Ok(events_after(&self.read_events(machine_id)?.0, after, limit))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn concurrent_atomic_writes_dont_clobber_each_other() {
+        let dir = tempfile::tempdir().unwrap();
+        let writers = ["x.wasm", "x.created", "x.wasm", "x.created"]
+            .into_iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                std::thread::spawn(move || {
+                    for _ in 0..100 {
+                        write_atomic(&path, name.as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        for name in ["x.wasm", "x.created"] {
+            assert_eq!(fs::read(dir.path().join(name)).unwrap(), name.as_bytes());
+        }
+        // no temporary files are left behind
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn disk_storage_can_only_be_opened_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = DiskStorage::open(dir.path()).unwrap();
+        assert!(DiskStorage::open(dir.path()).is_err());
+        drop(storage);
+        DiskStorage::open(dir.path()).unwrap();
+    }
+
+    #[test]
+    fn disk_storage_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = b"not really wasm";
+        let binary_id = BinaryId::new(binary);
+        let params = b"params";
+        let machine_id = MachineId::new(binary_id, params);
+
+        {
+            let storage = DiskStorage::open(dir.path()).unwrap();
+            storage.put_binary(binary_id, binary, 1_000).unwrap();
+            storage
+                .put_machine(machine_id, binary_id, params, 2_000)
+                .unwrap();
+        }
+
+        let storage = DiskStorage::open(dir.path()).unwrap();
+        assert_eq!(storage.list_binaries().unwrap(), vec![binary_id]);
+        assert_eq!(storage.get_binary(binary_id).unwrap().unwrap(), binary);
+        assert_eq!(storage.list_machines().unwrap(), vec![machine_id]);
+        assert_eq!(
+            storage.get_machine(machine_id).unwrap(),
+            Some((binary_id, params.to_vec()))
+        );
+        assert_eq!(storage.binary_created(binary_id).unwrap(), Some(1_000));
+        assert_eq!(storage.machine_created(machine_id).unwrap(), Some(2_000));
+        assert_eq!(
+            storage
+                .binary_created(BinaryId::new(b"other binary"))
+                .unwrap(),
+            None
+        );
+
+        fs::remove_file(storage.binary_path(binary_id).with_extension("created")).unwrap();
+        assert!(storage.binary_created(binary_id).is_err());
+    }
+
+    #[test]
+    fn disk_storage_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = b"not really wasm";
+        let binary_id = BinaryId::new(binary);
+        let machine_id = MachineId::new(binary_id, b"params");
+        let storage = DiskStorage::open(dir.path()).unwrap();
+        storage.put_binary(binary_id, binary, 1_000).unwrap();
+        storage
+            .put_machine(machine_id, binary_id, b"params", 2_000)
+            .unwrap();
+        storage.set_state(machine_id, b"key", b"value").unwrap();
+        storage
+            .append_event(machine_id, 10, "topic", b"data")
+            .unwrap();
+        storage
+            .record_attestation(machine_id, b"event", b"outcome")
+            .unwrap();
+
+        storage.delete_machine(machine_id).unwrap();
+        storage.delete_binary(binary_id).unwrap();
+        // deleting twice is fine
+        storage.delete_machine(machine_id).unwrap();
+
+        drop(storage);
+        let storage = DiskStorage::open(dir.path()).unwrap();
+        assert!(storage.list_binaries().unwrap().is_empty());
+        assert!(storage.list_machines().unwrap().is_empty());
+        assert_eq!(storage.get_binary(binary_id).unwrap(), None);
+        assert_eq!(storage.binary_created(binary_id).unwrap(), None);
+        assert_eq!(storage.machine_created(machine_id).unwrap(), None);
+        assert_eq!(storage.get_state(machine_id, b"key").unwrap(), None);
+        assert!(storage
+            .list_events(machine_id, None, 10)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            storage
+                .record_attestation(machine_id, b"event", b"other outcome")
+                .unwrap(),
+            Some(b"outcome".to_vec())
+        );
+    }
+
+    #[test]
+    fn disk_storage_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let machine_id = MachineId::new(BinaryId::new(b"binary"), b"params");
+        let other_machine_id = MachineId::new(BinaryId::new(b"binary"), b"other params");
+
+        {
+            let storage = DiskStorage::open(dir.path()).unwrap();
+            storage.set_state(machine_id, b"a/1", b"one").unwrap();
+            storage.set_state(machine_id, b"a/2", b"two").unwrap();
+            storage.set_state(machine_id, b"b", b"three").unwrap();
+            storage.set_state(machine_id, b"", b"empty key").unwrap();
+            storage.delete_state(machine_id, b"b").unwrap();
+            storage
+                .set_state(other_machine_id, b"a/3", b"other")
+                .unwrap();
+        }
+
+        let storage = DiskStorage::open(dir.path()).unwrap();
+        assert_eq!(
+            storage.get_state(machine_id, b"a/1").unwrap(),
+            Some(b"one".to_vec())
+        );
+        assert_eq!(storage.get_state(machine_id, b"b").unwrap(), None);
+        assert_eq!(
+            storage.get_state(machine_id, b"").unwrap(),
+            Some(b"empty key".to_vec())
+        );
+        assert_eq!(
+            storage.list_state(machine_id, b"a/").unwrap(),
+            vec![
+                (b"a/1".to_vec(), b"one".to_vec()),
+                (b"a/2".to_vec(), b"two".to_vec())
+            ]
+        );
+        assert_eq!(storage.list_state(machine_id, b"").unwrap().len(), 3);
+        assert_eq!(
+            storage.list_state(other_machine_id, b"a/").unwrap(),
+            vec![(b"a/3".to_vec(), b"other".to_vec())]
+        );
+    }
+
+    #[test]
+    fn disk_storage_attestations() {
+        let dir = tempfile::tempdir().unwrap();
+        let machine_id = MachineId::new(BinaryId::new(b"binary"), b"params");
+        let other_machine_id = MachineId::new(BinaryId::new(b"binary"), b"other params");
+
+        {
+            let storage = DiskStorage::open(dir.path()).unwrap();
+            assert_eq!(
+                storage
+                    .record_attestation(machine_id, b"event", b"heads")
+                    .unwrap(),
+                None
+            );
+        }
+
+        let storage = DiskStorage::open(dir.path()).unwrap();
+        assert_eq!(
+            storage
+                .record_attestation(machine_id, b"event", b"tails")
+                .unwrap(),
+            Some(b"heads".to_vec())
+        );
+        assert_eq!(
+            storage
+                .record_attestation(other_machine_id, b"event", b"tails")
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn disk_storage_scheduled_outputs() {
+        let dir = tempfile::tempdir().unwrap();
+        let machine_id = MachineId::new(BinaryId::new(b"binary"), b"params");
+
+        {
+            let storage = DiskStorage::open(dir.path()).unwrap();
+            storage
+                .put_scheduled_output(machine_id, "tick", 120, b"two")
+                .unwrap();
+            storage
+                .put_scheduled_output(machine_id, "tick", 60, b"one")
+                .unwrap();
+            storage
+                .put_scheduled_output(machine_id, "tick", 180, b"three")
+                .unwrap();
+            storage
+                .put_scheduled_output(machine_id, "tick_tock", 60, b"other")
+                .unwrap();
+        }
+
+        let storage = DiskStorage::open(dir.path()).unwrap();
+        assert_eq!(
+            storage
+                .list_scheduled_outputs(machine_id, "tick", 0)
+                .unwrap(),
+            vec![
+                (60, b"one".to_vec()),
+                (120, b"two".to_vec()),
+                (180, b"three".to_vec())
+            ]
+        );
+        assert_eq!(
+            storage
+                .list_scheduled_outputs(machine_id, "tick", 120)
+                .unwrap(),
+            vec![(180, b"three".to_vec())]
+        );
+        assert_eq!(
+            storage
+                .list_scheduled_outputs(machine_id, "tick_tock", 0)
+                .unwrap(),
+            vec![(60, b"other".to_vec())]
+        );
+        assert!(storage
+            .list_scheduled_outputs(machine_id, "tock", 0)
+            .unwrap()
+            .is_empty());
+
+        storage
+            .prune_scheduled_outputs(machine_id, "tick", 2)
+            .unwrap();
+        assert_eq!(
+            storage
+                .list_scheduled_outputs(machine_id, "tick", 0)
+                .unwrap(),
+            vec![(120, b"two".to_vec()), (180, b"three".to_vec())]
+        );
+        assert_eq!(
+            storage
+                .list_scheduled_outputs(machine_id, "tick_tock", 0)
+                .unwrap(),
+            vec![(60, b"other".to_vec())]
+        );
+
+        storage.delete_machine(machine_id).unwrap();
+        assert!(storage
+            .list_scheduled_outputs(machine_id, "tick", 0)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn disk_storage_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let machine_id = MachineId::new(BinaryId::new(b"binary"), b"params");
+        let other_machine_id = MachineId::new(BinaryId::new(b"binary"), b"other params");
+
+        {
+            let storage = DiskStorage::open(dir.path()).unwrap();
+            assert_eq!(
+                storage.append_event(machine_id, 10, "a", b"one").unwrap(),
+                0
+            );
+            assert_eq!(storage.append_event(machine_id, 20, "b", b"").unwrap(), 1);
+            assert_eq!(
+                storage
+                    .append_event(other_machine_id, 20, "a", b"other")
+                    .unwrap(),
+                0
+            );
+        }
+
+        let storage = DiskStorage::open(dir.path()).unwrap();
+        assert_eq!(
+            storage.append_event(machine_id, 30, "a", b"three").unwrap(),
+            2
+        );
+        let events = storage.list_events(machine_id, None, 10).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                Event {
+                    id: 0,
+                    time: 10,
+                    topic: "a".into(),
+                    data: b"one".to_vec()
+                },
+                Event {
+                    id: 1,
+                    time: 20,
+                    topic: "b".into(),
+                    data: vec![]
+                },
+                Event {
+                    id: 2,
+                    time: 30,
+                    topic: "a".into(),
+                    data: b"three".to_vec()
+                },
+            ]
+        );
+        assert_eq!(
+            storage.list_events(machine_id, Some(0), 1).unwrap(),
+            events[1..2]
+        );
+        assert!(storage
+            .list_events(machine_id, Some(2), 10)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            storage
+                .list_events(other_machine_id, None, 10)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn disk_storage_events_survive_torn_append() {
+        let dir = tempfile::tempdir().unwrap();
+        let machine_id = MachineId::new(BinaryId::new(b"binary"), b"params");
+        {
+            let storage = DiskStorage::open(dir.path()).unwrap();
+            storage.append_event(machine_id, 10, "a", b"one").unwrap();
+            // as if carol crashed half way through appending an event
+            let torn = encode_event(20, "b", b"two");
+            fs::OpenOptions::new()
+                .append(true)
+                .open(storage.events_path(machine_id))
+                .unwrap()
+                .write_all(&torn[..torn.len() - 1])
+                .unwrap();
+            assert_eq!(storage.list_events(machine_id, None, 10).unwrap().len(), 1);
+        }
+
+        let storage = DiskStorage::open(dir.path()).unwrap();
+        assert_eq!(
+            storage.append_event(machine_id, 30, "c", b"three").unwrap(),
+            1
+        );
+        let events = storage.list_events(machine_id, None, 10).unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| (event.id, event.topic.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(0, "a"), (1, "c")]
+        );
+    }
+}
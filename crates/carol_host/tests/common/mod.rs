@@ -0,0 +1,144 @@
+// each test only uses some of this
+#![allow(dead_code)]
+
+/// A host interface a [`Guest`] imports.
+pub struct Import<'a> {
+    /// The name of the interface e.g. `http-body` for `carol:machine/http-body@0.1.0`.
+    pub interface: &'a str,
+    /// The declarations inside the interface's instance type.
+    pub instance_type: &'a str,
+    /// The functions the guest calls along with the signature of their lowered core function.
+    pub funcs: &'a [(&'a str, &'a str)],
+}
+
+/// A guest component written by hand so we can make it misbehave in ways a Rust guest wouldn't.
+///
+/// The exports run the bodies given here (or `unreachable` if they are left out) and can call the
+/// functions it imports by their names e.g. `(call $read ...)`. Memory is allocated from 8192
+/// upwards.
+#[derive(Default)]
+pub struct Guest<'a> {
+    pub imports: &'a [Import<'a>],
+    /// Extra fields for the core module like data segments.
+    pub module_fields: &'a str,
+    pub get_binary_api: &'a str,
+    pub activate: &'a str,
+    pub handle_http: &'a str,
+    pub params_from_json: &'a str,
+    pub params_to_json: &'a str,
+}
+
+impl Guest<'_> {
+    pub fn build(&self) -> Vec<u8> {
+        let body = |body: &str| {
+            if body.is_empty() {
+                "unreachable".to_string()
+            } else {
+                body.to_string()
+            }
+        };
+        let (get_binary_api, activate, handle_http, params_from_json, params_to_json) = (
+            body(self.get_binary_api),
+            body(self.activate),
+            body(self.handle_http),
+            body(self.params_from_json),
+            body(self.params_to_json),
+        );
+        let module_fields = self.module_fields;
+        let mut imports = String::new();
+        let mut lowered = String::new();
+        let mut core_imports = String::new();
+        let mut with_imports = String::new();
+        let mut instantiate_with = String::new();
+        for import in self.imports {
+            let (name, instance_type) = (import.interface, import.instance_type);
+            imports += &format!(
+                "(import (interface \"carol:machine/{name}@0.1.0\") (instance ${name} {instance_type}))\n"
+            );
+            instantiate_with +=
+                &format!("(with \"carol:machine/{name}@0.1.0\" (instance ${name}))\n");
+            let mut exports = String::new();
+            for (func, signature) in import.funcs {
+                lowered += &format!(
+                    "(core func ${func} (canon lower (func ${name} \"{func}\") (memory $mem \"memory\") (realloc (func $mem \"realloc\"))))\n"
+                );
+                core_imports +=
+                    &format!("(import \"{name}\" \"{func}\" (func ${func} {signature}))\n");
+                exports += &format!("(export \"{func}\" (func ${func}))");
+            }
+            with_imports += &format!("(with \"{name}\" (instance {exports}))\n");
+        }
+        let wat = format!(
+            r#"
+(component
+  {imports}
+  (component $guest
+    {imports}
+    (core module $mem
+      (memory (export "memory") 1)
+      (global $next (mut i32) (i32.const 8192))
+      ;; a bump allocator that never frees anything
+      (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+        (local $ptr i32)
+        (local.set $ptr
+          (i32.and
+            (i32.add (global.get $next) (i32.sub (local.get 2) (i32.const 1)))
+            (i32.sub (i32.const 0) (local.get 2))))
+        (global.set $next (i32.add (local.get $ptr) (local.get 3)))
+        (local.get $ptr))
+    )
+    (core instance $mem (instantiate $mem))
+    {lowered}
+    (core module $m
+      (import "mem" "memory" (memory 1))
+      {core_imports}
+      (table 1 funcref)
+      {module_fields}
+      (func (export "get-binary-api") (result i32) {get_binary_api})
+      (func (export "activate") (param i32 i32 i32 i32 i32 i32) (result i32) {activate})
+      (func (export "handle-http") (param i32 i32 i32 i32 i32 i32 i32) (result i32) {handle_http})
+      (func (export "params-from-json") (param i32 i32) (result i32) {params_from_json})
+      (func (export "params-to-json") (param i32 i32) (result i32) {params_to_json})
+    )
+    (core instance $i (instantiate $m
+      (with "mem" (instance $mem))
+      {with_imports}))
+    (type $activation-description' (record (field "name" string) (field "schedule" (option string))))
+    (export $activation-description "activation-description" (type $activation-description'))
+    (type $binary-api' (record (field "activations" (list $activation-description))))
+    (export $binary-api "binary-api" (type $binary-api'))
+    (type $method' (enum "get" "post" "put" "patch" "delete"))
+    (export $method "method" (type $method'))
+    (type $headers (list (tuple string (list u8))))
+    (type $request' (record (field "method" $method) (field "uri" string) (field "headers" $headers) (field "body" (list u8))))
+    (export $request "request" (type $request'))
+    (type $response' (record (field "headers" $headers) (field "body" (list u8)) (field "status" u16)))
+    (export $response "response" (type $response'))
+    (func (export "get-binary-api") (result $binary-api)
+      (canon lift (core func $i "get-binary-api") (memory $mem "memory") (realloc (func $mem "realloc"))))
+    (func (export "activate") (param "machine-params" (list u8)) (param "activation" string) (param "input" (list u8)) (result (list u8))
+      (canon lift (core func $i "activate") (memory $mem "memory") (realloc (func $mem "realloc"))))
+    (func (export "handle-http") (param "request" $request) (result $response)
+      (canon lift (core func $i "handle-http") (memory $mem "memory") (realloc (func $mem "realloc"))))
+    (func (export "params-from-json") (param "json" string) (result (result (list u8) (error string)))
+      (canon lift (core func $i "params-from-json") (memory $mem "memory") (realloc (func $mem "realloc"))))
+    (func (export "params-to-json") (param "machine-params" (list u8)) (result (result string (error string)))
+      (canon lift (core func $i "params-to-json") (memory $mem "memory") (realloc (func $mem "realloc"))))
+  )
+  (instance $guest (instantiate $guest {instantiate_with}))
+  (export (interface "carol:machine/guest@0.1.0") (instance $guest))
+)
+"#
+        );
+        wat::parse_str(wat).unwrap()
+    }
+}
+
+/// A guest that only imports what every guest does and whose `activate` body is `activate_body`.
+pub fn guest_component(activate_body: &str) -> Vec<u8> {
+    Guest {
+        activate: activate_body,
+        ..Default::default()
+    }
+    .build()
+}
@@ -0,0 +1,126 @@
+use carol_host::{Executor, ExecutorConfig, State};
+use hyper::body::HttpBody;
+
+mod common;
+use common::{Guest, Import};
+
+/// A guest whose HTTP handler starts a `201` response, streams the request body back to the client
+/// one chunk at a time and then returns a response whose body is `!` (and whose status should be
+/// ignored since the response was already started).
+fn echo_guest_component() -> Vec<u8> {
+    Guest {
+        imports: &[Import {
+            interface: "http-body",
+            instance_type: r#"
+    (type $error' (variant (case "too-large" u64) (case "closed" string)))
+    (export $error "error" (type (eq $error')))
+    (export "read" (func (result (result (list u8) (error $error)))))
+    (export "start-response" (func (param "status" u16) (param "headers" (list (tuple string (list u8))))))
+    (export "write" (func (param "chunk" (list u8)) (result (result (error $error)))))
+"#,
+            funcs: &[
+                ("read", "(param i32)"),
+                ("start-response", "(param i32 i32 i32)"),
+                ("write", "(param i32 i32 i32)"),
+            ],
+        }],
+        module_fields: r#"(data (i32.const 100) "!")"#,
+        handle_http: r#"
+      (call $start-response (i32.const 201) (i32.const 0) (i32.const 0))
+      (block $done
+        (loop $next
+          (call $read (i32.const 4096))
+          ;; stop on an error or once the body is empty
+          (br_if $done (i32.load8_u (i32.const 4096)))
+          (br_if $done (i32.eqz (i32.load (i32.const 4108))))
+          (call $write (i32.load (i32.const 4104)) (i32.load (i32.const 4108)) (i32.const 4200))
+          (br $next)))
+      (i32.store (i32.const 2048) (i32.const 0))
+      (i32.store (i32.const 2052) (i32.const 0))
+      (i32.store (i32.const 2056) (i32.const 100))
+      (i32.store (i32.const 2060) (i32.const 1))
+      (i32.store16 (i32.const 2064) (i32.const 500))
+      i32.const 2048"#,
+        ..Default::default()
+    }
+    .build()
+}
+
+async fn echo(executor: &Executor, body: hyper::Body) -> (u16, Vec<u8>) {
+    let binary = echo_guest_component();
+    let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+    let state = State::new(
+        carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+    );
+    let request = http_crate::Request::post("/echo").body(body).unwrap();
+    let response = executor
+        .machine_handle_http_request(state, compiled_binary.into(), &[], request)
+        .await
+        .unwrap()
+        .unwrap()
+        .output;
+    let status = response.status().as_u16();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, body.to_vec())
+}
+
+#[tokio::test]
+async fn request_and_response_bodies_are_streamed() {
+    let executor = Executor::new();
+    let (mut sender, body) = hyper::Body::channel();
+    tokio::spawn(async move {
+        for chunk in ["hello", " ", "world"] {
+            sender.send_data(chunk.into()).await.unwrap();
+        }
+    });
+    assert_eq!(echo(&executor, body).await, (201, b"hello world!".to_vec()));
+}
+
+#[tokio::test]
+async fn request_body_size_is_limited() {
+    let executor = Executor::with_config(ExecutorConfig {
+        max_request_body_bytes: Some(8),
+        ..Default::default()
+    });
+    let (mut sender, body) = hyper::Body::channel();
+    tokio::spawn(async move {
+        for chunk in ["hello", " ", "world"] {
+            sender.send_data(chunk.into()).await.unwrap();
+        }
+    });
+    // the guest stops reading when it is told the body is too large
+    assert_eq!(echo(&executor, body).await, (201, b"hello !".to_vec()));
+}
+
+#[tokio::test]
+async fn response_starts_before_the_request_body_is_sent() {
+    let executor = Executor::new();
+    let binary = echo_guest_component();
+    let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+    let state = State::new(
+        carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+    );
+    let (mut sender, request_body) = hyper::Body::channel();
+    let request = http_crate::Request::post("/echo")
+        .body(request_body)
+        .unwrap();
+    let mut response = executor
+        .machine_handle_http_request(state, compiled_binary.into(), &[], request)
+        .await
+        .unwrap()
+        .unwrap()
+        .output;
+    assert_eq!(response.status().as_u16(), 201);
+
+    // each chunk comes straight back
+    for chunk in ["ping", "pong"] {
+        sender.send_data(chunk.into()).await.unwrap();
+        let echoed = response.body_mut().data().await.unwrap().unwrap();
+        assert_eq!(echoed, chunk.as_bytes());
+    }
+    drop(sender);
+    let rest = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(rest, "!".as_bytes());
+}
@@ -0,0 +1,42 @@
+use carol_bls::bls12_381::G1Affine;
+use carol_core::MachineId;
+use carol_host::{Executor, State};
+
+mod common;
+use common::{Guest, Import};
+
+#[tokio::test]
+async fn bls_machine_pubkey_is_what_the_guest_decodes() {
+    let executor = Executor::new();
+    // returns the list `bls-machine-pubkey` wrote to 4096
+    let binary = Guest {
+        imports: &[Import {
+            interface: "global",
+            instance_type: r#"(export "bls-machine-pubkey" (func (result (list u8))))"#,
+            funcs: &[("bls-machine-pubkey", "(param i32)")],
+        }],
+        activate: "(call $bls-machine-pubkey (i32.const 4096)) i32.const 4096",
+        ..Default::default()
+    }
+    .build();
+    let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+    let bls_keypair = carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap();
+    let state = State::new(
+        bls_keypair,
+        carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+    );
+    let output = executor
+        .activate_machine(state, &compiled_binary, &[], "pubkey", &[])
+        .await
+        .unwrap()
+        .unwrap()
+        .output;
+
+    // the same way carol_guest decodes it
+    let bytes: [u8; 96] = output.try_into().unwrap();
+    let machine_id = MachineId::new(compiled_binary.binary_id(), &[]);
+    assert_eq!(
+        G1Affine::from_uncompressed(&bytes).unwrap(),
+        bls_keypair.derive_for_machine(machine_id).public_key().0
+    );
+}
@@ -0,0 +1,123 @@
+use carol_host::{Executor, ExecutorConfig, GuestError, State};
+use std::time::Duration;
+
+mod common;
+use common::guest_component;
+
+async fn activate(executor: &Executor, binary: &[u8]) -> Result<Vec<u8>, GuestError> {
+    let compiled_binary = executor.load_binary_from_wasm_binary(binary).unwrap();
+    let state = State::new(
+        carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+    );
+    executor
+        .activate_machine(state, &compiled_binary, &[], "spin", &[])
+        .await
+        .unwrap()
+        .map(|outcome| outcome.output)
+}
+
+const SPIN: &str = "(loop $spin (br $spin)) unreachable";
+/// Returns a pointer to an empty list after growing memory by `n` pages.
+fn grow_memory(n: u32) -> String {
+    format!("(drop (memory.grow (i32.const {n}))) i32.const 2048")
+}
+
+#[tokio::test]
+async fn infinite_loop_runs_out_of_fuel() {
+    let executor = Executor::with_config(ExecutorConfig {
+        fuel_per_activation: Some(1_000_000),
+        ..Default::default()
+    });
+    match activate(&executor, &guest_component(SPIN)).await {
+        Err(GuestError::OutOfFuel { fuel }) => assert_eq!(fuel, 1_000_000),
+        other => panic!("expected guest to run out of fuel but got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn infinite_loop_times_out() {
+    let executor = Executor::with_config(ExecutorConfig {
+        activation_timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    });
+    match activate(&executor, &guest_component(SPIN)).await {
+        Err(GuestError::Timeout { after }) => assert_eq!(after, Duration::from_millis(100)),
+        other => panic!("expected guest to time out but got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn fuel_consumed_is_reported() {
+    let executor = Executor::with_config(ExecutorConfig {
+        fuel_per_activation: Some(1_000_000),
+        ..Default::default()
+    });
+    // returns a pointer to an empty list
+    let binary = guest_component("i32.const 2048");
+    let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+    let state = State::new(
+        carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+    );
+    let outcome = executor
+        .activate_machine(state, &compiled_binary, &[], "noop", &[])
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(outcome.output, Vec::<u8>::new());
+    let fuel_consumed = outcome.fuel_consumed.unwrap();
+    assert!(fuel_consumed > 0 && fuel_consumed < 1_000_000);
+}
+
+#[tokio::test]
+async fn greedy_memory_exceeds_limit() {
+    for pooling_allocator in [false, true] {
+        let executor = Executor::with_config(ExecutorConfig {
+            max_memory_bytes: Some(1024 * 1024),
+            pooling_allocator,
+            ..Default::default()
+        });
+        // 1 page is 64KiB
+        let greedy = guest_component(&grow_memory(100));
+        match activate(&executor, &greedy).await {
+            Err(GuestError::ResourceLimitExceeded { .. }) => {}
+            other => panic!("expected guest to exceed memory limit but got {:?}", other),
+        }
+
+        let modest = guest_component(&grow_memory(1));
+        assert_eq!(
+            activate(&executor, &modest).await.unwrap(),
+            Vec::<u8>::new()
+        );
+    }
+}
+
+#[tokio::test]
+async fn greedy_table_exceeds_limit() {
+    let executor = Executor::with_config(ExecutorConfig {
+        max_table_elements: Some(100),
+        ..Default::default()
+    });
+    let greedy =
+        guest_component("(drop (table.grow (ref.null func) (i32.const 1000))) i32.const 2048");
+    match activate(&executor, &greedy).await {
+        Err(GuestError::ResourceLimitExceeded { .. }) => {}
+        other => panic!("expected guest to exceed table limit but got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn too_many_instances() {
+    let executor = Executor::with_config(ExecutorConfig {
+        max_instances: Some(0),
+        ..Default::default()
+    });
+    match activate(&executor, &guest_component(&grow_memory(0))).await {
+        Err(GuestError::ResourceLimitExceeded { .. }) => {}
+        other => panic!(
+            "expected guest to exceed instance limit but got {:?}",
+            other
+        ),
+    }
+}
@@ -0,0 +1,108 @@
+use carol_core::BinaryId;
+use carol_host::rate_limit::MachineLimits;
+use carol_host::{Executor, ExecutorConfig, ExecutorState, State};
+
+mod common;
+use common::{Guest, Import};
+
+/// A guest whose `activate` calls `machines.self-activate` and returns what the inner activation
+/// returned. If the inner activation fails it returns the case index of the error instead so it
+/// ends up as the output of the outermost activation.
+fn recursive_guest_component() -> Vec<u8> {
+    Guest {
+        imports: &[Import {
+            interface: "machines",
+            instance_type: r#"
+    (type $machine-id' (list u8))
+    (export $machine-id "machine-id" (type (eq $machine-id')))
+    (type $panic-info' (record (field "reason" string) (field "machine" $machine-id)))
+    (export $panic-info "panic-info" (type (eq $panic-info')))
+    (type $error' (variant (case "panic" $panic-info) (case "not-found" $machine-id) (case "depth-limit-exceeded" u32) (case "busy" $machine-id)))
+    (export $error "error" (type (eq $error')))
+    (export "self-activate" (func (param "method" string) (param "input" (list u8)) (result (result (list u8) (error $error)))))
+"#,
+            funcs: &[("self-activate", "(param i32 i32 i32 i32 i32)")],
+        }],
+        module_fields: r#"(data (i32.const 0) "again")"#,
+        activate: r#"
+        (call $self-activate (i32.const 0) (i32.const 5) (i32.const 0) (i32.const 0) (i32.const 4096))
+        (if (i32.eqz (i32.load8_u (i32.const 4096)))
+          (then
+            ;; ok: return the list the inner activation returned
+            (i32.store (i32.const 2048) (i32.load (i32.const 4100)))
+            (i32.store (i32.const 2052) (i32.load (i32.const 4104))))
+          (else
+            ;; error: return a list holding the error's case index
+            (i32.store (i32.const 2048) (i32.const 4100))
+            (i32.store (i32.const 2052) (i32.const 1))))
+        i32.const 2048"#,
+        ..Default::default()
+    }
+    .build()
+}
+
+#[tokio::test]
+async fn nested_activations_are_limited_in_depth() {
+    let executor = Executor::with_config(ExecutorConfig {
+        max_activation_depth: Some(3),
+        ..Default::default()
+    });
+    let binary = recursive_guest_component();
+    let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+    let exec = ExecutorState::new(executor.clone());
+    exec.insert_binary(&binary, compiled_binary).unwrap();
+    let (_, machine_id) = exec.insert_machine(BinaryId::new(&binary), vec![]).unwrap();
+    let state = State {
+        exec: exec.clone(),
+        ..State::new(
+            carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+            carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        )
+    };
+
+    let (_, params) = exec.get_machine(machine_id).unwrap();
+    let compiled_binary = exec.get_binary(BinaryId::new(&binary)).unwrap();
+    let outcome = executor
+        .activate_machine(state, &compiled_binary, &params, "again", &[])
+        .await
+        .unwrap()
+        .unwrap();
+    // index of `depth-limit-exceeded` in `machines.error`
+    assert_eq!(outcome.output, vec![2]);
+}
+
+#[tokio::test]
+async fn nested_activations_are_subject_to_machine_limits() {
+    let executor = Executor::with_config(ExecutorConfig {
+        max_activation_depth: Some(3),
+        ..Default::default()
+    });
+    let binary = recursive_guest_component();
+    let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+    let exec = ExecutorState::new(executor.clone()).limit_machines(MachineLimits {
+        rate: None,
+        max_concurrent: Some(2),
+    });
+    exec.insert_binary(&binary, compiled_binary).unwrap();
+    let (_, machine_id) = exec.insert_machine(BinaryId::new(&binary), vec![]).unwrap();
+    let state = State {
+        exec: exec.clone(),
+        ..State::new(
+            carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+            carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        )
+    };
+
+    let (_, params) = exec.get_machine(machine_id).unwrap();
+    let compiled_binary = exec.get_binary(BinaryId::new(&binary)).unwrap();
+    let outcome = executor
+        .activate_machine(state, &compiled_binary, &params, "again", &[])
+        .await
+        .unwrap()
+        .unwrap();
+    // the two nested activations under the outer one take both places so the third is refused
+    // before it gets deep enough to hit the depth limit. `busy` is index 3 in `machines.error`.
+    assert_eq!(outcome.output, vec![3]);
+    // every place is given back
+    assert!(exec.admit_machine(machine_id).is_ok());
+}
@@ -0,0 +1,68 @@
+use carol_host::{DiskStorage, Executor, ExecutorState, State};
+use std::sync::Arc;
+
+mod common;
+use common::{Guest, Import};
+
+/// A guest whose `activate` attests to the outcome `event` for the event `event` and returns the
+/// case of the result and the case of the error (if it's an error).
+fn attesting_guest_component() -> Vec<u8> {
+    Guest {
+        imports: &[Import {
+            interface: "oracle",
+            instance_type: r#"
+    (type $error' (variant (case "already-attested" (list u8)) (case "storage-not-durable")))
+    (export $error "error" (type (eq $error')))
+    (export "attest" (func (param "event-id" (list u8)) (param "outcome" (list u8)) (result (result (list u8) (error $error)))))
+"#,
+            funcs: &[("attest", "(param i32 i32 i32 i32 i32)")],
+        }],
+        module_fields: r#"(data (i32.const 0) "event")"#,
+        activate: r#"
+        (call $attest (i32.const 0) (i32.const 5) (i32.const 0) (i32.const 5) (i32.const 4096))
+        (i32.store8 (i32.const 5000) (i32.load8_u (i32.const 4096)))
+        (i32.store8 (i32.const 5001) (i32.load8_u (i32.const 4100)))
+        (i32.store (i32.const 2048) (i32.const 5000))
+        (i32.store (i32.const 2052) (i32.const 2))
+        i32.const 2048"#,
+        ..Default::default()
+    }
+    .build()
+}
+
+async fn attest(exec: ExecutorState) -> Vec<u8> {
+    let executor = Executor::new();
+    let compiled_binary = executor
+        .load_binary_from_wasm_binary(&attesting_guest_component())
+        .unwrap();
+    let state = State {
+        exec,
+        ..State::new(
+            carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+            carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        )
+    };
+    executor
+        .activate_machine(state, &compiled_binary, &[], "attest", &[])
+        .await
+        .unwrap()
+        .unwrap()
+        .output
+}
+
+#[tokio::test]
+async fn attesting_needs_durable_storage() {
+    // err with `storage-not-durable`
+    assert_eq!(
+        attest(ExecutorState::new(Executor::new())).await,
+        vec![1, 1]
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let exec = ExecutorState::with_storage(
+        Executor::new(),
+        Arc::new(DiskStorage::open(dir.path()).unwrap()),
+    )
+    .unwrap();
+    assert_eq!(attest(exec).await[0], 0);
+}
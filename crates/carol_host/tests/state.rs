@@ -0,0 +1,37 @@
+use carol_core::BinaryId;
+use carol_host::{DiskStorage, Executor, ExecutorState};
+use std::sync::Arc;
+
+mod common;
+use common::guest_component;
+
+#[test]
+fn binaries_and_machines_are_loaded_from_storage() {
+    let dir = tempfile::tempdir().unwrap();
+    let executor = Executor::default();
+    let binary = guest_component("unreachable");
+    let binary_id = BinaryId::new(&binary);
+
+    let exec = ExecutorState::with_storage(
+        executor.clone(),
+        Arc::new(DiskStorage::open(dir.path()).unwrap()),
+    )
+    .unwrap();
+    let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+    exec.insert_binary(&binary, compiled_binary).unwrap();
+    let (_, machine_id) = exec.insert_machine(binary_id, vec![1, 2, 3]).unwrap();
+    let binaries = exec.list_binaries(None, 10);
+    let machines = exec.list_machines(None, None, 10);
+    drop(exec);
+
+    let reloaded =
+        ExecutorState::with_storage(executor, Arc::new(DiskStorage::open(dir.path()).unwrap()))
+            .unwrap();
+    assert!(reloaded.get_binary(binary_id).is_some());
+    let (machine_binary_id, params) = reloaded.get_machine(machine_id).unwrap();
+    assert_eq!(machine_binary_id, binary_id);
+    assert_eq!(params.as_ref(), &vec![1, 2, 3]);
+    // when they were created survives too
+    assert_eq!(reloaded.list_binaries(None, 10), binaries);
+    assert_eq!(reloaded.list_machines(None, None, 10), machines);
+}
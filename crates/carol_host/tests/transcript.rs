@@ -0,0 +1,154 @@
+use carol_core::MachineId;
+use carol_host::{Executor, State};
+use std::convert::Infallible;
+
+mod common;
+use common::{guest_component, Guest, Import};
+
+#[tokio::test]
+async fn transcript_is_only_recorded_when_asked_for() {
+    let executor = Executor::new();
+    // returns an empty list
+    let binary = guest_component("i32.const 2048");
+    let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+    let state = State::new(
+        carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap(),
+        carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+    );
+
+    let outcome = executor
+        .activate_machine(state.clone(), &compiled_binary, &[], "go", b"input")
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(outcome.transcript.is_none());
+
+    let outcome = executor
+        .activate_machine_with_transcript(state, &compiled_binary, &[], "go", b"input")
+        .await
+        .unwrap()
+        .unwrap();
+    let transcript = outcome.transcript.unwrap();
+    assert_eq!(
+        transcript.machine_id,
+        MachineId::new(compiled_binary.binary_id(), &[])
+    );
+    assert_eq!(transcript.activation, "go");
+    assert_eq!(transcript.input.0, b"input");
+    assert_eq!(transcript.output.0, outcome.output);
+    assert!(transcript.http.is_empty());
+}
+
+/// A guest whose `activate` makes a `GET` request to `uri` with an `Authorization` header.
+fn fetching_guest_component(uri: &str) -> Vec<u8> {
+    let module_fields = format!(
+        r#"
+      (data (i32.const 0) "{uri}")
+      ;; the headers list: one (name, value) tuple
+      (data (i32.const 512) "\58\02\00\00\0d\00\00\00\bc\02\00\00\0d\00\00\00")
+      (data (i32.const 600) "authorization")
+      (data (i32.const 700) "Bearer secret")"#
+    );
+    let activate = format!(
+        "(call $execute (i32.const 0) (i32.const 0) (i32.const {}) (i32.const 512) (i32.const 1) (i32.const 0) (i32.const 0) (i32.const 4096))
+        ;; returns an empty list
+        i32.const 2048",
+        uri.len()
+    );
+    Guest {
+        imports: &[Import {
+            interface: "http",
+            instance_type: r#"
+    (type $error' (variant (case "invalid-url" string) (case "invalid-header" string) (case "timeout") (case "connection" string) (case "unexpected" string)))
+    (export $error "error" (type (eq $error')))
+    (type $method' (enum "get" "post" "put" "patch" "delete"))
+    (export $method "method" (type (eq $method')))
+    (type $headers (list (tuple string (list u8))))
+    (type $request' (record (field "method" $method) (field "uri" string) (field "headers" $headers) (field "body" (list u8))))
+    (export $request "request" (type (eq $request')))
+    (type $response' (record (field "headers" $headers) (field "body" (list u8)) (field "status" u16)))
+    (export $response "response" (type (eq $response')))
+    (export "execute" (func (param "request" $request) (result (result $response (error $error)))))
+"#,
+            funcs: &[("execute", "(param i32 i32 i32 i32 i32 i32 i32 i32)")],
+        }],
+        module_fields: &module_fields,
+        activate: &activate,
+        ..Default::default()
+    }
+    .build()
+}
+
+#[tokio::test]
+async fn http_exchanges_are_recorded_without_credentials() {
+    let upstream = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(
+        hyper::service::make_service_fn(|_| async {
+            Ok::<_, Infallible>(hyper::service::service_fn(
+                |request: hyper::Request<hyper::Body>| async move {
+                    // the guest's credentials still reach the upstream
+                    assert_eq!(request.headers()["authorization"], "Bearer secret");
+                    Ok::<_, Infallible>(
+                        hyper::Response::builder()
+                            .header("set-cookie", "session=secret")
+                            .header("x-price", "42")
+                            .body(hyper::Body::from("hello"))
+                            .unwrap(),
+                    )
+                },
+            ))
+        }),
+    );
+    let uri = format!("http://{}/price", upstream.local_addr());
+    tokio::spawn(upstream);
+
+    let executor = Executor::new();
+    let binary = fetching_guest_component(&uri);
+    let compiled_binary = executor.load_binary_from_wasm_binary(&binary).unwrap();
+    let bls_keypair = carol_bls::KeyPair::from_bytes([42u8; 32]).unwrap();
+    let state = State::new(
+        bls_keypair,
+        carol_schnorr::KeyPair::from_bytes([42u8; 32]).unwrap(),
+    );
+    let transcript = executor
+        .activate_machine_with_transcript(state, &compiled_binary, &[], "fetch", &[])
+        .await
+        .unwrap()
+        .unwrap()
+        .transcript
+        .unwrap();
+
+    assert_eq!(transcript.http.len(), 1);
+    let exchange = &transcript.http[0];
+    assert_eq!(exchange.request.method, "GET");
+    assert_eq!(exchange.request.uri, uri);
+    assert_eq!(
+        exchange.request.headers,
+        vec![("authorization".into(), b"[redacted]".to_vec().into())]
+    );
+    let response = exchange.response.as_ref().unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body.0, b"hello");
+    let header = |name: &str| {
+        response
+            .headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.0.clone())
+    };
+    assert_eq!(header("set-cookie").unwrap(), b"[redacted]");
+    assert_eq!(header("x-price").unwrap(), b"42");
+
+    let signature = carol_bls::sign_transcript(bls_keypair, &transcript);
+    assert!(carol_bls::verify_transcript(
+        bls_keypair.public_key(),
+        signature,
+        &transcript
+    ));
+    let mut tampered = transcript;
+    tampered.http[0].response.as_mut().unwrap().body = b"goodbye".to_vec().into();
+    assert!(!carol_bls::verify_transcript(
+        bls_keypair.public_key(),
+        signature,
+        &tampered
+    ));
+}
@@ -1,7 +1,10 @@
-use alloc::{collections::BTreeMap, string::String};
-use carol_core::{serde, BinaryId, MachineId};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use carol_core::{serde, transcript::Transcript, BinaryId, Bytes, MachineId};
 use hyper::{header, http::HeaderValue, HeaderMap, StatusCode};
 
+/// Response header reporting how much fuel a machine consumed to produce the response.
+pub const FUEL_CONSUMED_HEADER: &str = "carol-fuel-consumed";
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Root {
     pub static_public_key: carol_bls::PublicKey,
@@ -63,10 +66,104 @@ impl Response for MachineCreated {
 pub struct GetMachine<'a> {
     pub binary_id: BinaryId,
     pub params: &'a [u8],
+    /// The params decoded to JSON by the machine's binary if it supports it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params_json: Option<serde_json::Value>,
+    /// The BLS public key derived for this machine from the node's static key.
+    pub public_key: carol_bls::PublicKey,
 }
 
 impl<'a> Response for GetMachine<'a> {}
 
+/// The response to an activation when a transcript of it was asked for. The output of the
+/// activation is in the transcript.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SignedTranscript {
+    pub transcript: Transcript,
+    /// The node's static BLS key's signature on the transcript (see
+    /// [`carol_bls::verify_transcript`]).
+    pub signature: carol_bls::Signature,
+}
+
+impl Response for SignedTranscript {}
+
+/// The outputs of a machine's scheduled activation from oldest to newest.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ScheduledOutputs {
+    pub outputs: Vec<ScheduledOutput>,
+}
+
+impl Response for ScheduledOutputs {}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ScheduledOutput {
+    /// When the run was due in seconds since the unix epoch.
+    pub time: u64,
+    pub output: Bytes,
+}
+
+/// A page of a machine's event log.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Events {
+    pub events: Vec<Event>,
+    /// Pass this as the `cursor` query parameter to get the events after these ones. `None` if
+    /// the machine hasn't published anything yet.
+    pub next_cursor: Option<u64>,
+}
+
+impl Response for Events {}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Event {
+    /// The event's sequence number in the machine's log.
+    pub id: u64,
+    /// When the event was published in seconds since the unix epoch.
+    pub time: u64,
+    pub topic: String,
+    pub data: Bytes,
+}
+
+/// A page of the binaries a node has.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Binaries {
+    pub binaries: Vec<BinarySummary>,
+    /// Pass this as the `cursor` query parameter to get the next page. `None` if this is the last
+    /// page.
+    pub next_cursor: Option<BinaryId>,
+}
+
+impl Response for Binaries {}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct BinarySummary {
+    pub id: BinaryId,
+    /// The size of the WASM component in bytes.
+    pub size: u64,
+    /// When the binary was uploaded in seconds since the unix epoch.
+    pub created: u64,
+}
+
+/// A page of the machines a node has.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Machines {
+    pub machines: Vec<MachineSummary>,
+    /// Pass this as the `cursor` query parameter to get the next page. `None` if this is the last
+    /// page.
+    pub next_cursor: Option<MachineId>,
+}
+
+impl Response for Machines {}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct MachineSummary {
+    pub id: MachineId,
+    pub binary_id: BinaryId,
+    /// The size of the machine's parameters in bytes.
+    pub params_size: u64,
+    /// When the machine was created in seconds since the unix epoch.
+    pub created: u64,
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct BinaryDescription {
     pub activations: BTreeMap<String, AcivationDescription>,
@@ -76,6 +173,120 @@ impl Response for BinaryDescription {}
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct AcivationDescription {
-    // I am sure this will map to some kind of metadata in the future
-    //empty for now
+    /// When the node runs the activation by itself (see [`carol_core::schedule::Schedule`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+}
+
+/// The content type of [`Problem`] responses.
+pub const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// The body of every error response as described by [RFC 7807].
+///
+/// [RFC 7807]: https://www.rfc-editor.org/rfc/rfc7807
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Problem {
+    /// A URI identifying the kind of problem. See [`ProblemType`].
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    /// A short summary of the kind of problem that is the same every time it occurs.
+    pub title: String,
+    pub status: u16,
+    /// What went wrong this time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// The path of the request that went wrong.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Members particular to the kind of problem (e.g. `backtrace` for a guest panic).
+    #[serde(flatten)]
+    pub extensions: BTreeMap<String, String>,
+}
+
+impl Problem {
+    /// The kind of problem if it's one this version knows about.
+    pub fn problem_type(&self) -> Option<ProblemType> {
+        ProblemType::from_uri(&self.type_uri)
+    }
+}
+
+impl core::fmt::Display for Problem {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} ({})", self.title, self.status)?;
+        if let Some(detail) = &self.detail {
+            write!(f, ": {}", detail)?;
+        }
+        for (name, value) in &self.extensions {
+            write!(f, "\n{}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Problem {}
+
+macro_rules! problem_types {
+    ($($(#[$doc:meta])* $variant:ident => $slug:literal, $title:literal;)*) => {
+        /// Every kind of problem the node reports. Each has a stable type URI that clients can
+        /// match on.
+        ///
+        /// The URIs are [`tag:`] URIs like `tag:carol.computer,2023:problems/machine-not-found`.
+        /// They only name the kind of problem. There's no page to fetch from them so the `title`
+        /// and `detail` of the problem are all there is to read.
+        ///
+        /// [`tag:`]: https://www.rfc-editor.org/rfc/rfc4151
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum ProblemType {
+            $($(#[$doc])* $variant,)*
+        }
+
+        impl ProblemType {
+            pub fn uri(&self) -> &'static str {
+                match self {
+                    $(ProblemType::$variant => concat!("tag:carol.computer,2023:problems/", $slug),)*
+                }
+            }
+
+            pub fn title(&self) -> &'static str {
+                match self {
+                    $(ProblemType::$variant => $title,)*
+                }
+            }
+
+            pub fn from_uri(uri: &str) -> Option<Self> {
+                [$(ProblemType::$variant),*]
+                    .into_iter()
+                    .find(|problem_type| problem_type.uri() == uri)
+            }
+        }
+    };
+}
+
+problem_types! {
+    BadRequest => "bad-request", "Bad request";
+    InternalServerError => "internal-server-error", "Internal server error";
+    /// The `Host` header didn't resolve to a machine.
+    MisdirectedRequest => "misdirected-request", "Host doesn't resolve to a machine";
+    NotFound => "not-found", "Not found";
+    MachineNotFound => "machine-not-found", "Machine not found";
+    BinaryNotFound => "binary-not-found", "Binary not found";
+    PayloadTooLarge => "payload-too-large", "Request body too large";
+    MethodNotAllowed => "method-not-allowed", "Method not allowed";
+    /// A segment of the path wasn't a valid id.
+    InvalidPathElement => "invalid-path-element", "Invalid path element";
+    Unauthorized => "unauthorized", "Unauthorized";
+    Forbidden => "forbidden", "Forbidden";
+    /// A rate limit or concurrency cap was hit. The `Retry-After` header says when to try again.
+    TooManyRequests => "too-many-requests", "Too many requests";
+    /// The binary is still used by machines.
+    BinaryInUse => "binary-in-use", "Binary in use";
+    /// The uploaded binary isn't a valid WASM component for carol.
+    InvalidBinary => "invalid-binary", "Invalid binary";
+    GuestPanic => "guest-panic", "Machine panicked";
+    GuestOutOfFuel => "guest-out-of-fuel", "Machine ran out of fuel";
+    GuestTimeout => "guest-timeout", "Machine timed out";
+    GuestResourceLimitExceeded => "guest-resource-limit-exceeded", "Machine exceeded a resource limit";
+    /// The host failed to run the machine.
+    ActivationFailed => "activation-failed", "Activation failed";
 }
@@ -0,0 +1,210 @@
+//! [BIP340] Schnorr signatures over secp256k1.
+//!
+//! Unlike our BLS signatures, the message is signed as is (no machine id is mixed into it) so the
+//! signatures can be checked by anything that understands BIP340 e.g. DLCs and Nostr. To keep
+//! machines from producing signatures on each other's behalf each machine signs with its own key
+//! derived from the node's keypair instead.
+//!
+//! [BIP340]: https://github.com/bitcoin/bips/blob/master/bip-0340.mediawiki
+use carol_core::{impl_display_debug_serialize, impl_fromstr_deserialize, MachineId};
+pub use schnorr_fun;
+use schnorr_fun::{
+    fun::{marker::*, s, Point, Scalar},
+    nonce::Deterministic,
+    Message, Schnorr,
+};
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct KeyPair(schnorr_fun::fun::KeyPair<EvenY>);
+
+impl KeyPair {
+    pub fn new(sk: Scalar) -> Self {
+        Self(schnorr_fun::fun::KeyPair::<EvenY>::new(sk))
+    }
+
+    pub fn random(rng: &mut impl rand_core::RngCore) -> Self {
+        Self::new(Scalar::random(rng))
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.0.public_key())
+    }
+
+    pub fn secret_key(&self) -> &Scalar {
+        self.0.secret_key()
+    }
+
+    /// Deterministically derives the keypair a particular machine signs with from the node's root
+    /// keypair.
+    ///
+    /// The machine's secret key is a tagged SHA256 hash of the root secret key and `machine_id`.
+    /// The root secret key is hashed after it has been negated to give an even Y public key (as
+    /// BIP340 requires) so a secret key and its negation, which have the same x-only public key,
+    /// derive the same machine keys. Unlike BIP32 unhardened derivation there is no tweak of the
+    /// public key: a machine's public key can't be worked out from the node's public key so it
+    /// has to be asked for with `schnorr-machine-pubkey`.
+    pub fn derive_for_machine(&self, machine_id: MachineId) -> KeyPair {
+        let hash = Sha256::default()
+            .chain_update(b"carol/schnorr/machine-key")
+            .chain_update(self.secret_key().to_bytes())
+            .chain_update(machine_id.as_ref());
+        Self::new(Scalar::from_hash(hash))
+    }
+
+    fn event_nonce_keypair(&self, event_id: &[u8]) -> schnorr_fun::fun::KeyPair<EvenY> {
+        let hash = Sha256::default()
+            .chain_update(b"carol/schnorr/event-nonce")
+            .chain_update(self.secret_key().to_bytes())
+            .chain_update((event_id.len() as u64).to_be_bytes())
+            .chain_update(event_id);
+        schnorr_fun::fun::KeyPair::<EvenY>::new(Scalar::from_hash(hash))
+    }
+
+    /// The nonce that will be used to sign the outcome of `event_id` with
+    /// [`sign_with_event_nonce`].
+    ///
+    /// It is derived deterministically from the secret key and `event_id` so it can be announced
+    /// long before the outcome is known without having to remember it.
+    pub fn event_nonce(&self, event_id: &[u8]) -> Nonce {
+        Nonce(self.event_nonce_keypair(event_id).public_key())
+    }
+}
+
+/// The public nonce a signature on the outcome of an event will use.
+///
+/// Publishing this ahead of time lets others anticipate the signature on each possible outcome
+/// (e.g. to set up a DLC).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Nonce(pub Point<EvenY>);
+
+impl_display_debug_serialize! {
+    fn to_bytes(nonce: &Nonce) -> [u8;32] {
+        nonce.0.to_xonly_bytes()
+    }
+}
+
+impl_fromstr_deserialize! {
+    name => "BIP340 nonce",
+    fn from_bytes(bytes: [u8;32]) -> Option<Nonce> {
+        Some(Nonce(Point::from_xonly_bytes(bytes)?))
+    }
+}
+
+/// An x-only public key.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey(pub Point<EvenY>);
+
+impl_display_debug_serialize! {
+    fn to_bytes(public_key: &PublicKey) -> [u8;32] {
+        public_key.0.to_xonly_bytes()
+    }
+}
+
+impl_fromstr_deserialize! {
+    name => "secp256k1 x-only public key",
+    fn from_bytes(bytes: [u8;32]) -> Option<PublicKey> {
+        Some(PublicKey(Point::from_xonly_bytes(bytes)?))
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Signature(pub schnorr_fun::Signature);
+
+impl_display_debug_serialize! {
+    fn to_bytes(signature: &Signature) -> [u8;64] {
+        signature.0.to_bytes()
+    }
+}
+
+impl_fromstr_deserialize! {
+    name => "BIP340 signature",
+    fn from_bytes(bytes: [u8;64]) -> Option<Signature> {
+        Some(Signature(schnorr_fun::Signature::from_bytes(bytes)?))
+    }
+}
+
+fn schnorr() -> Schnorr<Sha256, Deterministic<Sha256>> {
+    Schnorr::default()
+}
+
+pub fn sign(keypair: &KeyPair, message: &[u8]) -> Signature {
+    Signature(schnorr().sign(&keypair.0, Message::<Public>::raw(message)))
+}
+
+/// Signs `message` using the nonce for `event_id` given by [`KeyPair::event_nonce`].
+///
+/// Signing two different messages for the same event reveals the secret key! It is up to the
+/// caller to make sure this never happens.
+pub fn sign_with_event_nonce(keypair: &KeyPair, event_id: &[u8], message: &[u8]) -> Signature {
+    let nonce = keypair.event_nonce_keypair(event_id);
+    let (r, nonce_point) = nonce.as_tuple();
+    let (x, public_key) = keypair.0.as_tuple();
+    let c = schnorr().challenge(&nonce_point, &public_key, Message::<Public>::raw(message));
+    let s = s!(r + c * x).public();
+    Signature(schnorr_fun::Signature { R: nonce_point, s })
+}
+
+#[must_use]
+pub fn verify(public_key: PublicKey, signature: &Signature, message: &[u8]) -> bool {
+    schnorr().verify(&public_key.0, Message::<Public>::raw(message), &signature.0)
+}
+
+impl_fromstr_deserialize! {
+    name => "secp256k1 scalar",
+    fn from_bytes(bytes: [u8;32]) -> Option<KeyPair> {
+        Some(KeyPair::new(Scalar::from_bytes(bytes)?.non_zero()?))
+    }
+}
+
+impl_display_debug_serialize! {
+    fn to_bytes(kp: &KeyPair) -> [u8;32] {
+        kp.secret_key().to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn sign_verify(sk in any::<[u8;32]>(), message in any::<[u8;32]>()) {
+            let kp = KeyPair::new(Scalar::from_hash(Sha256::new().chain_update(sk)));
+
+            let signature = sign(&kp, &message[..]);
+            prop_assert!(verify(kp.public_key(), &signature, &message))
+        }
+
+        #[test]
+        fn derived_machine_keys(sk in any::<[u8;32]>(), message in any::<[u8;32]>(), machine_id in any::<[u8;32]>(), other_machine_id in any::<[u8;32]>()) {
+            prop_assume!(machine_id != other_machine_id);
+            let kp = KeyPair::new(Scalar::from_hash(Sha256::new().chain_update(sk)));
+            let machine_id = MachineId::from_bytes(machine_id);
+            let other_machine_id = MachineId::from_bytes(other_machine_id);
+
+            let machine_kp = kp.derive_for_machine(machine_id);
+            prop_assert_eq!(&machine_kp, &kp.derive_for_machine(machine_id));
+            prop_assert_ne!(&machine_kp, &kp);
+            prop_assert_ne!(&machine_kp, &kp.derive_for_machine(other_machine_id));
+
+            let signature = sign(&machine_kp, &message[..]);
+            prop_assert!(verify(machine_kp.public_key(), &signature, &message));
+            prop_assert!(!verify(kp.public_key(), &signature, &message));
+        }
+
+        #[test]
+        fn event_nonce_signature(sk in any::<[u8;32]>(), message in any::<[u8;32]>(), event_id in any::<[u8;8]>(), other_event_id in any::<[u8;8]>()) {
+            prop_assume!(event_id != other_event_id);
+            let kp = KeyPair::new(Scalar::from_hash(Sha256::new().chain_update(sk)));
+            let nonce = kp.event_nonce(&event_id);
+            prop_assert_eq!(nonce, kp.event_nonce(&event_id));
+            prop_assert_ne!(nonce, kp.event_nonce(&other_event_id));
+
+            let signature = sign_with_event_nonce(&kp, &event_id, &message);
+            prop_assert_eq!(signature.0.R, nonce.0);
+            prop_assert!(verify(kp.public_key(), &signature, &message));
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use carol_guest::*;
 pub use time;
 
+const COMPOSITE_INDEX_URL: &str = "https://www.bitmex.com/api/v1/instrument/compositeIndex";
+
 #[derive(Debug, Clone, Copy)]
 #[codec]
 pub struct AttestIndexPrice<S> {
@@ -29,6 +31,37 @@ pub struct AttestBit<'a> {
     pub bit_value: bool,
 }
 
+/// Identifies the event of `symbol` having a price at a particular minute.
+#[derive(bincode::Encode)]
+pub struct PriceEvent<'a> {
+    pub symbol: &'a str,
+    #[bincode(with_serde)]
+    pub time: time::OffsetDateTime,
+}
+
+impl<'a> PriceEvent<'a> {
+    pub fn new(symbol: &'a str, time: time::OffsetDateTime) -> Self {
+        // ignore seconds like [`BitMexAttest::index_price_at_minute`] does
+        let time = time
+            .replace_second(0)
+            .unwrap()
+            .replace_nanosecond(0)
+            .unwrap();
+        Self { symbol, time }
+    }
+
+    pub fn id(&self) -> Vec<u8> {
+        bincode::encode_to_vec(self, bincode::config::standard()).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+#[codec]
+pub struct PriceAnnouncement {
+    pub public_key: schnorr::PublicKey,
+    pub nonce: schnorr::Nonce,
+}
+
 #[machine]
 /// This is an example oracle attestation guest running on [carol] host. It shows how to fetch data
 /// from a remote source (bitmex.com) and attest to it using BLS signatures. Note that this HTML
@@ -54,7 +87,7 @@ impl BitMexAttest {
     #[activate(http(GET))]
     pub fn bit_decompose_attest_to_price_at_minute(
         &self,
-        cap: &(impl bls::Cap + http::Cap + log::Cap),
+        cap: &(impl bls::Cap + http::Cap + log::Cap + clock::Cap),
         #[with_serde(with = "time::serde::iso8601")] time: time::OffsetDateTime,
         symbol: String,
         n_bits: u8,
@@ -92,7 +125,7 @@ impl BitMexAttest {
     #[activate(http(GET))]
     pub fn attest_to_price_at_minute(
         &self,
-        cap: &(impl bls::Cap + http::Cap + log::Cap),
+        cap: &(impl bls::Cap + http::Cap + log::Cap + clock::Cap),
         #[with_serde(with = "time::serde::iso8601")] time: time::OffsetDateTime,
         symbol: String,
     ) -> Result<AttestIndexPrice<bls::Signature>, Error> {
@@ -110,14 +143,80 @@ impl BitMexAttest {
         Ok(AttestIndexPrice { price, signature })
     }
 
+    /// Announce the public key and nonce that
+    /// [`Self::schnorr_attest_to_price_at_minute`] will use to sign the price of `symbol` at the
+    /// minute described by `time` (seconds are ignored). Publish this before the time to allow
+    /// people to set up [DLCs] on the price.
+    ///
+    /// [DLCs]: https://github.com/discreetlogcontracts/dlcspecs
+    ///
+    /// ### Example
+    ///
+    /// Clicking this link (when hosted on carol) will make the request:
+    ///
+    /// [announce_price_at_minute?time=2023-04-16T12:30:00Z&symbol=.BXBT](announce_price_at_minute?time=2023-04-16T12:30:00Z&symbol=.BXBT)
+    #[activate(http(GET))]
+    pub fn announce_price_at_minute(
+        &self,
+        cap: &(impl schnorr::Cap + oracle::Cap),
+        #[with_serde(with = "time::serde::iso8601")] time: time::OffsetDateTime,
+        symbol: String,
+    ) -> Result<PriceAnnouncement, oracle::Error> {
+        Ok(PriceAnnouncement {
+            public_key: cap.schnorr_public_key(),
+            nonce: cap.oracle_announce_nonce(&PriceEvent::new(&symbol, time).id())?,
+        })
+    }
+
+    /// Provide a BIP340 signature over the rounded down price of `symbol` (as a decimal string)
+    /// at the minute described by `time` (seconds are ignored) using the nonce from
+    /// [`Self::announce_price_at_minute`].
+    ///
+    /// ### Example
+    ///
+    /// Clicking this link (when hosted on carol) will make the request:
+    ///
+    /// [schnorr_attest_to_price_at_minute?time=2023-04-16T12:30:00Z&symbol=.BXBT](schnorr_attest_to_price_at_minute?time=2023-04-16T12:30:00Z&symbol=.BXBT)
+    #[activate(http(GET))]
+    pub fn schnorr_attest_to_price_at_minute(
+        &self,
+        cap: &(impl oracle::Cap + http::Cap + log::Cap + clock::Cap),
+        #[with_serde(with = "time::serde::iso8601")] time: time::OffsetDateTime,
+        symbol: String,
+    ) -> Result<AttestIndexPrice<schnorr::Signature>, Error> {
+        let price = self.index_price_at_minute(cap, &symbol, time)?;
+        let event_id = PriceEvent::new(&symbol, time).id();
+        let signature = cap
+            .oracle_attest(&event_id, price.to_string().as_bytes())
+            .map_err(|e| Error {
+                url: COMPOSITE_INDEX_URL.into(),
+                problem: Problem::Attest(e),
+            })?;
+
+        Ok(AttestIndexPrice { price, signature })
+    }
+
     pub fn index_price_at_minute(
         &self,
-        cap: &(impl http::Cap + log::Cap),
+        cap: &(impl http::Cap + log::Cap + clock::Cap),
         symbol: &str,
         time: time::OffsetDateTime,
     ) -> Result<u64, Error> {
-        let mut url = url::Url::parse("https://www.bitmex.com/api/v1/instrument/compositeIndex")
-            .expect("valid url");
+        let mut url = url::Url::parse(COMPOSITE_INDEX_URL).expect("valid url");
+
+        let minute_end = time
+            .replace_second(0)
+            .unwrap()
+            .replace_nanosecond(0)
+            .unwrap()
+            + time::Duration::MINUTE;
+        let now = time::OffsetDateTime::UNIX_EPOCH + cap.clock_now();
+        if minute_end > now {
+            return Err(Error {
+                url: url.into(),
+                problem: Problem::MinuteNotFinished,
+            });
+        }
 
         #[derive(serde::Serialize)]
         struct Filter<'a> {
@@ -186,8 +285,15 @@ pub struct Error {
 #[serde(tag = "kind")]
 pub enum Problem {
     Error(http::Error),
-    BadStatus { status: u16 },
-    Deserialization { error: String },
+    BadStatus {
+        status: u16,
+    },
+    Deserialization {
+        error: String,
+    },
+    Attest(oracle::Error),
+    /// Refused to attest to the price at a minute that isn't over yet.
+    MinuteNotFinished,
 }
 
 #[cfg(test)]
@@ -205,4 +311,44 @@ mod test {
             .unwrap();
         assert_eq!(index_price.price, 30492);
     }
+
+    #[test]
+    fn refuses_to_attest_to_unfinished_minute() {
+        use crate::{BitMexAttest, Problem};
+        use carol_guest::TestCap;
+        use std::time::Duration;
+        let time = time::macros::datetime!(2023-04-15 0:00 UTC);
+        let cap = TestCap::default();
+        cap.set_clock(Duration::from_secs(time.unix_timestamp() as u64 + 59));
+
+        let error = BitMexAttest
+            .attest_to_price_at_minute(&cap, time, ".BXBT".into())
+            .unwrap_err();
+        assert!(matches!(error.problem, Problem::MinuteNotFinished));
+    }
+
+    #[test]
+    fn announced_nonce_is_used_to_attest() {
+        use crate::{BitMexAttest, PriceEvent};
+        use carol_guest::{oracle::Cap, schnorr, TestCap};
+        let time = time::macros::datetime!(2023-04-15 0:00:30 UTC);
+        let cap = TestCap::default();
+        let announcement = BitMexAttest
+            .announce_price_at_minute(&cap, time, ".BXBT".into())
+            .unwrap();
+        let event_id = PriceEvent::new(".BXBT", time).id();
+
+        let signature = cap.oracle_attest(&event_id, b"30492").unwrap();
+        assert_eq!(signature.0.R, announcement.nonce.0);
+        assert!(schnorr::verify(
+            announcement.public_key,
+            &signature,
+            b"30492"
+        ));
+        assert_eq!(
+            PriceEvent::new(".BXBT", time::macros::datetime!(2023-04-15 0:00 UTC)).id(),
+            event_id
+        );
+        assert!(cap.oracle_attest(&event_id, b"30493").is_err());
+    }
 }